@@ -0,0 +1,73 @@
+use aum_core::prelude::{ErrorCode, Response};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// The engine rejected the request.
+    #[error("{message} ({code:?})")]
+    Remote {
+        code: ErrorCode,
+        message: String,
+        details: Option<serde_json::Value>,
+    },
+    #[error("WebSocket Error: {0}")]
+    WsError(Box<tungstenite::Error>),
+    #[error("Encoding Error: {0}")]
+    ProtocolError(#[from] aum_core::errors::ProtocolError),
+    #[error("I/O Error: {0}")]
+    IoError(#[from] std::io::Error),
+    #[error("TLS Error: {0}")]
+    TlsError(#[from] rustls::Error),
+    #[error("Connection closed.")]
+    Closed,
+    /// The engine closed the connection, e.g. because a limit was exceeded.
+    #[error("Connection closed by the engine: {reason} ({code})")]
+    ClosedByEngine { code: u16, reason: String },
+    /// The engine speaks an older protocol version than this client supports.
+    #[error("Engine protocol version {protocol_version} is not supported.")]
+    IncompatibleProtocol { protocol_version: u32 },
+    /// The engine doesn't support this kind of request, as reported by its `Hello` reply.
+    #[error("Request {0} is not supported by the engine.")]
+    Unsupported(&'static str),
+    #[error("Credentials can't be sent in a header.")]
+    InvalidCredentials,
+}
+
+impl From<tungstenite::Error> for Error {
+    fn from(e: tungstenite::Error) -> Self {
+        Error::WsError(Box::new(e))
+    }
+}
+
+impl Error {
+    /// Converts a failed WebSocket upgrade, turning an error reported by the engine in the
+    /// HTTP reply body (e.g. a rejected API key) into [`Error::Remote`].
+    pub(crate) fn from_handshake(e: tungstenite::Error) -> Self {
+        if let tungstenite::Error::Http(res) = &e {
+            let remote = res
+                .body()
+                .as_deref()
+                .and_then(|body| serde_json::from_slice::<Response>(body).ok());
+            if let Some(Response::Error {
+                code,
+                message,
+                details,
+            }) = remote
+            {
+                return Error::Remote {
+                    code,
+                    message,
+                    details,
+                };
+            }
+        }
+        e.into()
+    }
+
+    /// Returns the error code reported by the engine, if the error came from it.
+    pub fn code(&self) -> Option<ErrorCode> {
+        match self {
+            Error::Remote { code, .. } => Some(*code),
+            _ => None,
+        }
+    }
+}
@@ -1,92 +1,858 @@
-use aum_core::prelude::{Request, Response};
-use futures_util::{SinkExt, StreamExt};
-use tokio::net::TcpStream;
-use tokio_tungstenite::{MaybeTlsStream, WebSocketStream, connect_async};
-use tungstenite::http::{Method, Request as WsRequest};
+mod errors;
+pub use errors::Error;
+
+use aum_core::jsonrpc::{self, Id, RpcRequest, RpcResponse};
+use aum_core::prelude::{
+    ErrorCode, Event, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION, Protocol, Request, RequestEnvelope,
+    Response, ResponseEnvelope, Topic,
+};
+use futures_util::{
+    SinkExt, StreamExt,
+    stream::{self, BoxStream, SplitSink, SplitStream},
+};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::BufReader,
+    path::PathBuf,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+    sync::{Mutex, broadcast, oneshot},
+    task::JoinHandle,
+};
+use tokio_tungstenite::{
+    Connector, MaybeTlsStream, WebSocketStream, client_async_tls_with_config,
+    tungstenite::{
+        client::IntoClientRequest,
+        http::{Uri, header},
+    },
+};
+
+/// Prefix of addresses that name a Unix domain socket, e.g. `unix:/run/aum.sock`.
+const UNIX_PREFIX: &str = "unix:";
+
+/// The socket under the WebSocket, TCP or a Unix domain socket.
+trait Io: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Io for T {}
+
+type WsStream = WebSocketStream<MaybeTlsStream<Box<dyn Io>>>;
+type Pending = Arc<std::sync::Mutex<HashMap<u64, oneshot::Sender<Reply>>>>;
+
+/// Publishes pushed events to event streams; cleared when the connection closes so that
+/// the streams end.
+type Events = Arc<std::sync::Mutex<Option<broadcast::Sender<Event>>>>;
+
+/// The code and reason of the close frame sent by the engine, if any.
+type CloseReason = Arc<std::sync::Mutex<Option<(u16, String)>>>;
+
+/// A stream of events pushed by the engine for subscribed topics.
+pub type EventStream = BoxStream<'static, Event>;
+
+const EVENT_CAPACITY: usize = 1024;
+
+/// A reply as received, before it is turned into a [`Response`].
+enum Reply {
+    Native(Response),
+    JsonRpc(RpcResponse),
+}
 
 pub struct AumAPI {
     bind: String,
+    tls: bool,
+    root_ca: Option<PathBuf>,
+    credentials: Option<Credentials>,
+    protocol: Protocol,
+}
+
+/// An API key used to authenticate with the engine.
+#[derive(Clone)]
+pub struct Credentials {
+    pub key_id: String,
+    pub secret: String,
+}
+
+impl Credentials {
+    pub fn new(key_id: impl Into<String>, secret: impl Into<String>) -> Self {
+        Self {
+            key_id: key_id.into(),
+            secret: secret.into(),
+        }
+    }
+
+    fn authorization(&self) -> String {
+        format!("Bearer {}:{}", self.key_id, self.secret)
+    }
+}
+
+impl std::fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Credentials")
+            .field("key_id", &self.key_id)
+            .finish_non_exhaustive()
+    }
 }
 impl AumAPI {
+    /// Creates a client for `bind`: `host:port`, a full `ws://`/`wss://` URL, or the path of
+    /// a Unix domain socket prefixed with `unix:`, e.g. `unix:/run/aum/engine.sock`.
     pub fn new(bind: &str) -> Self {
         Self {
             bind: bind.to_owned(),
+            tls: false,
+            root_ca: None,
+            credentials: None,
+            protocol: Protocol::Native,
+        }
+    }
+
+    /// Authenticates every connection with the given API key.
+    pub fn with_credentials(mut self, credentials: Credentials) -> Self {
+        self.credentials = Some(credentials);
+        self
+    }
+
+    /// Speaks `protocol` instead of the native envelope protocol.
+    ///
+    /// The engine must support it, otherwise [`AumAPI::connect`] fails.
+    pub fn with_protocol(mut self, protocol: Protocol) -> Self {
+        self.protocol = protocol;
+        self
+    }
+
+    /// Connects over `wss://`, trusting the bundled Mozilla root certificates.
+    pub fn with_tls(mut self) -> Self {
+        self.tls = true;
+        self
+    }
+
+    /// Connects over `wss://`, additionally trusting the CA certificates in a PEM file.
+    ///
+    /// Useful for engines serving a self-signed or privately issued certificate.
+    pub fn with_root_ca(mut self, pem_path: impl Into<PathBuf>) -> Self {
+        self.tls = true;
+        self.root_ca = Some(pem_path.into());
+        self
+    }
+
+    pub async fn connect(&self) -> Result<AumConnection, Error> {
+        let url = self.url();
+        let connector = if url.starts_with("wss://") {
+            Some(Connector::Rustls(Arc::new(self.tls_config()?)))
+        } else {
+            None
+        };
+        let mut request = url.into_client_request()?;
+        if let Some(credentials) = &self.credentials {
+            request.headers_mut().insert(
+                header::AUTHORIZATION,
+                credentials
+                    .authorization()
+                    .parse()
+                    .map_err(|_| Error::InvalidCredentials)?,
+            );
+        }
+        // Engines that predate protocol negotiation only speak the native protocol, so it
+        // is not offered explicitly.
+        if self.protocol != Protocol::Native {
+            request.headers_mut().insert(
+                header::SEC_WEBSOCKET_PROTOCOL,
+                header::HeaderValue::from_static(self.protocol.subprotocol()),
+            );
+        }
+        let socket: Box<dyn Io> = match self.bind.strip_prefix(UNIX_PREFIX) {
+            Some(path) => Box::new(connect_unix(path).await?),
+            None => Box::new(connect_tcp(request.uri()).await?),
+        };
+        let (ws_stream, _) = client_async_tls_with_config(request, socket, None, connector)
+            .await
+            .map_err(Error::from_handshake)?;
+
+        Ok(AumConnection::new(ws_stream, self.protocol))
+    }
+
+    fn url(&self) -> String {
+        let scheme = if self.tls { "wss" } else { "ws" };
+        if self.bind.starts_with(UNIX_PREFIX) {
+            // The host only ends up in the `Host` header and the TLS server name.
+            format!("{}://localhost/", scheme)
+        } else if self.bind.contains("://") {
+            self.bind.clone()
+        } else {
+            format!("{}://{}", scheme, self.bind)
         }
     }
-    pub async fn connect(&self) -> Result<AumConnection, Box<dyn std::error::Error>> {
-        let url = format!("ws://{}", self.bind);
-        let (ws_stream, _) = connect_async(url).await?;
 
-        Ok(AumConnection { ws_stream })
+    fn tls_config(&self) -> Result<rustls::ClientConfig, Error> {
+        let mut roots = rustls::RootCertStore {
+            roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+        };
+        if let Some(path) = &self.root_ca {
+            for cert in rustls_pemfile::certs(&mut BufReader::new(File::open(path)?)) {
+                roots.add(cert?)?;
+            }
+        }
+        let config = rustls::ClientConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+        Ok(config)
     }
 }
 
+async fn connect_tcp(uri: &Uri) -> Result<TcpStream, Error> {
+    let host = uri.host().unwrap_or_default();
+    // IPv6 hosts keep their brackets in URLs.
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let port = uri
+        .port_u16()
+        .unwrap_or(if uri.scheme_str() == Some("wss") {
+            443
+        } else {
+            80
+        });
+    Ok(TcpStream::connect((host, port)).await?)
+}
+
+#[cfg(unix)]
+async fn connect_unix(path: &str) -> Result<tokio::net::UnixStream, Error> {
+    Ok(tokio::net::UnixStream::connect(path).await?)
+}
+
+#[cfg(not(unix))]
+async fn connect_unix(_: &str) -> Result<TcpStream, Error> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "Unix domain sockets are not supported on this platform",
+    )
+    .into())
+}
+
+/// A connection to the engine.
+///
+/// Requests are tagged with an id and replies are matched back by it, so several requests
+/// can be in flight at once through a shared `&AumConnection`.
 pub struct AumConnection {
-    ws_stream: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    sink: Mutex<SplitSink<WsStream, tungstenite::Message>>,
+    pending: Pending,
+    events: Events,
+    close_reason: CloseReason,
+    next_id: AtomicU64,
+    protocol: Protocol,
+    /// Request kinds the engine listed in its `Hello` reply, once [`AumConnection::hello`]
+    /// has been called.
+    supported: std::sync::Mutex<Option<Vec<String>>>,
+    reader: JoinHandle<()>,
 }
 impl AumConnection {
-    pub async fn send_request(
-        &mut self,
-        request: Request,
-    ) -> Result<Response, Box<dyn std::error::Error>> {
-        let request_json = serde_json::to_string(&request)?;
-        self.ws_stream
-            .send(tungstenite::Message::Text(request_json.into()))
-            .await?;
+    fn new(ws_stream: WsStream, protocol: Protocol) -> Self {
+        let (sink, stream) = ws_stream.split();
+        let pending = Pending::default();
+        let events = Arc::new(std::sync::Mutex::new(Some(
+            broadcast::channel(EVENT_CAPACITY).0,
+        )));
+        let close_reason = CloseReason::default();
+        let reader = tokio::spawn(read_responses(
+            stream,
+            Arc::clone(&pending),
+            Arc::clone(&events),
+            Arc::clone(&close_reason),
+            protocol,
+        ));
+        Self {
+            sink: Mutex::new(sink),
+            pending,
+            events,
+            close_reason,
+            next_id: AtomicU64::new(1),
+            protocol,
+            supported: std::sync::Mutex::default(),
+            reader,
+        }
+    }
+
+    /// Sends `request` and waits for the engine's reply.
+    ///
+    /// After [`AumConnection::hello`], requests the engine did not list as supported fail
+    /// with [`Error::Unsupported`] without being sent.
+    pub async fn send_request(&self, request: Request) -> Result<Response, Error> {
+        if !self.supports(request.kind()) {
+            return Err(Error::Unsupported(request.kind()));
+        }
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = oneshot::channel();
+        self.pending.lock().unwrap().insert(id, tx);
 
-        if let Some(msg) = self.ws_stream.next().await {
-            match msg? {
-                tungstenite::Message::Text(response_json) => {
-                    let response: Response = serde_json::from_str(&response_json)?;
-                    Ok(response)
+        let method = jsonrpc::method_of(&request);
+        let data = match self.protocol {
+            Protocol::JsonRpc => self
+                .protocol
+                .encode(&RpcRequest::new(&request, Some(Id::Number(id as i64))))?,
+            protocol => protocol.encode(&RequestEnvelope::new(id, request))?,
+        };
+        let message = if self.protocol.is_binary() {
+            tungstenite::Message::Binary(data.into())
+        } else {
+            tungstenite::Message::Text(String::from_utf8_lossy(&data).into_owned().into())
+        };
+        if let Err(e) = self.sink.lock().await.send(message).await {
+            self.pending.lock().unwrap().remove(&id);
+            return Err(match e {
+                tungstenite::Error::AlreadyClosed | tungstenite::Error::ConnectionClosed => {
+                    self.closed()
                 }
-                _ => Err("Unexpected WebSocket message type".into()),
+                e => e.into(),
+            });
+        }
+
+        let response = match rx.await {
+            Ok(Reply::Native(response)) => response,
+            Ok(Reply::JsonRpc(response)) => response.into_response(method),
+            Err(_) => return Err(self.closed()),
+        };
+        match response {
+            Response::Error {
+                code,
+                message,
+                details,
+            } => Err(Error::Remote {
+                code,
+                message,
+                details,
+            }),
+            response => Ok(response),
+        }
+    }
+
+    /// Introduces the client to the engine and returns its `Hello` reply, listing the
+    /// engine's version, supported requests, network and features.
+    ///
+    /// Fails with [`Error::IncompatibleProtocol`] if the engine's protocol version is older
+    /// than this client supports. Engines that reject the handshake itself because they are
+    /// too new report [`ErrorCode::UnsupportedProtocolVersion`].
+    pub async fn hello(&self, client_name: Option<String>) -> Result<Response, Error> {
+        let response = self
+            .send_request(Request::Hello {
+                protocol_version: PROTOCOL_VERSION,
+                client_name,
+            })
+            .await?;
+        if let Response::Hello {
+            protocol_version,
+            requests,
+            ..
+        } = &response
+        {
+            if *protocol_version < MIN_PROTOCOL_VERSION {
+                return Err(Error::IncompatibleProtocol {
+                    protocol_version: *protocol_version,
+                });
             }
-        } else {
-            Err("WebSocket stream closed".into())
+            *self.supported.lock().unwrap() = Some(requests.clone());
+        }
+        Ok(response)
+    }
+
+    /// Returns whether the engine supports requests of `kind`, e.g. `"SendTransaction"`.
+    ///
+    /// Always true until [`AumConnection::hello`] has told which requests are supported.
+    pub fn supports(&self, kind: &str) -> bool {
+        match self.supported.lock().unwrap().as_ref() {
+            Some(requests) => requests.iter().any(|request| request == kind),
+            None => true,
         }
     }
 
-    pub async fn retrieve_address(&mut self) -> Result<Response, Box<dyn std::error::Error>> {
+    pub async fn retrieve_address(&self) -> Result<Response, Error> {
         self.send_request(Request::RetrieveAddress).await
     }
 
-    pub async fn send_transaction(
-        &mut self,
+    pub async fn send_transaction(&self, to: String, amount: u64) -> Result<Response, Error> {
+        self.send_request(Request::SendTransaction {
+            to,
+            amount,
+            idempotency_key: None,
+        })
+        .await
+    }
+
+    /// Sends a transaction that is safe to retry: as long as the engine remembers
+    /// `idempotency_key`, a retry returns the original txid instead of paying again.
+    ///
+    /// Engines that don't list the `idempotency` feature in their `Hello` reply ignore the
+    /// key.
+    pub async fn send_transaction_idempotent(
+        &self,
         to: String,
         amount: u64,
-    ) -> Result<Response, Box<dyn std::error::Error>> {
-        self.send_request(Request::SendTransaction { to, amount })
-            .await
+        idempotency_key: String,
+    ) -> Result<Response, Error> {
+        self.send_request(Request::SendTransaction {
+            to,
+            amount,
+            idempotency_key: Some(idempotency_key),
+        })
+        .await
     }
 
     pub async fn send_transaction_from(
-        &mut self,
+        &self,
         from: String,
         to: String,
         amount: u64,
-    ) -> Result<Response, Box<dyn std::error::Error>> {
-        self.send_request(Request::SendTransactionFrom { from, to, amount })
-            .await
+    ) -> Result<Response, Error> {
+        self.send_request(Request::SendTransactionFrom {
+            from,
+            to,
+            amount,
+            idempotency_key: None,
+        })
+        .await
+    }
+
+    /// Like [`AumConnection::send_transaction_idempotent`], from a specific wallet.
+    pub async fn send_transaction_from_idempotent(
+        &self,
+        from: String,
+        to: String,
+        amount: u64,
+        idempotency_key: String,
+    ) -> Result<Response, Error> {
+        self.send_request(Request::SendTransactionFrom {
+            from,
+            to,
+            amount,
+            idempotency_key: Some(idempotency_key),
+        })
+        .await
     }
 
-    pub async fn retrieve_balance(
-        &mut self,
-        address: String,
-    ) -> Result<Response, Box<dyn std::error::Error>> {
+    pub async fn retrieve_balance(&self, address: String) -> Result<Response, Error> {
         self.send_request(Request::RetrieveBalance { address })
             .await
     }
 
-    pub async fn retrieve_balances(&mut self) -> Result<Response, Box<dyn std::error::Error>> {
+    pub async fn retrieve_balances(&self) -> Result<Response, Error> {
         self.send_request(Request::RetrieveBalances).await
     }
 
-    pub async fn list_wallets(&mut self) -> Result<Response, Box<dyn std::error::Error>> {
+    pub async fn list_wallets(&self) -> Result<Response, Error> {
         self.send_request(Request::ListWallets).await
     }
 
-    pub async fn sync(&mut self) -> Result<Response, Box<dyn std::error::Error>> {
+    pub async fn sync(&self) -> Result<Response, Error> {
         self.send_request(Request::Sync).await
     }
+
+    /// Asks whether the engine is healthy and what state its monitor is in.
+    pub async fn health(&self) -> Result<Response, Error> {
+        self.send_request(Request::Health).await
+    }
+
+    /// Reads the audit log entries recorded between `since` and `until`, in Unix
+    /// milliseconds.
+    pub async fn query_audit(
+        &self,
+        since: Option<u64>,
+        until: Option<u64>,
+    ) -> Result<Response, Error> {
+        self.send_request(Request::QueryAudit { since, until })
+            .await
+    }
+
+    /// Asks the engine to check the hash chain of its audit log.
+    pub async fn verify_audit(&self) -> Result<Response, Error> {
+        self.send_request(Request::VerifyAudit).await
+    }
+
+    /// Lists the engine's scheduled jobs and how their last runs went.
+    pub async fn list_jobs(&self) -> Result<Response, Error> {
+        self.send_request(Request::ListJobs).await
+    }
+
+    /// Runs the scheduled job `name` now and waits for it to finish.
+    pub async fn run_job(&self, name: String) -> Result<Response, Error> {
+        self.send_request(Request::RunJob { name }).await
+    }
+
+    /// Executes `requests` in order as one unit, see [`Request::Batch`].
+    ///
+    /// The reply holds one response per request; failed requests are answered with
+    /// [`Response::Error`] instead of failing the whole call.
+    pub async fn send_batch(
+        &self,
+        requests: Vec<Request>,
+        atomic: bool,
+    ) -> Result<Response, Error> {
+        self.send_request(Request::Batch { requests, atomic }).await
+    }
+
+    /// Subscribes to `topics` and returns a stream of the matching events.
+    ///
+    /// Each stream only yields events for the topics it was created with, even if the
+    /// connection is subscribed to more. Events that arrive while a stream falls far behind
+    /// are skipped. The stream ends when the connection closes.
+    pub async fn subscribe(&self, topics: Vec<Topic>) -> Result<EventStream, Error> {
+        let receiver = match self.events.lock().unwrap().as_ref() {
+            Some(events) => events.subscribe(),
+            None => return Err(self.closed()),
+        };
+        self.send_request(Request::Subscribe {
+            topics: topics.clone(),
+        })
+        .await?;
+
+        let events = stream::unfold(receiver, |mut receiver| async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(event) => return Some((event, receiver)),
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        })
+        .filter(move |event| std::future::ready(topics.contains(&event.topic())));
+        Ok(events.boxed())
+    }
+
+    /// Returns why the connection is closed, including the engine's close reason if it
+    /// sent one, e.g. because a connection limit was hit.
+    fn closed(&self) -> Error {
+        match self.close_reason.lock().unwrap().clone() {
+            Some((code, reason)) => Error::ClosedByEngine { code, reason },
+            None => Error::Closed,
+        }
+    }
+
+    /// Stops the engine from pushing events for `topics` on this connection.
+    pub async fn unsubscribe(&self, topics: Vec<Topic>) -> Result<Response, Error> {
+        self.send_request(Request::Unsubscribe { topics }).await
+    }
+}
+
+impl Drop for AumConnection {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
+
+/// Routes every incoming reply to the request waiting on its id, and pushed events to
+/// the event streams.
+///
+/// When the stream ends the pending map is cleared, which wakes all waiters with an error.
+async fn read_responses(
+    mut stream: SplitStream<WsStream>,
+    pending: Pending,
+    events: Events,
+    close_reason: CloseReason,
+    protocol: Protocol,
+) {
+    while let Some(Ok(msg)) = stream.next().await {
+        let data = match &msg {
+            tungstenite::Message::Text(text) => text.as_bytes(),
+            tungstenite::Message::Binary(data) => data,
+            tungstenite::Message::Close(Some(frame)) => {
+                *close_reason.lock().unwrap() =
+                    Some((u16::from(frame.code), frame.reason.to_string()));
+                continue;
+            }
+            _ => continue,
+        };
+        let incoming = match protocol {
+            Protocol::JsonRpc => decode_jsonrpc(data),
+            protocol => decode_envelope(protocol, data),
+        };
+        for incoming in incoming {
+            match incoming {
+                Incoming::Reply(id, reply) => {
+                    if let Some(tx) = pending.lock().unwrap().remove(&id) {
+                        let _ = tx.send(reply);
+                    }
+                }
+                Incoming::Event(event) => {
+                    if let Some(events) = events.lock().unwrap().as_ref() {
+                        let _ = events.send(event);
+                    }
+                }
+            }
+        }
+    }
+    pending.lock().unwrap().clear();
+    events.lock().unwrap().take();
+}
+
+/// A decoded message from the engine.
+enum Incoming {
+    Reply(u64, Reply),
+    Event(Event),
+}
+
+/// The id of a response envelope whose response could not be parsed.
+#[derive(Deserialize)]
+struct EnvelopeId {
+    id: Option<u64>,
+}
+
+fn decode_envelope(protocol: Protocol, data: &[u8]) -> Vec<Incoming> {
+    let Ok(envelope) = protocol.decode::<ResponseEnvelope>(data) else {
+        // A response this client doesn't know, e.g. from a newer engine; fail the request
+        // waiting for it instead of leaving it hanging.
+        return match protocol.decode::<EnvelopeId>(data) {
+            Ok(EnvelopeId { id: Some(id) }) => {
+                let response = Response::error(ErrorCode::Unknown, "Unrecognized response.");
+                vec![Incoming::Reply(id, Reply::Native(response))]
+            }
+            _ => Vec::new(),
+        };
+    };
+    match (envelope.id, envelope.response) {
+        (Some(id), response) => vec![Incoming::Reply(id, Reply::Native(response))],
+        (None, Response::Event { event }) => vec![Incoming::Event(event)],
+        (None, _) => Vec::new(),
+    }
+}
+
+fn decode_jsonrpc(data: &[u8]) -> Vec<Incoming> {
+    let messages = match serde_json::from_slice::<serde_json::Value>(data) {
+        Ok(serde_json::Value::Array(messages)) => messages,
+        Ok(message) => vec![message],
+        Err(_) => return Vec::new(),
+    };
+    messages
+        .into_iter()
+        .filter_map(|message| {
+            if message.get("method").and_then(|m| m.as_str()) == Some(jsonrpc::EVENT_METHOD) {
+                let event = serde_json::from_value(message.get("params")?.clone()).ok()?;
+                return Some(Incoming::Event(event));
+            }
+            let response = serde_json::from_value::<RpcResponse>(message).ok()?;
+            match response.id {
+                Id::Number(id) => Some(Incoming::Reply(id as u64, Reply::JsonRpc(response))),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::tungstenite::{
+        Message,
+        handshake::server::{Request as HttpRequest, Response as HttpResponse},
+    };
+
+    /// A fake engine speaking `protocol` to a single client. It reads `count` requests, then
+    /// hands their ids and requests to `reply`, which returns the messages to send back.
+    async fn engine<F>(protocol: Protocol, count: usize, reply: F) -> String
+    where
+        F: FnOnce(Vec<(u64, Request)>) -> Vec<Message> + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let bind = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            #[allow(clippy::result_large_err)]
+            let accept = |_: &HttpRequest, mut response: HttpResponse| {
+                if protocol != Protocol::Native {
+                    response.headers_mut().insert(
+                        header::SEC_WEBSOCKET_PROTOCOL,
+                        header::HeaderValue::from_static(protocol.subprotocol()),
+                    );
+                }
+                Ok(response)
+            };
+            let mut ws = tokio_tungstenite::accept_hdr_async(socket, accept)
+                .await
+                .unwrap();
+            let mut requests = Vec::new();
+            while requests.len() < count {
+                let data = match ws.next().await.unwrap().unwrap() {
+                    Message::Text(text) => text.as_bytes().to_vec(),
+                    Message::Binary(data) => data.to_vec(),
+                    _ => continue,
+                };
+                requests.push(match protocol {
+                    Protocol::JsonRpc => {
+                        let call: RpcRequest = protocol.decode(&data).unwrap();
+                        let Some(Id::Number(id)) = call.id else {
+                            panic!("calls have numeric ids");
+                        };
+                        (id as u64, call.to_request().unwrap())
+                    }
+                    protocol => {
+                        let envelope: RequestEnvelope = protocol.decode(&data).unwrap();
+                        (envelope.id, envelope.request)
+                    }
+                });
+            }
+            for message in reply(requests) {
+                ws.send(message).await.unwrap();
+            }
+            // Keep the connection open until the client is done with it.
+            while ws.next().await.is_some() {}
+        });
+        bind
+    }
+
+    /// Answers a balance request with the address it asked for.
+    fn balance(request: &Request) -> Response {
+        match request {
+            Request::RetrieveBalance { address } => Response::RetrieveBalance {
+                address: address.clone(),
+                balance: address.len() as u64,
+            },
+            request => panic!("unexpected request {:?}", request),
+        }
+    }
+
+    fn encode(protocol: Protocol, id: Option<u64>, response: Response) -> Message {
+        let data = match (protocol, id) {
+            (Protocol::JsonRpc, Some(id)) => protocol
+                .encode(&RpcResponse::new(Id::Number(id as i64), response))
+                .unwrap(),
+            (Protocol::JsonRpc, None) => match response {
+                Response::Event { event } => {
+                    jsonrpc::event_notification(&event).to_string().into_bytes()
+                }
+                response => panic!("only events are sent without an id, not {:?}", response),
+            },
+            (protocol, id) => protocol
+                .encode(&ResponseEnvelope::new(id, response))
+                .unwrap(),
+        };
+        if protocol.is_binary() {
+            Message::Binary(data.into())
+        } else {
+            Message::Text(String::from_utf8(data).unwrap().into())
+        }
+    }
+
+    fn assert_balance(response: Result<Response, Error>, expected: &str) {
+        match response {
+            Ok(Response::RetrieveBalance { address, balance }) => {
+                assert_eq!(address, expected);
+                assert_eq!(balance, expected.len() as u64);
+            }
+            other => panic!("expected the balance of {}, got {:?}", expected, other),
+        }
+    }
+
+    #[tokio::test]
+    async fn replies_out_of_order_reach_their_requests() {
+        for protocol in Protocol::ALL {
+            let bind = engine(protocol, 3, move |requests| {
+                requests
+                    .iter()
+                    .rev()
+                    .map(|(id, request)| encode(protocol, Some(*id), balance(request)))
+                    .collect()
+            })
+            .await;
+            let connection = AumAPI::new(&bind)
+                .with_protocol(protocol)
+                .connect()
+                .await
+                .unwrap();
+            let (a, bb, ccc) = tokio::join!(
+                connection.retrieve_balance("a".to_owned()),
+                connection.retrieve_balance("bb".to_owned()),
+                connection.retrieve_balance("ccc".to_owned()),
+            );
+            assert_balance(a, "a");
+            assert_balance(bb, "bb");
+            assert_balance(ccc, "ccc");
+        }
+    }
+
+    #[tokio::test]
+    async fn errors_and_events_are_routed_by_id() {
+        let protocol = Protocol::Native;
+        let bind = engine(protocol, 2, move |requests| {
+            let id = |kind| {
+                let (id, _) = requests.iter().find(|(_, r)| r.kind() == kind).unwrap();
+                *id
+            };
+            let (subscribe, send) = (id("Subscribe"), id("SendTransaction"));
+            let event = Event::WalletCreated {
+                address: "a".to_owned(),
+            };
+            vec![
+                // A reply nobody waits for is dropped.
+                encode(protocol, Some(999), Response::Sync { success: true }),
+                encode(
+                    protocol,
+                    Some(send),
+                    Response::error(ErrorCode::InsufficientBalance, "not enough"),
+                ),
+                encode(
+                    protocol,
+                    Some(subscribe),
+                    Response::Subscribed {
+                        topics: vec![Topic::Wallets],
+                    },
+                ),
+                encode(protocol, None, Response::Event { event }),
+            ]
+        })
+        .await;
+        let connection = AumAPI::new(&bind).connect().await.unwrap();
+        let (events, sent) = tokio::join!(
+            connection.subscribe(vec![Topic::Wallets]),
+            connection.send_transaction("b".to_owned(), 5),
+        );
+        assert_eq!(
+            sent.unwrap_err().code(),
+            Some(ErrorCode::InsufficientBalance)
+        );
+        let event = events.unwrap().next().await.unwrap();
+        assert_eq!(
+            event,
+            Event::WalletCreated {
+                address: "a".to_owned()
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn unrecognized_replies_fail_their_request() {
+        let bind = engine(Protocol::Native, 2, |requests| {
+            requests
+                .iter()
+                .map(|(id, request)| match request {
+                    Request::RetrieveBalance { address } if address == "a" => {
+                        encode(Protocol::Native, Some(*id), balance(request))
+                    }
+                    _ => Message::Text(
+                        format!(r#"{{"id":{},"response":{{"Teleported":{{}}}}}}"#, id).into(),
+                    ),
+                })
+                .rev()
+                .collect()
+        })
+        .await;
+        let connection = AumAPI::new(&bind).connect().await.unwrap();
+        let (first, second) = tokio::join!(
+            connection.retrieve_balance("a".to_owned()),
+            connection.retrieve_balance("bb".to_owned()),
+        );
+        assert_balance(first, "a");
+        assert_eq!(second.unwrap_err().code(), Some(ErrorCode::Unknown));
+    }
+
+    #[tokio::test]
+    async fn requests_in_flight_fail_when_the_connection_closes() {
+        let bind = engine(Protocol::Native, 1, |_| vec![Message::Close(None)]).await;
+        let connection = AumAPI::new(&bind).connect().await.unwrap();
+        assert!(matches!(connection.sync().await, Err(Error::Closed)));
+        assert!(matches!(connection.sync().await, Err(Error::Closed)));
+    }
 }
@@ -0,0 +1,401 @@
+use crate::errors::Error;
+use aum_api::{AumAPI, Credentials};
+use aum_core::prelude::{Protocol, Request, Topic};
+use std::{path::PathBuf, time::Duration};
+
+/// Engine address used when neither `--url` nor `AUM_URL` is given; the engine's default
+/// listen address.
+const DEFAULT_URL: &str = "127.0.0.1:9000";
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub const USAGE: &str = "\
+Talks to a running AUM engine.
+
+Usage: aum [OPTIONS] <COMMAND> [ARGS]
+
+Commands:
+  info                             Show the engine's version, network and features
+  address                          Print the address of the best wallet to receive funds
+  send <TO> <AMOUNT>               Send AMOUNT to TO from any wallet
+  send-from <FROM> <TO> <AMOUNT>   Send AMOUNT to TO from the wallet FROM
+  balance <ADDRESS>                Show the balance of a wallet
+  balances                         Show the balances of all wallets
+  wallets                          List the wallets
+  sync                             Synchronize the engine with its backend
+  watch [TOPIC]...                 Print events until the connection closes; TOPIC is one of
+                                   balance_changes, incoming_transactions,
+                                   outgoing_confirmations or wallets, all by default
+  batch [FILE]                     Send a JSON array of requests read from FILE or stdin
+  audit                            Show the audit log
+  verify-audit                     Check the hash chain of the audit log
+  health                           Show the health of the engine and its monitor
+  jobs                             List the scheduled jobs
+  run-job <NAME>                   Run a scheduled job now and wait for it to finish
+
+Options:
+  -u, --url <URL>              Engine address: host:port, a ws:// or wss:// URL, or
+                               unix:PATH [env: AUM_URL] [default: 127.0.0.1:9000]
+  -k, --key <ID:SECRET>        API key to authenticate with [env: AUM_API_KEY]
+      --tls                    Connect over TLS [env: AUM_TLS]
+      --root-ca <FILE>         Also trust the CA certificates in this PEM file; implies
+                               --tls [env: AUM_ROOT_CA]
+      --protocol <NAME>        Wire protocol: json, jsonrpc, cbor or msgpack
+                               [env: AUM_PROTOCOL] [default: json]
+      --timeout <SECONDS>      Give up if the engine doesn't answer in time
+                               [env: AUM_TIMEOUT] [default: 30]
+      --json                   Print responses as JSON instead of tables
+      --idempotency-key <KEY>  With send and send-from, make retries with the same KEY
+                               return the original transaction
+      --atomic                 With batch, run no request unless all of them pass
+      --since <MS>             With audit, skip entries recorded before this Unix time
+      --until <MS>             With audit, skip entries recorded after this Unix time
+  -h, --help                   Print this help
+  -V, --version                Print the version
+
+Exit status:
+  0  Success
+  1  The engine failed the request
+  2  Invalid command line or batch input
+  3  The engine could not be reached, or the connection was lost
+  4  Authentication failed, or the API key is not permitted to do this
+  5  The engine rejected the request as invalid, e.g. an unknown address or job
+  6  Insufficient balance
+  7  Rate limited
+  8  The engine is degraded, or its audit log failed verification
+";
+
+/// What the command line asks for.
+pub enum Invocation {
+    Run(Box<Cli>),
+    Help,
+    Version,
+}
+
+pub struct Cli {
+    pub connect: Connect,
+    pub timeout: Duration,
+    pub json: bool,
+    pub command: Command,
+}
+
+/// How to reach the engine.
+pub struct Connect {
+    url: String,
+    credentials: Option<Credentials>,
+    tls: bool,
+    root_ca: Option<PathBuf>,
+    protocol: Protocol,
+}
+
+impl Connect {
+    pub fn api(&self) -> AumAPI {
+        let mut api = AumAPI::new(&self.url).with_protocol(self.protocol);
+        if let Some(credentials) = &self.credentials {
+            api = api.with_credentials(credentials.clone());
+        }
+        match &self.root_ca {
+            Some(root_ca) => api.with_root_ca(root_ca),
+            None if self.tls => api.with_tls(),
+            None => api,
+        }
+    }
+}
+
+pub enum Command {
+    /// Print the engine's `Hello` reply.
+    Info,
+    /// Send a request and print the response.
+    Request(Request),
+    /// Subscribe to topics and print events as they arrive.
+    Watch(Vec<Topic>),
+    /// Send the requests read from a file, or stdin if there is none, as a batch.
+    Batch { path: Option<PathBuf>, atomic: bool },
+}
+
+/// Options that only apply to some commands.
+#[derive(Default)]
+struct CommandOptions {
+    idempotency_key: Option<String>,
+    atomic: bool,
+    since: Option<u64>,
+    until: Option<u64>,
+}
+
+/// Parses the command line, excluding the program name; `env` looks up environment
+/// variables, which flags take precedence over.
+pub fn parse(
+    args: impl IntoIterator<Item = String>,
+    env: impl Fn(&str) -> Option<String>,
+) -> Result<Invocation, Error> {
+    let mut url = env("AUM_URL");
+    let mut key = env("AUM_API_KEY");
+    let mut tls = env("AUM_TLS")
+        .map(|value| parse_bool("AUM_TLS", &value))
+        .transpose()?
+        .unwrap_or(false);
+    let mut root_ca = env("AUM_ROOT_CA").map(PathBuf::from);
+    let mut protocol = env("AUM_PROTOCOL");
+    let mut timeout = env("AUM_TIMEOUT");
+    let mut json = false;
+    let mut options = CommandOptions::default();
+    let mut positional = Vec::new();
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            positional.extend(args.by_ref());
+            break;
+        }
+        // `-` alone names stdin.
+        if !arg.starts_with('-') || arg == "-" {
+            positional.push(arg);
+            continue;
+        }
+        let (flag, inline) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => (flag, Some(value.to_owned())),
+            _ => (arg.as_str(), None),
+        };
+        let mut value = || {
+            inline
+                .clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| usage(format!("{} needs a value", flag)))
+        };
+        match flag {
+            "-h" | "--help" => return Ok(Invocation::Help),
+            "-V" | "--version" => return Ok(Invocation::Version),
+            "-u" | "--url" => url = Some(value()?),
+            "-k" | "--key" => key = Some(value()?),
+            "--tls" => tls = true,
+            "--root-ca" => root_ca = Some(value()?.into()),
+            "--protocol" => protocol = Some(value()?),
+            "--timeout" => timeout = Some(value()?),
+            "--json" => json = true,
+            "--idempotency-key" => options.idempotency_key = Some(value()?),
+            "--atomic" => options.atomic = true,
+            "--since" => options.since = Some(parse_number(flag, &value()?)?),
+            "--until" => options.until = Some(parse_number(flag, &value()?)?),
+            _ => return Err(usage(format!("unknown option {}", flag))),
+        }
+        if inline.is_some() && matches!(flag, "--tls" | "--json" | "--atomic") {
+            return Err(usage(format!("{} takes no value", flag)));
+        }
+    }
+
+    let credentials = key
+        .map(|key| match key.split_once(':') {
+            Some((id, secret)) if !id.is_empty() => Ok(Credentials::new(id, secret)),
+            _ => Err(usage("the API key must be given as ID:SECRET")),
+        })
+        .transpose()?;
+    let protocol = protocol
+        .map(|name| parse_protocol(&name))
+        .transpose()?
+        .unwrap_or_default();
+    let timeout = match timeout {
+        Some(seconds) => Duration::from_secs(parse_number("--timeout", &seconds)?),
+        None => DEFAULT_TIMEOUT,
+    };
+    let command = parse_command(positional, options)?;
+
+    Ok(Invocation::Run(Box::new(Cli {
+        connect: Connect {
+            url: url.unwrap_or_else(|| DEFAULT_URL.to_owned()),
+            credentials,
+            tls,
+            root_ca,
+            protocol,
+        },
+        timeout,
+        json,
+        command,
+    })))
+}
+
+fn parse_command(positional: Vec<String>, options: CommandOptions) -> Result<Command, Error> {
+    let Some((name, args)) = positional.split_first() else {
+        return Err(usage("no command given"));
+    };
+    let expect = |names: &[&str]| {
+        if args.len() == names.len() {
+            Ok(())
+        } else if names.is_empty() {
+            Err(usage(format!("{} takes no arguments", name)))
+        } else {
+            Err(usage(format!(
+                "usage: aum {} <{}>",
+                name,
+                names.join("> <")
+            )))
+        }
+    };
+    let CommandOptions {
+        idempotency_key,
+        atomic,
+        since,
+        until,
+    } = options;
+    let mut unused = Vec::new();
+    if idempotency_key.is_some() && !matches!(name.as_str(), "send" | "send-from") {
+        unused.push("--idempotency-key");
+    }
+    if atomic && name != "batch" {
+        unused.push("--atomic");
+    }
+    if since.is_some() && name != "audit" {
+        unused.push("--since");
+    }
+    if until.is_some() && name != "audit" {
+        unused.push("--until");
+    }
+    if let Some(flag) = unused.first() {
+        return Err(usage(format!("{} can't be used with {}", flag, name)));
+    }
+
+    let request = match name.as_str() {
+        "info" => {
+            expect(&[])?;
+            return Ok(Command::Info);
+        }
+        "watch" => {
+            let topics = match args {
+                [] => vec![
+                    Topic::BalanceChanges,
+                    Topic::IncomingTransactions,
+                    Topic::OutgoingConfirmations,
+                    Topic::Wallets,
+                ],
+                topics => topics
+                    .iter()
+                    .map(|topic| parse_topic(topic))
+                    .collect::<Result<_, _>>()?,
+            };
+            return Ok(Command::Watch(topics));
+        }
+        "batch" => {
+            let path = match args {
+                [] => None,
+                [path] if path == "-" => None,
+                [path] => Some(path.into()),
+                _ => return Err(usage("usage: aum batch [FILE]")),
+            };
+            return Ok(Command::Batch { path, atomic });
+        }
+        "address" => {
+            expect(&[])?;
+            Request::RetrieveAddress
+        }
+        "send" => {
+            expect(&["TO", "AMOUNT"])?;
+            Request::SendTransaction {
+                to: args[0].clone(),
+                amount: parse_number("AMOUNT", &args[1])?,
+                idempotency_key,
+            }
+        }
+        "send-from" => {
+            expect(&["FROM", "TO", "AMOUNT"])?;
+            Request::SendTransactionFrom {
+                from: args[0].clone(),
+                to: args[1].clone(),
+                amount: parse_number("AMOUNT", &args[2])?,
+                idempotency_key,
+            }
+        }
+        "balance" => {
+            expect(&["ADDRESS"])?;
+            Request::RetrieveBalance {
+                address: args[0].clone(),
+            }
+        }
+        "balances" => {
+            expect(&[])?;
+            Request::RetrieveBalances
+        }
+        "wallets" => {
+            expect(&[])?;
+            Request::ListWallets
+        }
+        "sync" => {
+            expect(&[])?;
+            Request::Sync
+        }
+        "audit" => {
+            expect(&[])?;
+            Request::QueryAudit { since, until }
+        }
+        "verify-audit" => {
+            expect(&[])?;
+            Request::VerifyAudit
+        }
+        "health" => {
+            expect(&[])?;
+            Request::Health
+        }
+        "jobs" => {
+            expect(&[])?;
+            Request::ListJobs
+        }
+        "run-job" => {
+            expect(&["NAME"])?;
+            Request::RunJob {
+                name: args[0].clone(),
+            }
+        }
+        _ => return Err(usage(format!("unknown command {:?}", name))),
+    };
+    Ok(Command::Request(request))
+}
+
+fn usage(message: impl Into<String>) -> Error {
+    Error::Usage(message.into())
+}
+
+fn parse_number(name: &str, value: &str) -> Result<u64, Error> {
+    value
+        .parse()
+        .map_err(|_| usage(format!("{} must be a whole number, not {:?}", name, value)))
+}
+
+fn parse_bool(name: &str, value: &str) -> Result<bool, Error> {
+    match value.to_ascii_lowercase().as_str() {
+        "" | "0" | "false" | "no" => Ok(false),
+        "1" | "true" | "yes" => Ok(true),
+        _ => Err(usage(format!(
+            "{} must be true or false, not {:?}",
+            name, value
+        ))),
+    }
+}
+
+/// Accepts the short protocol names from the usage text as well as subprotocol names.
+fn parse_protocol(name: &str) -> Result<Protocol, Error> {
+    let protocol = match name {
+        "json" | "native" => Some(Protocol::Native),
+        "jsonrpc" => Some(Protocol::JsonRpc),
+        "cbor" => Some(Protocol::Cbor),
+        "msgpack" | "messagepack" => Some(Protocol::MessagePack),
+        name => Protocol::from_subprotocol(name),
+    };
+    protocol.ok_or_else(|| {
+        usage(format!(
+            "unknown protocol {:?}, expected json, jsonrpc, cbor or msgpack",
+            name
+        ))
+    })
+}
+
+fn parse_topic(name: &str) -> Result<Topic, Error> {
+    serde_json::from_value(name.replace('-', "_").into())
+        .map_err(|_| usage(format!("unknown topic {:?}", name)))
+}
+
+/// Reads the requests of a batch, a JSON array such as `[{"RetrieveBalance": {"address":
+/// "..."}}, "ListWallets"]`.
+pub fn read_batch(path: Option<&PathBuf>) -> Result<Vec<Request>, Error> {
+    let input = match path {
+        Some(path) => std::fs::read_to_string(path)?,
+        None => std::io::read_to_string(std::io::stdin())?,
+    };
+    Ok(serde_json::from_str(&input)?)
+}
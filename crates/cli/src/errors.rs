@@ -0,0 +1,100 @@
+use aum_core::prelude::ErrorCode;
+use std::{process::ExitCode, time::Duration};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// The command line is invalid.
+    #[error("{0}")]
+    Usage(String),
+    #[error(transparent)]
+    Api(#[from] aum_api::Error),
+    #[error("The engine did not answer within {} s.", .0.as_secs())]
+    Timeout(Duration),
+    #[error("Could not read the batch: {0}")]
+    BatchUnreadable(#[from] std::io::Error),
+    #[error("Invalid batch, expected a JSON array of requests: {0}")]
+    InvalidBatch(#[from] serde_json::Error),
+}
+
+/// Exit status of the CLI, as listed in the usage text.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Status {
+    Success = 0,
+    /// The engine failed the request.
+    Failed = 1,
+    /// The command line or the batch input is invalid.
+    Usage = 2,
+    /// The engine could not be reached, or the connection was lost.
+    Unreachable = 3,
+    /// Authentication failed, or the API key is not permitted to make the request.
+    Denied = 4,
+    /// The engine rejected the request as invalid, e.g. an unknown address or job.
+    Rejected = 5,
+    InsufficientBalance = 6,
+    RateLimited = 7,
+    /// The engine is degraded, or its audit log failed verification.
+    Unhealthy = 8,
+}
+
+impl From<Status> for ExitCode {
+    fn from(status: Status) -> Self {
+        ExitCode::from(status as u8)
+    }
+}
+
+impl Status {
+    /// Returns the exit status for a request the engine failed with `code`.
+    pub fn of(code: ErrorCode) -> Self {
+        match code {
+            ErrorCode::Unauthorized | ErrorCode::Forbidden => Status::Denied,
+            ErrorCode::InvalidRequest
+            | ErrorCode::InvalidAddress
+            | ErrorCode::UnsupportedAddressFormat
+            | ErrorCode::InvalidKey
+            | ErrorCode::InvalidTransaction
+            | ErrorCode::InvalidHash
+            | ErrorCode::IdempotencyConflict
+            | ErrorCode::UnknownJob => Status::Rejected,
+            ErrorCode::InsufficientBalance => Status::InsufficientBalance,
+            ErrorCode::RateLimited => Status::RateLimited,
+            ErrorCode::MonitorNotRunning
+            | ErrorCode::MonitorFailed
+            | ErrorCode::AuditLogCorrupted => Status::Unhealthy,
+            ErrorCode::KeyGenerationFailed
+            | ErrorCode::TransactionFailed
+            | ErrorCode::OutcomeUnknown
+            | ErrorCode::Aborted
+            | ErrorCode::UnsupportedProtocolVersion
+            | ErrorCode::AuditDisabled
+            | ErrorCode::JobFailed
+            | ErrorCode::Internal
+            | ErrorCode::Unknown => Status::Failed,
+        }
+    }
+}
+
+impl Error {
+    /// Returns the exit status to report this error with.
+    pub fn status(&self) -> Status {
+        match self {
+            Error::Usage(_)
+            | Error::BatchUnreadable(_)
+            | Error::InvalidBatch(_)
+            | Error::Api(aum_api::Error::InvalidCredentials) => Status::Usage,
+            Error::Api(aum_api::Error::Remote { code, .. }) => Status::of(*code),
+            Error::Api(
+                aum_api::Error::WsError(_)
+                | aum_api::Error::IoError(_)
+                | aum_api::Error::TlsError(_)
+                | aum_api::Error::Closed
+                | aum_api::Error::ClosedByEngine { .. },
+            )
+            | Error::Timeout(_) => Status::Unreachable,
+            Error::Api(
+                aum_api::Error::ProtocolError(_)
+                | aum_api::Error::IncompatibleProtocol { .. }
+                | aum_api::Error::Unsupported(_),
+            ) => Status::Failed,
+        }
+    }
+}
@@ -0,0 +1,104 @@
+//! `aum`, a command-line client for a running AUM engine. Run `aum --help` for usage.
+
+mod args;
+mod errors;
+mod output;
+
+use args::{Cli, Command, Invocation};
+use errors::{Error, Status};
+use futures_util::StreamExt;
+use std::{future::Future, process::ExitCode, time::Duration};
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> ExitCode {
+    let invocation = args::parse(std::env::args().skip(1), |name| std::env::var(name).ok());
+    let cli = match invocation {
+        Ok(Invocation::Run(cli)) => cli,
+        Ok(Invocation::Help) => {
+            print!("{}", args::USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Ok(Invocation::Version) => {
+            println!("aum {}", env!("CARGO_PKG_VERSION"));
+            return ExitCode::SUCCESS;
+        }
+        Err(e) => {
+            eprintln!("error: {}\n\nRun `aum --help` for usage.", e);
+            return Status::Usage.into();
+        }
+    };
+    match run(&cli).await {
+        Ok(status) => status.into(),
+        Err(Error::Api(aum_api::Error::Remote {
+            code,
+            message,
+            details,
+        })) if cli.json => {
+            // Scripts get engine errors in the same shape as any other response.
+            let _ = output::response(
+                &aum_core::prelude::Response::Error {
+                    code,
+                    message,
+                    details,
+                },
+                true,
+            );
+            Status::of(code).into()
+        }
+        Err(e) => {
+            eprintln!("error: {}", e);
+            e.status().into()
+        }
+    }
+}
+
+async fn run(cli: &Cli) -> Result<Status, Error> {
+    // Read a batch before connecting, so that bad input fails fast.
+    let batch = match &cli.command {
+        Command::Batch { path, .. } => Some(args::read_batch(path.as_ref())?),
+        _ => None,
+    };
+
+    let connection = within(cli.timeout, cli.connect.api().connect()).await?;
+    // Also makes the connection refuse requests the engine doesn't support.
+    let hello = within(
+        cli.timeout,
+        connection.hello(Some(format!("aum-cli/{}", env!("CARGO_PKG_VERSION")))),
+    )
+    .await?;
+
+    let response = match &cli.command {
+        Command::Info => hello,
+        Command::Request(request) => {
+            within(cli.timeout, connection.send_request(request.clone())).await?
+        }
+        Command::Batch { atomic, .. } => {
+            let requests = batch.unwrap_or_default();
+            within(cli.timeout, connection.send_batch(requests, *atomic)).await?
+        }
+        Command::Watch(topics) => {
+            let mut events = within(cli.timeout, connection.subscribe(topics.clone())).await?;
+            while let Some(event) = events.next().await {
+                if output::event(&event, cli.json).is_err() {
+                    // Nobody is reading anymore.
+                    return Ok(Status::Success);
+                }
+            }
+            return Err(aum_api::Error::Closed.into());
+        }
+    };
+    // A reader that went away early, e.g. `head`, is not an error.
+    let _ = output::response(&response, cli.json);
+    Ok(output::status(&response))
+}
+
+/// Waits for a call to the engine, giving up after `timeout`.
+async fn within<T>(
+    timeout: Duration,
+    call: impl Future<Output = Result<T, aum_api::Error>>,
+) -> Result<T, Error> {
+    tokio::time::timeout(timeout, call)
+        .await
+        .map_err(|_| Error::Timeout(timeout))?
+        .map_err(Error::from)
+}
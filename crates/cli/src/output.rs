@@ -0,0 +1,413 @@
+use crate::errors::Status;
+use aum_core::prelude::{
+    AuditAction, AuditEntry, AuditOutcome, ErrorCode, Event, HealthStatus, JobInfo, Response,
+};
+use serde::Serialize;
+use std::{
+    fmt,
+    io::{self, Write},
+};
+
+/// Prints a response, as a table or as the JSON the engine sent.
+///
+/// Fails if stdout is closed, e.g. because it was piped into `head`.
+pub fn response(response: &Response, json: bool) -> io::Result<()> {
+    if json {
+        writeln!(io::stdout(), "{}", response)
+    } else {
+        write!(io::stdout(), "{}", render(response))
+    }
+}
+
+/// Prints an event on one line, or as one line of JSON.
+pub fn event(event: &Event, json: bool) -> io::Result<()> {
+    let mut stdout = io::stdout();
+    if json {
+        writeln!(stdout, "{}", serde_json::to_string(event)?)?;
+    } else {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        writeln!(stdout, "{}  {}", format_time(now), describe_event(event))?;
+    }
+    // Events may be far apart, so don't leave them sitting in a pipe's buffer.
+    stdout.flush()
+}
+
+/// Returns the exit status for a response that arrived but reports a failure.
+pub fn status(response: &Response) -> Status {
+    match response {
+        Response::Error { code, .. } => Status::of(*code),
+        Response::Sync { success: false } => Status::Failed,
+        Response::Health {
+            status: HealthStatus::Degraded,
+            ..
+        } => Status::Unhealthy,
+        // Report why an atomic batch failed rather than the requests aborted because of it.
+        Response::Batch { responses } => responses
+            .iter()
+            .filter(|response| {
+                !matches!(
+                    response,
+                    Response::Error {
+                        code: ErrorCode::Aborted,
+                        ..
+                    }
+                )
+            })
+            .chain(responses)
+            .map(status)
+            .find(|status| *status != Status::Success)
+            .unwrap_or(Status::Success),
+        _ => Status::Success,
+    }
+}
+
+fn render(response: &Response) -> String {
+    match response {
+        Response::Hello {
+            protocol_version,
+            server_version,
+            requests,
+            network,
+            features,
+        } => fields(&[
+            ("Server version", server_version.clone()),
+            ("Protocol version", protocol_version.to_string()),
+            ("Network", network.clone().unwrap_or_else(|| "-".to_owned())),
+            ("Features", features.join(", ")),
+            ("Requests", requests.join(", ")),
+        ]),
+        Response::RetrieveAddress { address } => format!("{}\n", address),
+        Response::SendTransaction { txid } => format!("{}\n", txid),
+        Response::SendTransactionFrom { from, txid } => {
+            fields(&[("From", from.clone()), ("Txid", txid.clone())])
+        }
+        Response::RetrieveBalance { address, balance } => {
+            let mut table = Table::new(&["ADDRESS", "BALANCE"]).right_align(1);
+            table.row(vec![address.clone(), balance.to_string()]);
+            table.to_string()
+        }
+        Response::RetrieveBalances { balances } => {
+            let mut table = Table::new(&["ADDRESS", "BALANCE"]).right_align(1);
+            for (address, balance) in balances {
+                table.row(vec![address.clone(), balance.to_string()]);
+            }
+            let total: u128 = balances.iter().map(|(_, balance)| *balance as u128).sum();
+            table.row(vec!["TOTAL".to_owned(), total.to_string()]);
+            table.to_string()
+        }
+        Response::ListWallets { wallets } => {
+            let mut table = Table::new(&["ADDRESS"]);
+            for wallet in wallets {
+                table.row(vec![wallet.clone()]);
+            }
+            table.to_string()
+        }
+        Response::Sync { success: true } => "Synchronized.\n".to_owned(),
+        Response::Sync { success: false } => "Synchronization failed.\n".to_owned(),
+        Response::Batch { responses } => {
+            let mut table = Table::new(&["#", "RESULT"]).right_align(0);
+            for (i, response) in responses.iter().enumerate() {
+                table.row(vec![(i + 1).to_string(), summarize(response)]);
+            }
+            table.to_string()
+        }
+        Response::AuditEntries { entries } => {
+            let mut table =
+                Table::new(&["SEQ", "TIME", "IDENTITY", "ACTION", "OUTCOME"]).right_align(0);
+            for entry in entries {
+                table.row(audit_row(entry));
+            }
+            table.to_string()
+        }
+        Response::AuditVerified { entries, head } => fields(&[
+            ("Audit log", "intact".to_owned()),
+            ("Entries", entries.to_string()),
+            ("Head", head.clone().unwrap_or_else(|| "-".to_owned())),
+        ]),
+        Response::Health { status, monitor } => fields(&[
+            ("Status", name(status)),
+            ("Monitor", name(&monitor.state)),
+            (
+                "Consecutive failures",
+                monitor.consecutive_failures.to_string(),
+            ),
+            ("Restarts", monitor.restarts.to_string()),
+            ("Last error", optional(monitor.last_error.clone())),
+            (
+                "Retry in",
+                optional(monitor.retry_in_ms.map(format_duration)),
+            ),
+        ]),
+        Response::Jobs { jobs } => {
+            let mut table = Table::new(&[
+                "NAME",
+                "SCHEDULE",
+                "STATE",
+                "RUNS",
+                "FAILURES",
+                "LAST RUN",
+                "DURATION",
+                "NEXT RUN",
+                "LAST ERROR",
+            ])
+            .right_align(3)
+            .right_align(4);
+            for job in jobs {
+                table.row(vec![
+                    job.name.clone(),
+                    job.schedule.clone(),
+                    job_state(job).to_owned(),
+                    job.runs.to_string(),
+                    job.failures.to_string(),
+                    optional(job.last_run_ms.map(format_time)),
+                    optional(job.last_duration_ms.map(format_duration)),
+                    optional(job.next_run_ms.map(format_time)),
+                    optional(job.last_error.clone()),
+                ]);
+            }
+            table.to_string()
+        }
+        Response::JobRun { job } => fields(&[
+            ("Job", job.name.clone()),
+            ("Schedule", job.schedule.clone()),
+            ("State", job_state(job).to_owned()),
+            ("Runs", job.runs.to_string()),
+            ("Failures", job.failures.to_string()),
+            ("Last run", optional(job.last_run_ms.map(format_time))),
+            (
+                "Duration",
+                optional(job.last_duration_ms.map(format_duration)),
+            ),
+            ("Next run", optional(job.next_run_ms.map(format_time))),
+        ]),
+        Response::Subscribed { topics } => {
+            let topics: Vec<String> = topics.iter().map(name).collect();
+            format!("Subscribed to {}.\n", topics.join(", "))
+        }
+        Response::Event { event } => format!("{}\n", describe_event(event)),
+        Response::Error { .. } => format!("{}\n", summarize(response)),
+    }
+}
+
+/// Describes a response on one line, for the rows of a batch.
+fn summarize(response: &Response) -> String {
+    match response {
+        Response::RetrieveAddress { address } => address.clone(),
+        Response::SendTransaction { txid } => format!("txid {}", txid),
+        Response::SendTransactionFrom { from, txid } => format!("txid {} from {}", txid, from),
+        Response::RetrieveBalance { address, balance } => format!("{}: {}", address, balance),
+        Response::RetrieveBalances { balances } => {
+            let total: u128 = balances.iter().map(|(_, balance)| *balance as u128).sum();
+            format!("{} wallets, total {}", balances.len(), total)
+        }
+        Response::ListWallets { wallets } => format!("{} wallets", wallets.len()),
+        Response::Sync { success } => if *success {
+            "synchronized"
+        } else {
+            "synchronization failed"
+        }
+        .to_owned(),
+        Response::Health { status, .. } => name(status),
+        Response::Error { code, message, .. } => format!("error: {} ({})", message, name(code)),
+        response => serde_json::to_string(response).unwrap_or_default(),
+    }
+}
+
+fn describe_event(event: &Event) -> String {
+    match event {
+        Event::BalanceChanged {
+            address,
+            old_balance,
+            new_balance,
+        } => format!("balance   {}  {} -> {}", address, old_balance, new_balance),
+        Event::IncomingTransaction { address, amount } => {
+            format!("incoming  {}  +{}", address, amount)
+        }
+        Event::OutgoingConfirmed {
+            from,
+            to,
+            amount,
+            txid,
+        } => format!(
+            "outgoing  {} -> {}  {}  txid {}",
+            from.as_deref().unwrap_or("-"),
+            to,
+            amount,
+            txid
+        ),
+        Event::WalletCreated { address } => format!("wallet    {}  created", address),
+        Event::WalletDeleted { address } => format!("wallet    {}  deleted", address),
+    }
+}
+
+fn audit_row(entry: &AuditEntry) -> Vec<String> {
+    let action = match &entry.action {
+        AuditAction::SendTransaction { to, amount, .. } => format!("send {} to {}", amount, to),
+        AuditAction::SendTransactionFrom {
+            from, to, amount, ..
+        } => format!("send {} from {} to {}", amount, from, to),
+        AuditAction::WalletCreated { address } => format!("wallet {} created", address),
+        AuditAction::WalletDeleted { address } => format!("wallet {} deleted", address),
+    };
+    let mut outcome = match &entry.outcome {
+        AuditOutcome::Pending => "pending".to_owned(),
+        AuditOutcome::Success { txid: Some(txid) } => format!("ok, txid {}", txid),
+        AuditOutcome::Success { txid: None } => "ok".to_owned(),
+        AuditOutcome::Failure { code, .. } => format!("failed ({})", name(code)),
+    };
+    if let Some(intent) = entry.intent {
+        outcome.push_str(&format!(", of #{}", intent));
+    }
+    if entry.replay {
+        outcome.push_str(", replayed");
+    }
+    vec![
+        entry.seq.to_string(),
+        format_time(entry.timestamp_ms),
+        optional(entry.identity.clone()),
+        action,
+        outcome,
+    ]
+}
+
+fn job_state(job: &JobInfo) -> &'static str {
+    if job.running { "running" } else { "idle" }
+}
+
+/// Returns the name a unit enum variant is serialized as, e.g. `balance_changes`.
+fn name<T: Serialize>(value: &T) -> String {
+    match serde_json::to_value(value) {
+        Ok(serde_json::Value::String(name)) => name,
+        _ => "-".to_owned(),
+    }
+}
+
+fn optional(value: Option<String>) -> String {
+    value.unwrap_or_else(|| "-".to_owned())
+}
+
+/// Lines up labels and values, one per line.
+fn fields(fields: &[(&str, String)]) -> String {
+    let width = fields
+        .iter()
+        .map(|(label, _)| label.len())
+        .max()
+        .unwrap_or(0);
+    fields
+        .iter()
+        .map(|(label, value)| {
+            format!(
+                "{:width$}  {}\n",
+                format!("{}:", label),
+                value,
+                width = width + 1
+            )
+        })
+        .collect()
+}
+
+/// Formats Unix milliseconds as a UTC date and time, e.g. `2026-10-18 09:30:00`.
+fn format_time(ms: u64) -> String {
+    let seconds = ms / 1000;
+    let (days, time) = (seconds / 86_400, seconds % 86_400);
+    // Howard Hinnant's `civil_from_days`, with years starting in March.
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let day_of_era = z % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let (month, year) = if month_from_march < 10 {
+        (month_from_march + 3, year_of_era + era * 400)
+    } else {
+        (month_from_march - 9, year_of_era + era * 400 + 1)
+    };
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
+/// Formats milliseconds as e.g. `850ms`, `2.5s` or `1h30m`.
+fn format_duration(ms: u64) -> String {
+    match ms {
+        0..1_000 => format!("{}ms", ms),
+        1_000..60_000 => format!("{:.1}s", ms as f64 / 1000.0),
+        _ => {
+            let minutes = ms / 60_000;
+            match (minutes / 60, minutes % 60) {
+                (0, minutes) => format!("{}m", minutes),
+                (hours, 0) => format!("{}h", hours),
+                (hours, minutes) => format!("{}h{}m", hours, minutes),
+            }
+        }
+    }
+}
+
+/// Columns of text padded to line up, under a header row.
+struct Table {
+    headers: &'static [&'static str],
+    right_aligned: Vec<usize>,
+    rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    fn new(headers: &'static [&'static str]) -> Self {
+        Self {
+            headers,
+            right_aligned: Vec::new(),
+            rows: Vec::new(),
+        }
+    }
+
+    fn right_align(mut self, column: usize) -> Self {
+        self.right_aligned.push(column);
+        self
+    }
+
+    fn row(&mut self, cells: Vec<String>) {
+        self.rows.push(cells);
+    }
+}
+
+impl fmt::Display for Table {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let headers: Vec<String> = self.headers.iter().map(|&h| h.to_owned()).collect();
+        let lines: Vec<&Vec<String>> = std::iter::once(&headers).chain(&self.rows).collect();
+        let widths: Vec<usize> = (0..headers.len())
+            .map(|column| {
+                lines
+                    .iter()
+                    .map(|cells| cells[column].chars().count())
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect();
+        for cells in lines {
+            let mut line = String::new();
+            for (column, cell) in cells.iter().enumerate() {
+                if column > 0 {
+                    line.push_str("  ");
+                }
+                let width = widths[column];
+                if self.right_aligned.contains(&column) {
+                    line.push_str(&format!("{:>width$}", cell));
+                } else {
+                    line.push_str(&format!("{:width$}", cell));
+                }
+            }
+            writeln!(f, "{}", line.trim_end())?;
+        }
+        Ok(())
+    }
+}
@@ -1,3 +1,5 @@
+use serde::{Deserialize, Serialize};
+
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("{0}")]
@@ -15,3 +17,170 @@ pub enum Error {
     #[error("{0}")]
     HashError(#[from] crate::hash::HashError),
 }
+
+/// A stable, machine-readable classification of an error returned to clients.
+///
+/// Codes are serialized in `snake_case` and are never renamed once released. Codes unknown
+/// to an older client deserialize as [`ErrorCode::Unknown`].
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// The request could not be parsed or is not valid in this context.
+    InvalidRequest,
+    /// The caller did not present valid credentials.
+    Unauthorized,
+    /// The caller's credentials don't permit this request.
+    Forbidden,
+    /// An address is malformed or does not belong to the engine.
+    InvalidAddress,
+    /// The address format is not supported by the backend.
+    UnsupportedAddressFormat,
+    /// A public or secret key is invalid.
+    InvalidKey,
+    /// A key pair could not be generated.
+    KeyGenerationFailed,
+    /// A wallet does not hold enough funds.
+    InsufficientBalance,
+    /// A transaction or transaction id is malformed.
+    InvalidTransaction,
+    /// The backend failed to build or send a transaction.
+    TransactionFailed,
+    /// A hash is malformed or could not be computed.
+    InvalidHash,
+    /// The monitor is not running.
+    MonitorNotRunning,
+    /// The monitor failed or its health check did not pass.
+    MonitorFailed,
+    /// The request was not executed because another request of its atomic batch failed.
+    Aborted,
+    /// An idempotency key was reused for a request with different parameters.
+    IdempotencyConflict,
+    /// The first request with an idempotency key failed in a way that leaves open whether
+    /// it moved funds, so a retry is not sent; check the wallets before using a new key.
+    OutcomeUnknown,
+    /// The peer speaks a protocol version that is no longer supported.
+    UnsupportedProtocolVersion,
+    /// The caller sent too many requests; `retry_after_ms` in the details says when to retry.
+    RateLimited,
+    /// The audit log is not enabled on this engine.
+    AuditDisabled,
+    /// The audit log failed verification: an entry was edited, removed or reordered.
+    AuditLogCorrupted,
+    /// No job with the requested name is scheduled.
+    UnknownJob,
+    /// A job run on request failed.
+    JobFailed,
+    /// An unexpected error inside the engine.
+    Internal,
+    /// A code this client does not know about.
+    #[serde(other)]
+    Unknown,
+}
+
+impl Error {
+    /// Returns the error code reported to clients for this error.
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Error::AddressError(e) => e.code(),
+            Error::TransactionError(e) => e.code(),
+            Error::KeypairError(e) => e.code(),
+            Error::MonitorError(e) => e.code(),
+            Error::WalletManagerError(e) => e.code(),
+            Error::WalletError(e) => e.code(),
+            Error::HashError(e) => e.code(),
+        }
+    }
+}
+
+impl crate::address::AddressError {
+    /// Returns the error code reported to clients for this error.
+    pub fn code(&self) -> ErrorCode {
+        use crate::address::AddressError;
+        match self {
+            AddressError::InvalidFormat | AddressError::ParseError => ErrorCode::InvalidAddress,
+            AddressError::UnsupportedFormat => ErrorCode::UnsupportedAddressFormat,
+            AddressError::InvalidPublicKey | AddressError::InvalidSecretKey => {
+                ErrorCode::InvalidKey
+            }
+        }
+    }
+}
+
+impl crate::transaction::TransactionError {
+    /// Returns the error code reported to clients for this error.
+    pub fn code(&self) -> ErrorCode {
+        use crate::transaction::TransactionError;
+        match self {
+            TransactionError::InvalidTransactionId | TransactionError::InvalidBytes => {
+                ErrorCode::InvalidTransaction
+            }
+            TransactionError::Custom(_) => ErrorCode::TransactionFailed,
+        }
+    }
+}
+
+impl crate::keypair::KeyPairError {
+    /// Returns the error code reported to clients for this error.
+    pub fn code(&self) -> ErrorCode {
+        use crate::keypair::KeyPairError;
+        match self {
+            KeyPairError::FailedToGenerateKeyPair | KeyPairError::Custom(_) => {
+                ErrorCode::KeyGenerationFailed
+            }
+            KeyPairError::InvalidBytes
+            | KeyPairError::InvalidHex
+            | KeyPairError::InvalidPublicKey
+            | KeyPairError::InvalidSecretKey => ErrorCode::InvalidKey,
+        }
+    }
+}
+
+impl crate::monitor::MonitorError {
+    /// Returns the error code reported to clients for this error.
+    pub fn code(&self) -> ErrorCode {
+        use crate::monitor::MonitorError;
+        match self {
+            MonitorError::WalletManagerError(e) => e.code(),
+            MonitorError::Custom(_) | MonitorError::HealthCheckFailed => ErrorCode::MonitorFailed,
+            MonitorError::NotRunning => ErrorCode::MonitorNotRunning,
+        }
+    }
+}
+
+impl crate::wallet::WalletManagerError {
+    /// Returns the error code reported to clients for this error.
+    pub fn code(&self) -> ErrorCode {
+        use crate::wallet::WalletManagerError;
+        match self {
+            WalletManagerError::WalletError(e) => e.code(),
+            WalletManagerError::Custom(_) => ErrorCode::Internal,
+        }
+    }
+}
+
+impl crate::wallet::WalletError {
+    /// Returns the error code reported to clients for this error.
+    pub fn code(&self) -> ErrorCode {
+        use crate::wallet::WalletError;
+        match self {
+            WalletError::InsufficientBalance => ErrorCode::InsufficientBalance,
+            WalletError::InvalidAddress => ErrorCode::InvalidAddress,
+            WalletError::TransactionError(e) => e.code(),
+            WalletError::Custom(_) => ErrorCode::Internal,
+        }
+    }
+}
+
+impl crate::hash::HashError {
+    /// Returns the error code reported to clients for this error.
+    pub fn code(&self) -> ErrorCode {
+        ErrorCode::InvalidHash
+    }
+}
+
+impl crate::protocol::ProtocolError {
+    /// Returns the error code reported to clients for this error.
+    pub fn code(&self) -> ErrorCode {
+        ErrorCode::InvalidRequest
+    }
+}
@@ -0,0 +1,642 @@
+//! JSON-RPC 2.0 mapping of the engine protocol.
+//!
+//! Every [`Request`] variant is exposed as a method named after it, e.g. `RetrieveAddress`
+//! becomes `aum_retrieveAddress` and `SendTransaction` becomes `aum_sendTransaction`. The
+//! variant's fields are the params, given either by name or by position in declaration
+//! order, and the result is the body of the matching [`Response`] variant. Failures are
+//! reported as JSON-RPC error objects whose `data` carries the engine [`ErrorCode`].
+//!
+//! Events for subscribed topics are pushed as `aum_event` notifications.
+use crate::error::ErrorCode;
+use crate::reqres::{Event, Request, Response};
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::{Map, Value, json};
+
+/// The `jsonrpc` member of every message.
+pub const VERSION: &str = "2.0";
+
+/// Method of the notifications carrying pushed events.
+pub const EVENT_METHOD: &str = "aum_event";
+
+/// Invalid JSON was received.
+pub const PARSE_ERROR: i64 = -32700;
+/// The JSON sent is not a valid request object.
+pub const INVALID_REQUEST: i64 = -32600;
+/// The method does not exist.
+pub const METHOD_NOT_FOUND: i64 = -32601;
+/// Invalid method parameters.
+pub const INVALID_PARAMS: i64 = -32602;
+/// Internal error.
+pub const INTERNAL_ERROR: i64 = -32603;
+/// Any other engine error; the exact [`ErrorCode`] is in the error's `data`.
+pub const SERVER_ERROR: i64 = -32000;
+
+/// A request exposed as a JSON-RPC method.
+struct Method {
+    name: &'static str,
+    request: &'static str,
+    response: &'static str,
+    /// Fields of the request variant in declaration order, for positional params.
+    params: &'static [&'static str],
+}
+
+const METHODS: &[Method] = &[
+    Method {
+        name: "aum_hello",
+        request: "Hello",
+        response: "Hello",
+        params: &["protocol_version", "client_name"],
+    },
+    Method {
+        name: "aum_retrieveAddress",
+        request: "RetrieveAddress",
+        response: "RetrieveAddress",
+        params: &[],
+    },
+    Method {
+        name: "aum_sendTransaction",
+        request: "SendTransaction",
+        response: "SendTransaction",
+        params: &["to", "amount", "idempotency_key"],
+    },
+    Method {
+        name: "aum_sendTransactionFrom",
+        request: "SendTransactionFrom",
+        response: "SendTransactionFrom",
+        params: &["from", "to", "amount", "idempotency_key"],
+    },
+    Method {
+        name: "aum_retrieveBalance",
+        request: "RetrieveBalance",
+        response: "RetrieveBalance",
+        params: &["address"],
+    },
+    Method {
+        name: "aum_retrieveBalances",
+        request: "RetrieveBalances",
+        response: "RetrieveBalances",
+        params: &[],
+    },
+    Method {
+        name: "aum_listWallets",
+        request: "ListWallets",
+        response: "ListWallets",
+        params: &[],
+    },
+    Method {
+        name: "aum_sync",
+        request: "Sync",
+        response: "Sync",
+        params: &[],
+    },
+    Method {
+        name: "aum_subscribe",
+        request: "Subscribe",
+        response: "Subscribed",
+        params: &["topics"],
+    },
+    Method {
+        name: "aum_unsubscribe",
+        request: "Unsubscribe",
+        response: "Subscribed",
+        params: &["topics"],
+    },
+    Method {
+        name: "aum_batch",
+        request: "Batch",
+        response: "Batch",
+        params: &["requests", "atomic"],
+    },
+    Method {
+        name: "aum_queryAudit",
+        request: "QueryAudit",
+        response: "AuditEntries",
+        params: &["since", "until"],
+    },
+    Method {
+        name: "aum_verifyAudit",
+        request: "VerifyAudit",
+        response: "AuditVerified",
+        params: &[],
+    },
+    Method {
+        name: "aum_health",
+        request: "Health",
+        response: "Health",
+        params: &[],
+    },
+    Method {
+        name: "aum_listJobs",
+        request: "ListJobs",
+        response: "Jobs",
+        params: &[],
+    },
+    Method {
+        name: "aum_runJob",
+        request: "RunJob",
+        response: "JobRun",
+        params: &["name"],
+    },
+];
+
+fn find_method(name: &str) -> Option<&'static Method> {
+    METHODS.iter().find(|method| method.name == name)
+}
+
+/// A request identifier: a number, a string or `null`.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq, Hash)]
+#[serde(untagged)]
+pub enum Id {
+    Number(i64),
+    String(String),
+    Null,
+}
+
+/// A JSON-RPC request, or a notification when it has no `id`.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct RpcRequest {
+    pub jsonrpc: String,
+    pub method: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub params: Option<Value>,
+    #[serde(
+        default,
+        deserialize_with = "deserialize_id",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub id: Option<Id>,
+}
+
+/// A JSON-RPC response; exactly one of `result` and `error` is set.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct RpcResponse {
+    pub jsonrpc: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<RpcError>,
+    pub id: Id,
+}
+
+/// A JSON-RPC error object.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+/// Tells a present `"id": null` apart from a missing id, which marks a notification.
+fn deserialize_id<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Id>, D::Error> {
+    Id::deserialize(deserializer).map(Some)
+}
+
+impl RpcRequest {
+    /// Wraps `request` as a call with the given id, or as a notification if `id` is `None`.
+    pub fn new(request: &Request, id: Option<Id>) -> Self {
+        // Variants with fields serialize as `{ "Variant": { ...fields } }`, the rest as a string.
+        let params = match serde_json::to_value(request) {
+            Ok(Value::Object(map)) => map.into_iter().next().map(|(_, v)| v),
+            _ => None,
+        };
+        Self {
+            jsonrpc: VERSION.to_owned(),
+            method: method_of(request).to_owned(),
+            params,
+            id,
+        }
+    }
+
+    /// Turns the call back into a [`Request`].
+    pub fn to_request(&self) -> Result<Request, RpcError> {
+        if self.jsonrpc != VERSION {
+            return Err(RpcError::new(INVALID_REQUEST, "Invalid Request"));
+        }
+        let Some(method) = find_method(&self.method) else {
+            return Err(RpcError::new(METHOD_NOT_FOUND, "Method not found"));
+        };
+        let invalid_params = |reason: String| {
+            RpcError::new(INVALID_PARAMS, "Invalid params").with_data(json!({ "reason": reason }))
+        };
+        let params = match self.params.clone() {
+            None | Some(Value::Null) => Map::new(),
+            Some(Value::Object(params)) => params,
+            Some(Value::Array(params)) => {
+                if params.len() > method.params.len() {
+                    return Err(invalid_params(format!(
+                        "expected at most {} params",
+                        method.params.len()
+                    )));
+                }
+                method
+                    .params
+                    .iter()
+                    .map(|name| name.to_string())
+                    .zip(params)
+                    .collect()
+            }
+            Some(_) => return Err(invalid_params("params must be an array or object".into())),
+        };
+        // Variants without fields serialize as a bare string.
+        let request = if method.params.is_empty() {
+            json!(method.request)
+        } else {
+            json!({ method.request: params })
+        };
+        serde_json::from_value(request).map_err(|e| invalid_params(e.to_string()))
+    }
+}
+
+impl RpcResponse {
+    /// Builds the reply to a call from the engine's response.
+    pub fn new(id: Id, response: Response) -> Self {
+        match response {
+            Response::Error {
+                code,
+                message,
+                details,
+            } => Self::error(id, RpcError::from_engine(code, message, details)),
+            response => {
+                let result = match serde_json::to_value(response) {
+                    Ok(Value::Object(map)) => map.into_iter().next().map(|(_, v)| v),
+                    _ => None,
+                };
+                Self {
+                    jsonrpc: VERSION.to_owned(),
+                    result: Some(result.unwrap_or(Value::Null)),
+                    error: None,
+                    id,
+                }
+            }
+        }
+    }
+
+    pub fn error(id: Id, error: RpcError) -> Self {
+        Self {
+            jsonrpc: VERSION.to_owned(),
+            result: None,
+            error: Some(error),
+            id,
+        }
+    }
+
+    /// Turns the reply to a call of `method` back into a [`Response`].
+    pub fn into_response(self, method: &str) -> Response {
+        if let Some(error) = self.error {
+            return error.into_response();
+        }
+        let Some(method) = find_method(method) else {
+            return Response::error(ErrorCode::Unknown, format!("unknown method {:?}", method));
+        };
+        let result = self.result.unwrap_or(Value::Null);
+        serde_json::from_value(json!({ method.response: result })).unwrap_or_else(|e| {
+            Response::Error {
+                code: ErrorCode::Unknown,
+                message: "malformed result".to_owned(),
+                details: Some(json!({ "reason": e.to_string() })),
+            }
+        })
+    }
+}
+
+impl RpcError {
+    pub fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            data: None,
+        }
+    }
+
+    pub fn with_data(mut self, data: Value) -> Self {
+        self.data = Some(data);
+        self
+    }
+
+    /// Converts an engine error, keeping its [`ErrorCode`] and details in `data`.
+    pub fn from_engine(code: ErrorCode, message: String, details: Option<Value>) -> Self {
+        let rpc_code = match code {
+            ErrorCode::InvalidRequest => INVALID_PARAMS,
+            ErrorCode::Internal => INTERNAL_ERROR,
+            _ => SERVER_ERROR,
+        };
+        let mut data = json!({ "code": code });
+        if let Some(details) = details {
+            data["details"] = details;
+        }
+        Self::new(rpc_code, message).with_data(data)
+    }
+
+    /// Converts the error into a [`Response::Error`].
+    ///
+    /// Errors raised by the JSON-RPC layer itself carry no engine code and are reported
+    /// as [`ErrorCode::InvalidRequest`] or [`ErrorCode::Internal`].
+    pub fn into_response(self) -> Response {
+        let code = self
+            .data
+            .as_ref()
+            .and_then(|data| data.get("code"))
+            .and_then(|code| serde_json::from_value(code.clone()).ok())
+            .unwrap_or(match self.code {
+                PARSE_ERROR | INVALID_REQUEST | METHOD_NOT_FOUND | INVALID_PARAMS => {
+                    ErrorCode::InvalidRequest
+                }
+                INTERNAL_ERROR => ErrorCode::Internal,
+                _ => ErrorCode::Unknown,
+            });
+        let details = match self.data {
+            Some(Value::Object(mut data)) if data.contains_key("code") => data.remove("details"),
+            data => data,
+        };
+        Response::Error {
+            code,
+            message: self.message,
+            details,
+        }
+    }
+}
+
+/// Returns the method name of a request, e.g. `aum_sendTransaction`.
+pub fn method_of(request: &Request) -> &'static str {
+    let kind = request.kind();
+    METHODS
+        .iter()
+        .find(|method| method.request == kind)
+        .map(|method| method.name)
+        .expect("every request variant has a method")
+}
+
+/// Builds the `aum_event` notification that pushes `event`.
+pub fn event_notification(event: &Event) -> Value {
+    json!({
+        "jsonrpc": VERSION,
+        "method": EVENT_METHOD,
+        "params": event,
+    })
+}
+
+/// A decoded incoming message: a single call or a batch of them.
+#[derive(Debug)]
+pub enum Incoming {
+    Single(Result<RpcRequest, RpcError>),
+    Batch(Vec<Result<RpcRequest, RpcError>>),
+}
+
+/// Decodes a message received from a JSON-RPC client.
+///
+/// Fails for messages that are not valid JSON or an empty batch, which are answered with
+/// a single error and a `null` id; elements of a batch that are not valid requests are
+/// decoded as errors individually.
+pub fn parse(text: &str) -> Result<Incoming, RpcError> {
+    let value: Value = serde_json::from_str(text).map_err(|e| {
+        RpcError::new(PARSE_ERROR, "Parse error").with_data(json!({ "reason": e.to_string() }))
+    })?;
+    match value {
+        Value::Array(items) if items.is_empty() => {
+            Err(RpcError::new(INVALID_REQUEST, "Invalid Request"))
+        }
+        Value::Array(items) => Ok(Incoming::Batch(
+            items.into_iter().map(parse_request).collect(),
+        )),
+        value => Ok(Incoming::Single(parse_request(value))),
+    }
+}
+
+fn parse_request(value: Value) -> Result<RpcRequest, RpcError> {
+    serde_json::from_value(value).map_err(|e| {
+        RpcError::new(INVALID_REQUEST, "Invalid Request")
+            .with_data(json!({ "reason": e.to_string() }))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reqres::Topic;
+
+    fn json<T: Serialize>(value: &T) -> Value {
+        serde_json::to_value(value).unwrap()
+    }
+
+    fn call(text: &str) -> RpcRequest {
+        match parse(text).unwrap() {
+            Incoming::Single(request) => request.unwrap(),
+            Incoming::Batch(_) => panic!("expected a single call"),
+        }
+    }
+
+    #[test]
+    fn every_method_round_trips() {
+        let requests = [
+            Request::Hello {
+                protocol_version: 1,
+                client_name: None,
+            },
+            Request::RetrieveAddress,
+            Request::SendTransaction {
+                to: "b".to_owned(),
+                amount: 5,
+                idempotency_key: Some("k".to_owned()),
+            },
+            Request::SendTransactionFrom {
+                from: "a".to_owned(),
+                to: "b".to_owned(),
+                amount: 5,
+                idempotency_key: None,
+            },
+            Request::RetrieveBalance {
+                address: "a".to_owned(),
+            },
+            Request::RetrieveBalances,
+            Request::ListWallets,
+            Request::Sync,
+            Request::Subscribe {
+                topics: vec![Topic::Wallets],
+            },
+            Request::Unsubscribe { topics: Vec::new() },
+            Request::Batch {
+                requests: vec![Request::Sync],
+                atomic: false,
+            },
+            Request::QueryAudit {
+                since: None,
+                until: Some(9),
+            },
+            Request::VerifyAudit,
+            Request::Health,
+            Request::ListJobs,
+            Request::RunJob {
+                name: "sync".to_owned(),
+            },
+        ];
+        assert_eq!(requests.len(), Request::KINDS.len());
+        for (id, request) in (1..).zip(requests) {
+            let text =
+                serde_json::to_string(&RpcRequest::new(&request, Some(Id::Number(id)))).unwrap();
+            let decoded = call(&text);
+            assert_eq!(decoded.id, Some(Id::Number(id)));
+            assert_eq!(decoded.method, method_of(&request));
+            assert_eq!(json(&decoded.to_request().unwrap()), json(&request));
+        }
+    }
+
+    #[test]
+    fn params_can_be_positional() {
+        let request = call(
+            r#"{"jsonrpc":"2.0","method":"aum_sendTransactionFrom","params":["a","b",5],"id":"x"}"#,
+        );
+        assert_eq!(request.id, Some(Id::String("x".to_owned())));
+        assert!(matches!(
+            request.to_request().unwrap(),
+            Request::SendTransactionFrom { from, to, amount: 5, idempotency_key: None }
+                if from == "a" && to == "b"
+        ));
+
+        let too_many = call(r#"{"jsonrpc":"2.0","method":"aum_runJob","params":["a","b"],"id":1}"#);
+        assert_eq!(too_many.to_request().unwrap_err().code, INVALID_PARAMS);
+    }
+
+    #[test]
+    fn bad_calls_get_the_matching_error() {
+        let error = |text: &str| call(text).to_request().unwrap_err().code;
+        assert_eq!(
+            error(r#"{"jsonrpc":"1.0","method":"aum_sync","id":1}"#),
+            INVALID_REQUEST
+        );
+        assert_eq!(
+            error(r#"{"jsonrpc":"2.0","method":"aum_pay","id":1}"#),
+            METHOD_NOT_FOUND
+        );
+        assert_eq!(
+            error(r#"{"jsonrpc":"2.0","method":"aum_retrieveBalance","params":{},"id":1}"#),
+            INVALID_PARAMS
+        );
+        assert_eq!(parse("{").unwrap_err().code, PARSE_ERROR);
+        assert_eq!(parse("[]").unwrap_err().code, INVALID_REQUEST);
+    }
+
+    #[test]
+    fn null_ids_are_not_notifications() {
+        let notification = call(r#"{"jsonrpc":"2.0","method":"aum_sync"}"#);
+        assert_eq!(notification.id, None);
+        let null = call(r#"{"jsonrpc":"2.0","method":"aum_sync","id":null}"#);
+        assert_eq!(null.id, Some(Id::Null));
+        assert!(
+            serde_json::to_string(&null)
+                .unwrap()
+                .contains(r#""id":null"#)
+        );
+    }
+
+    #[test]
+    fn batches_decode_each_call() {
+        let Incoming::Batch(calls) = parse(
+            r#"[{"jsonrpc":"2.0","method":"aum_sync","id":1}, 5, {"jsonrpc":"2.0","method":"aum_health","id":2}]"#,
+        )
+        .unwrap() else {
+            panic!("expected a batch");
+        };
+        assert_eq!(calls.len(), 3);
+        assert_eq!(calls[0].as_ref().unwrap().id, Some(Id::Number(1)));
+        assert_eq!(calls[1].as_ref().unwrap_err().code, INVALID_REQUEST);
+        assert_eq!(calls[2].as_ref().unwrap().method, "aum_health");
+    }
+
+    #[test]
+    fn results_round_trip() {
+        let responses = [
+            (
+                "aum_retrieveBalance",
+                Response::RetrieveBalance {
+                    address: "a".to_owned(),
+                    balance: 7,
+                },
+            ),
+            (
+                "aum_subscribe",
+                Response::Subscribed {
+                    topics: vec![Topic::IncomingTransactions],
+                },
+            ),
+            (
+                "aum_batch",
+                Response::Batch {
+                    responses: vec![Response::Sync { success: true }],
+                },
+            ),
+            (
+                "aum_verifyAudit",
+                Response::AuditVerified {
+                    entries: 0,
+                    head: None,
+                },
+            ),
+        ];
+        for (method, response) in responses {
+            let reply = RpcResponse::new(Id::Number(3), response.clone());
+            let text = serde_json::to_string(&reply).unwrap();
+            let decoded: RpcResponse = serde_json::from_str(&text).unwrap();
+            assert_eq!(decoded.id, Id::Number(3));
+            assert!(decoded.error.is_none());
+            assert_eq!(json(&decoded.into_response(method)), json(&response));
+        }
+    }
+
+    #[test]
+    fn engine_errors_keep_their_code_and_details() {
+        let error = Response::Error {
+            code: ErrorCode::RateLimited,
+            message: "slow down".to_owned(),
+            details: Some(json!({ "retry_after_ms": 250 })),
+        };
+        let reply = RpcResponse::new(Id::Number(1), error.clone());
+        let rpc_error = reply.error.as_ref().unwrap();
+        assert_eq!(rpc_error.code, SERVER_ERROR);
+        assert_eq!(rpc_error.data.as_ref().unwrap()["code"], "rate_limited");
+
+        let text = serde_json::to_string(&reply).unwrap();
+        let decoded: RpcResponse = serde_json::from_str(&text).unwrap();
+        assert!(decoded.result.is_none());
+        assert_eq!(json(&decoded.into_response("aum_sync")), json(&error));
+
+        let invalid = RpcResponse::new(
+            Id::Null,
+            Response::error(ErrorCode::InvalidRequest, "bad amount"),
+        );
+        assert_eq!(invalid.error.as_ref().unwrap().code, INVALID_PARAMS);
+    }
+
+    #[test]
+    fn protocol_errors_map_to_engine_codes() {
+        let not_found = RpcError::new(METHOD_NOT_FOUND, "Method not found")
+            .with_data(json!({ "reason": "aum_pay" }));
+        match not_found.into_response() {
+            Response::Error { code, details, .. } => {
+                assert_eq!(code, ErrorCode::InvalidRequest);
+                assert_eq!(details, Some(json!({ "reason": "aum_pay" })));
+            }
+            other => panic!("expected an error, got {:?}", other),
+        }
+        let other = RpcError::new(-1, "custom").into_response();
+        assert!(matches!(
+            other,
+            Response::Error {
+                code: ErrorCode::Unknown,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn events_are_notifications() {
+        let event = Event::WalletCreated {
+            address: "a".to_owned(),
+        };
+        let notification = call(&event_notification(&event).to_string());
+        assert_eq!(notification.method, EVENT_METHOD);
+        assert_eq!(notification.id, None);
+        let params: Event = serde_json::from_value(notification.params.unwrap()).unwrap();
+        assert_eq!(params, event);
+    }
+}
@@ -1,9 +1,11 @@
 mod address;
 mod error;
 mod hash;
+pub mod jsonrpc;
 mod keypair;
 mod monitor;
 mod network;
+mod protocol;
 mod reqres;
 mod storage;
 mod transaction;
@@ -11,10 +13,11 @@ mod wallet;
 
 pub mod errors {
     pub use crate::address::AddressError;
-    pub use crate::error::Error;
+    pub use crate::error::{Error, ErrorCode};
     pub use crate::hash::HashError;
     pub use crate::keypair::KeyPairError;
     pub use crate::monitor::MonitorError;
+    pub use crate::protocol::ProtocolError;
     pub use crate::transaction::TransactionError;
     pub use crate::wallet::WalletError;
     pub use crate::wallet::WalletManagerError;
@@ -22,13 +25,22 @@ pub mod errors {
 
 pub mod prelude {
     pub use crate::address::{Address, Format};
-    pub use crate::error::Error;
+    pub use crate::error::{Error, ErrorCode};
     pub use crate::hash::Hash;
     pub use crate::keypair::{PublicKey, SecretKey};
     pub use crate::monitor::Monitor;
     pub use crate::network::Network;
-    pub use crate::reqres::{Request, Response};
+    pub use crate::protocol::Protocol;
+    pub use crate::reqres::{
+        AuditAction, AuditEntry, AuditOutcome, Event, HealthStatus, JobInfo, MIN_PROTOCOL_VERSION,
+        MonitorHealth, MonitorState, PROTOCOL_VERSION, Request, RequestEnvelope, Response,
+        ResponseEnvelope, Topic,
+    };
     pub use crate::storage::Storage;
-    pub use crate::transaction::{SignedTransaction, Transaction, TransactionId};
-    pub use crate::wallet::{Wallet, WalletManager};
+    pub use crate::transaction::{
+        SignedTransaction, Transaction, TransactionId, TransactionSignature,
+    };
+    pub use crate::wallet::{
+        AsyncWallet, AsyncWalletManager, SharedWalletManager, Wallet, WalletManager,
+    };
 }
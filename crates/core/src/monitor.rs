@@ -1,12 +1,21 @@
+use crate::wallet::SharedWalletManager;
 use thiserror::Error;
 
 #[async_trait::async_trait]
 pub trait Monitor {
     /// Type that represents wallet manager.
-    type WalletManager: crate::wallet::WalletManager + Send;
-
-    /// Start the monitor with the given scale.
-    async fn start(&self, scale: &mut Self::WalletManager) -> Result<(), MonitorError>;
+    type WalletManager: crate::wallet::AsyncWalletManager;
+
+    /// Start the monitor with the given wallet manager.
+    ///
+    /// The wallet manager is shared with the engine and not locked while the monitor runs,
+    /// so `start` may either run the monitor until it stops, or return once started and
+    /// keep the handle to create and delete wallets later. Wallets created and deleted
+    /// through the handle are recorded in the engine's audit log.
+    async fn start(
+        &self,
+        scale: SharedWalletManager<Self::WalletManager>,
+    ) -> Result<(), MonitorError>;
 
     /// Stop the monitor.
     fn stop(&self) -> Result<(), MonitorError>;
@@ -15,7 +24,10 @@ pub trait Monitor {
     fn is_running(&self) -> bool;
 
     /// Restart the monitor by stopping and starting it again.
-    async fn restart(&self, scale: &mut Self::WalletManager) -> Result<(), MonitorError> {
+    async fn restart(
+        &self,
+        scale: SharedWalletManager<Self::WalletManager>,
+    ) -> Result<(), MonitorError> {
         Self::stop(&self)?;
         Self::start(&self, scale).await?;
         Ok(())
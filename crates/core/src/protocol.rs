@@ -0,0 +1,288 @@
+use serde::{Serialize, de::DeserializeOwned};
+use std::fmt;
+
+/// A wire protocol a connection can speak, negotiated through the WebSocket
+/// `Sec-WebSocket-Protocol` header.
+///
+/// A client that offers no subprotocol gets [`Protocol::Native`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Protocol {
+    /// [`RequestEnvelope`](crate::prelude::RequestEnvelope) and
+    /// [`ResponseEnvelope`](crate::prelude::ResponseEnvelope) as JSON text frames.
+    #[default]
+    Native,
+    /// JSON-RPC 2.0, see [`jsonrpc`](crate::jsonrpc).
+    JsonRpc,
+    /// The native envelopes as CBOR binary frames.
+    Cbor,
+    /// The native envelopes as MessagePack binary frames.
+    MessagePack,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ProtocolError {
+    #[error("Invalid JSON: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Invalid CBOR: {0}")]
+    Cbor(String),
+    #[error("Invalid MessagePack: {0}")]
+    MessagePack(String),
+}
+
+impl Protocol {
+    /// Every protocol, in the order the server prefers them.
+    pub const ALL: [Protocol; 4] = [
+        Protocol::Native,
+        Protocol::JsonRpc,
+        Protocol::Cbor,
+        Protocol::MessagePack,
+    ];
+
+    /// Returns the subprotocol name of this protocol.
+    pub fn subprotocol(self) -> &'static str {
+        match self {
+            Protocol::Native => "aum.json",
+            Protocol::JsonRpc => "jsonrpc-2.0",
+            Protocol::Cbor => "aum.cbor",
+            Protocol::MessagePack => "aum.msgpack",
+        }
+    }
+
+    /// Looks a protocol up by its subprotocol name.
+    pub fn from_subprotocol(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|protocol| protocol.subprotocol() == name.trim())
+    }
+
+    /// Returns whether messages are sent as binary rather than text frames.
+    pub fn is_binary(self) -> bool {
+        matches!(self, Protocol::Cbor | Protocol::MessagePack)
+    }
+
+    /// Serializes a message in this protocol's encoding.
+    ///
+    /// Text protocols produce UTF-8 JSON.
+    pub fn encode<T: Serialize>(self, value: &T) -> Result<Vec<u8>, ProtocolError> {
+        match self {
+            Protocol::Native | Protocol::JsonRpc => Ok(serde_json::to_vec(value)?),
+            Protocol::Cbor => {
+                let mut bytes = Vec::new();
+                ciborium::into_writer(value, &mut bytes)
+                    .map_err(|e| ProtocolError::Cbor(e.to_string()))?;
+                Ok(bytes)
+            }
+            // Structs are written as maps so that optional fields can be left out.
+            Protocol::MessagePack => rmp_serde::to_vec_named(value)
+                .map_err(|e| ProtocolError::MessagePack(e.to_string())),
+        }
+    }
+
+    /// Deserializes a message in this protocol's encoding.
+    pub fn decode<T: DeserializeOwned>(self, bytes: &[u8]) -> Result<T, ProtocolError> {
+        match self {
+            Protocol::Native | Protocol::JsonRpc => Ok(serde_json::from_slice(bytes)?),
+            Protocol::Cbor => {
+                ciborium::from_reader(bytes).map_err(|e| ProtocolError::Cbor(e.to_string()))
+            }
+            Protocol::MessagePack => {
+                rmp_serde::from_slice(bytes).map_err(|e| ProtocolError::MessagePack(e.to_string()))
+            }
+        }
+    }
+}
+
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.subprotocol())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorCode;
+    use crate::reqres::{
+        AuditAction, AuditEntry, AuditOutcome, Event, Request, RequestEnvelope, Response,
+        ResponseEnvelope, Topic,
+    };
+    use serde_json::{Value, json};
+
+    fn requests() -> Vec<Request> {
+        vec![
+            Request::Hello {
+                protocol_version: 1,
+                client_name: Some("test".to_owned()),
+            },
+            Request::RetrieveAddress,
+            Request::SendTransaction {
+                to: "addr".to_owned(),
+                amount: u64::MAX,
+                idempotency_key: None,
+            },
+            Request::SendTransactionFrom {
+                from: "a".to_owned(),
+                to: "b".to_owned(),
+                amount: 5,
+                idempotency_key: Some("k1".to_owned()),
+            },
+            Request::Subscribe {
+                topics: vec![Topic::BalanceChanges, Topic::Wallets],
+            },
+            Request::Batch {
+                requests: vec![Request::Sync, Request::ListWallets],
+                atomic: true,
+            },
+            Request::QueryAudit {
+                since: Some(1),
+                until: None,
+            },
+        ]
+    }
+
+    fn responses() -> Vec<Response> {
+        vec![
+            Response::RetrieveBalances {
+                balances: vec![("a".to_owned(), 1), ("b".to_owned(), 0)],
+            },
+            Response::SendTransactionFrom {
+                from: "a".to_owned(),
+                txid: "tx".to_owned(),
+            },
+            Response::Batch {
+                responses: vec![
+                    Response::Sync { success: true },
+                    Response::error(ErrorCode::InsufficientBalance, "not enough"),
+                ],
+            },
+            Response::AuditEntries {
+                entries: vec![AuditEntry {
+                    seq: 1,
+                    timestamp_ms: 1_700_000_000_000,
+                    identity: None,
+                    action: AuditAction::WalletCreated {
+                        address: "a".to_owned(),
+                    },
+                    outcome: AuditOutcome::Success { txid: None },
+                    intent: None,
+                    replay: false,
+                    prev_hash: "00".to_owned(),
+                    hash: "ff".to_owned(),
+                }],
+            },
+            Response::Error {
+                code: ErrorCode::RateLimited,
+                message: "slow down".to_owned(),
+                details: Some(json!({ "retry_after_ms": 250, "nested": [1, "two", null] })),
+            },
+        ]
+    }
+
+    /// Compares messages by their JSON form, which every field takes part in.
+    fn json<T: Serialize>(value: &T) -> Value {
+        serde_json::to_value(value).unwrap()
+    }
+
+    #[test]
+    fn envelopes_round_trip_in_every_protocol() {
+        for protocol in Protocol::ALL {
+            for (id, request) in (1..).zip(requests()) {
+                let envelope = RequestEnvelope::new(id, request);
+                let bytes = protocol.encode(&envelope).unwrap();
+                let decoded: RequestEnvelope = protocol.decode(&bytes).unwrap();
+                assert_eq!(json(&decoded), json(&envelope), "{}", protocol);
+            }
+            for (id, response) in [Some(7), None].into_iter().cycle().zip(responses()) {
+                let envelope = ResponseEnvelope::new(id, response);
+                let bytes = protocol.encode(&envelope).unwrap();
+                let decoded: ResponseEnvelope = protocol.decode(&bytes).unwrap();
+                assert_eq!(json(&decoded), json(&envelope), "{}", protocol);
+            }
+        }
+    }
+
+    #[test]
+    fn pushed_events_round_trip_in_every_protocol() {
+        let event = Event::OutgoingConfirmed {
+            from: None,
+            to: "b".to_owned(),
+            amount: 3,
+            txid: "tx".to_owned(),
+        };
+        for protocol in Protocol::ALL {
+            let envelope = ResponseEnvelope::new(
+                None,
+                Response::Event {
+                    event: event.clone(),
+                },
+            );
+            let bytes = protocol.encode(&envelope).unwrap();
+            match protocol.decode::<ResponseEnvelope>(&bytes).unwrap() {
+                ResponseEnvelope {
+                    id: None,
+                    response: Response::Event { event: decoded },
+                } => assert_eq!(decoded, event, "{}", protocol),
+                other => panic!("{}: decoded {:?}", protocol, other),
+            }
+        }
+    }
+
+    #[test]
+    fn optional_fields_can_be_left_out() {
+        let envelope =
+            json!({ "id": 1, "request": { "SendTransaction": { "to": "b", "amount": 2 } } });
+        for protocol in Protocol::ALL {
+            let bytes = protocol.encode(&envelope).unwrap();
+            let decoded: RequestEnvelope = protocol.decode(&bytes).unwrap();
+            assert!(matches!(
+                decoded.request,
+                Request::SendTransaction {
+                    idempotency_key: None,
+                    ..
+                }
+            ));
+        }
+    }
+
+    #[test]
+    fn binary_protocols_are_not_json() {
+        let envelope = RequestEnvelope::new(1, Request::Sync);
+        for protocol in [Protocol::Cbor, Protocol::MessagePack] {
+            let bytes = protocol.encode(&envelope).unwrap();
+            assert!(serde_json::from_slice::<Value>(&bytes).is_err());
+            assert!(Protocol::Native.decode::<RequestEnvelope>(&bytes).is_err());
+        }
+    }
+
+    #[test]
+    fn garbage_is_a_protocol_error() {
+        let garbage = [0xff, 0x00, 0x13];
+        assert!(matches!(
+            Protocol::Native.decode::<RequestEnvelope>(&garbage),
+            Err(ProtocolError::Json(_))
+        ));
+        assert!(matches!(
+            Protocol::Cbor.decode::<RequestEnvelope>(&garbage),
+            Err(ProtocolError::Cbor(_))
+        ));
+        assert!(matches!(
+            Protocol::MessagePack.decode::<RequestEnvelope>(&garbage),
+            Err(ProtocolError::MessagePack(_))
+        ));
+    }
+
+    #[test]
+    fn subprotocol_names_round_trip() {
+        for protocol in Protocol::ALL {
+            assert_eq!(
+                Protocol::from_subprotocol(protocol.subprotocol()),
+                Some(protocol)
+            );
+        }
+        assert_eq!(
+            Protocol::from_subprotocol(" aum.cbor "),
+            Some(Protocol::Cbor)
+        );
+        assert_eq!(Protocol::from_subprotocol("aum.xml"), None);
+    }
+}
@@ -1,19 +1,55 @@
+use crate::error::ErrorCode;
 use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Version of the request/response protocol spoken by this crate.
+///
+/// It is raised whenever requests or responses change; additions that older peers can
+/// safely ignore don't require a new minimum version.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Oldest protocol version this crate can still talk to.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
 
 /// Represents various types of requests that can be made.
 #[derive(Deserialize, Serialize, Clone, Debug)]
 pub enum Request {
+    /// Request to introduce the client and learn what the engine supports.
+    ///
+    /// Clients should send it first on every connection, see [`Response::Hello`].
+    Hello {
+        protocol_version: u32,
+        #[serde(default)]
+        client_name: Option<String>,
+    },
+
     /// Request to retrieve the address of the best wallet to use.
     RetrieveAddress,
 
     /// Request to send a transaction to a specific address with a specified amount.
-    SendTransaction { to: String, amount: u64 },
+    ///
+    /// Retrying with the same `idempotency_key` returns the original transaction instead
+    /// of sending another one, see [`Request::SendTransactionFrom`].
+    SendTransaction {
+        to: String,
+        amount: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        idempotency_key: Option<String>,
+    },
 
     /// Request to send a transaction from a specific address to another with a specified amount.
+    ///
+    /// The engine remembers how a request with an `idempotency_key` ended for a while after
+    /// it did; a retry with the same key and parameters returns the original txid or error
+    /// without sending again, or an `outcome_unknown` error if the first request may have
+    /// sent the transaction before failing. Reusing the key for different parameters is
+    /// rejected.
     SendTransactionFrom {
         from: String,
         to: String,
         amount: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        idempotency_key: Option<String>,
     },
 
     /// Request to retrieve the balance of specified wallet.
@@ -25,11 +61,112 @@ pub enum Request {
     ListWallets,
     /// Request to synchronize the system state.
     Sync,
+
+    /// Request to receive [`Response::Event`] pushes for the given topics on this connection.
+    Subscribe { topics: Vec<Topic> },
+
+    /// Request to stop receiving pushes for the given topics.
+    Unsubscribe { topics: Vec<Topic> },
+
+    /// Request to execute several requests in order, answered by [`Response::Batch`].
+    ///
+    /// If `atomic` is set, every request is validated first, including addresses and
+    /// balances, and none is executed unless all of them pass. Batches can't be nested and
+    /// can't contain subscription requests or job runs.
+    Batch {
+        requests: Vec<Request>,
+        #[serde(default)]
+        atomic: bool,
+    },
+
+    /// Request to read the audit log entries recorded between `since` and `until`, in Unix
+    /// milliseconds, both inclusive and unbounded if not given.
+    QueryAudit {
+        #[serde(default)]
+        since: Option<u64>,
+        #[serde(default)]
+        until: Option<u64>,
+    },
+
+    /// Request to check the hash chain of the audit log, answered by
+    /// [`Response::AuditVerified`] or an `audit_log_corrupted` error.
+    VerifyAudit,
+
+    /// Request to report whether the engine and its monitor are healthy.
+    Health,
+
+    /// Request to list the engine's scheduled jobs.
+    ListJobs,
+
+    /// Request to run a scheduled job now and wait for it to finish, answered by
+    /// [`Response::JobRun`] or a `job_failed` error.
+    RunJob { name: String },
+}
+
+impl Request {
+    /// Names of every request variant, as returned by [`Request::kind`].
+    pub const KINDS: &'static [&'static str] = &[
+        "Hello",
+        "RetrieveAddress",
+        "SendTransaction",
+        "SendTransactionFrom",
+        "RetrieveBalance",
+        "RetrieveBalances",
+        "ListWallets",
+        "Sync",
+        "Subscribe",
+        "Unsubscribe",
+        "Batch",
+        "QueryAudit",
+        "VerifyAudit",
+        "Health",
+        "ListJobs",
+        "RunJob",
+    ];
+
+    /// Returns the name of the request variant, e.g. `"SendTransaction"`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Request::Hello { .. } => "Hello",
+            Request::RetrieveAddress => "RetrieveAddress",
+            Request::SendTransaction { .. } => "SendTransaction",
+            Request::SendTransactionFrom { .. } => "SendTransactionFrom",
+            Request::RetrieveBalance { .. } => "RetrieveBalance",
+            Request::RetrieveBalances => "RetrieveBalances",
+            Request::ListWallets => "ListWallets",
+            Request::Sync => "Sync",
+            Request::Subscribe { .. } => "Subscribe",
+            Request::Unsubscribe { .. } => "Unsubscribe",
+            Request::Batch { .. } => "Batch",
+            Request::QueryAudit { .. } => "QueryAudit",
+            Request::VerifyAudit => "VerifyAudit",
+            Request::Health => "Health",
+            Request::ListJobs => "ListJobs",
+            Request::RunJob { .. } => "RunJob",
+        }
+    }
 }
 
 /// Represents various types of responses that can be returned.
 #[derive(Deserialize, Serialize, Clone, Debug)]
 pub enum Response {
+    /// Response describing the engine, sent in reply to [`Request::Hello`].
+    ///
+    /// Clients should check `protocol_version` against [`MIN_PROTOCOL_VERSION`] and only
+    /// send requests listed in `requests`.
+    Hello {
+        /// Protocol version spoken by the engine.
+        protocol_version: u32,
+        /// Version of the engine crate.
+        server_version: String,
+        /// Kinds of requests the engine accepts, e.g. `"SendTransaction"`.
+        requests: Vec<String>,
+        /// Name of the network the engine's wallets live on, if configured.
+        network: Option<String>,
+        /// Optional features enabled on this engine, e.g. `"tls"` or `"rest"`.
+        features: Vec<String>,
+    },
+
     /// Response containing the retrieved wallet address.
     RetrieveAddress { address: String },
 
@@ -50,15 +187,285 @@ pub enum Response {
 
     /// Response containing the transaction ID and the originating address for a sent transaction.
     SendTransactionFrom { from: String, txid: String },
+
+    /// Response containing one response per request of a [`Request::Batch`], in order.
+    ///
+    /// Requests that failed, or were not executed because the atomic batch failed, are
+    /// answered with [`Response::Error`].
+    Batch { responses: Vec<Response> },
+
+    /// Response containing the audit log entries matching a [`Request::QueryAudit`], oldest
+    /// first.
+    AuditEntries { entries: Vec<AuditEntry> },
+
+    /// Response confirming that the audit log is intact.
+    AuditVerified {
+        /// Number of entries checked.
+        entries: u64,
+        /// Hash of the last entry, which can be kept elsewhere to detect later truncation.
+        head: Option<String>,
+    },
+
+    /// Response describing the health of the engine.
+    Health {
+        status: HealthStatus,
+        monitor: MonitorHealth,
+    },
+
+    /// Response listing the scheduled jobs, by name.
+    Jobs { jobs: Vec<JobInfo> },
+
+    /// Response to a [`Request::RunJob`] whose run succeeded.
+    JobRun { job: JobInfo },
+
+    /// Response containing the topics the connection is subscribed to after a change.
+    Subscribed { topics: Vec<Topic> },
+
+    /// A pushed event for a subscribed topic; sent with no envelope id.
+    Event { event: Event },
+
+    /// Response describing why a request failed.
+    Error {
+        code: ErrorCode,
+        message: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        details: Option<serde_json::Value>,
+    },
 }
 
 impl Response {
-    pub fn to_string(&self) -> String {
-        serde_json::to_string_pretty(self).unwrap()
+    /// Creates an error response without details.
+    pub fn error(code: ErrorCode, message: impl Into<String>) -> Self {
+        Response::Error {
+            code,
+            message: message.into(),
+            details: None,
+        }
     }
 }
-impl Into<String> for Response {
-    fn into(self) -> String {
-        self.to_string()
+
+/// A category of events a connection can subscribe to.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Topic {
+    /// A wallet's balance changed.
+    BalanceChanges,
+    /// Funds arrived in a wallet.
+    IncomingTransactions,
+    /// A transaction sent by the engine was accepted by the backend.
+    OutgoingConfirmations,
+    /// A wallet was created or deleted.
+    Wallets,
+}
+
+/// A change pushed to subscribed connections.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// The balance of a wallet changed.
+    BalanceChanged {
+        address: String,
+        old_balance: u64,
+        new_balance: u64,
+    },
+
+    /// A wallet received funds.
+    IncomingTransaction { address: String, amount: u64 },
+
+    /// A transaction sent by the engine was accepted by the backend.
+    OutgoingConfirmed {
+        from: Option<String>,
+        to: String,
+        amount: u64,
+        txid: String,
+    },
+
+    /// A wallet was created.
+    WalletCreated { address: String },
+
+    /// A wallet was deleted.
+    WalletDeleted { address: String },
+}
+
+impl Event {
+    /// Returns the topic this event is published under.
+    pub fn topic(&self) -> Topic {
+        match self {
+            Event::BalanceChanged { .. } => Topic::BalanceChanges,
+            Event::IncomingTransaction { .. } => Topic::IncomingTransactions,
+            Event::OutgoingConfirmed { .. } => Topic::OutgoingConfirmations,
+            Event::WalletCreated { .. } | Event::WalletDeleted { .. } => Topic::Wallets,
+        }
+    }
+}
+
+/// Overall health of an engine.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthStatus {
+    Ok,
+    /// The monitor kept failing; the engine still serves requests, but its view of the
+    /// backend may be stale.
+    Degraded,
+}
+
+/// What the engine's supervisor knows about the monitor.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct MonitorHealth {
+    pub state: MonitorState,
+    /// Failed starts and deaths since the monitor last started successfully.
+    pub consecutive_failures: u32,
+    /// Times the monitor was restarted after failing.
+    pub restarts: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    /// When the next start is attempted, while backing off.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_in_ms: Option<u64>,
+}
+
+/// Lifecycle state of the monitor.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MonitorState {
+    Stopped,
+    Starting,
+    Running,
+    /// The monitor failed and is restarted after a delay.
+    BackingOff,
+}
+
+/// A recurring job of the engine and how its runs went.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct JobInfo {
+    pub name: String,
+    /// When the job runs, e.g. `@every 5m` or a cron expression such as `0 * * * *`.
+    pub schedule: String,
+    /// Whether a run is in progress.
+    pub running: bool,
+    pub runs: u64,
+    pub failures: u64,
+    /// When the last run started, in Unix milliseconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_run_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_duration_ms: Option<u64>,
+    /// Why the last run failed, if it did.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    /// When the job runs next, in Unix milliseconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_run_ms: Option<u64>,
+}
+
+/// A record of a state-changing request or wallet change in the engine's audit log.
+///
+/// Entries are hash-chained: `hash` covers every other field, including `prev_hash`, the
+/// hash of the entry before it.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct AuditEntry {
+    /// Position in the log, starting at 1.
+    pub seq: u64,
+    /// When the entry was recorded, in Unix milliseconds.
+    pub timestamp_ms: u64,
+    /// Key id of the caller, or `None` for anonymous callers and changes made by the engine,
+    /// its jobs or its monitor.
+    pub identity: Option<String>,
+    pub action: AuditAction,
+    pub outcome: AuditOutcome,
+    /// For the outcome of an action recorded as [`AuditOutcome::Pending`] before it was
+    /// taken, the `seq` of that entry.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub intent: Option<u64>,
+    /// Set if the request was answered with the outcome of an earlier one with the same
+    /// idempotency key, without taking the action again.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub replay: bool,
+    /// Hex SHA-256 hash of the previous entry, all zeros for the first one.
+    pub prev_hash: String,
+    /// Hex SHA-256 hash of this entry.
+    pub hash: String,
+}
+
+/// What an [`AuditEntry`] records.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+pub enum AuditAction {
+    SendTransaction {
+        to: String,
+        amount: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        idempotency_key: Option<String>,
+    },
+    SendTransactionFrom {
+        from: String,
+        to: String,
+        amount: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        idempotency_key: Option<String>,
+    },
+    /// A wallet was created in the wallet manager.
+    WalletCreated { address: String },
+    /// A wallet was deleted from the wallet manager.
+    WalletDeleted { address: String },
+}
+
+/// How the action of an [`AuditEntry`] ended.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+pub enum AuditOutcome {
+    /// The action is about to be taken; a later entry referring to this one as its
+    /// `intent` records how it ended. An entry left without one is an action the engine
+    /// stopped during, which may or may not have happened.
+    Pending,
+    Success {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        txid: Option<String>,
+    },
+    Failure {
+        code: ErrorCode,
+        message: String,
+    },
+}
+
+impl fmt::Display for Response {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&serde_json::to_string_pretty(self).map_err(|_| fmt::Error)?)
+    }
+}
+impl From<Response> for String {
+    fn from(response: Response) -> Self {
+        response.to_string()
+    }
+}
+
+/// A request tagged with a client-chosen identifier.
+///
+/// The identifier is echoed back in the matching [`ResponseEnvelope`], which lets a client
+/// keep several requests in flight on one connection and match replies arriving out of order.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct RequestEnvelope {
+    /// Identifier chosen by the client.
+    pub id: u64,
+    /// The wrapped request.
+    pub request: Request,
+}
+
+/// A response tagged with the identifier of the request it answers.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct ResponseEnvelope {
+    /// Identifier of the originating request, or `None` if the reply can't be tied to one,
+    /// e.g. because the request failed to parse.
+    pub id: Option<u64>,
+    /// The outcome of the request; failures are reported as [`Response::Error`].
+    pub response: Response,
+}
+
+impl RequestEnvelope {
+    pub fn new(id: u64, request: Request) -> Self {
+        Self { id, request }
+    }
+}
+
+impl ResponseEnvelope {
+    pub fn new(id: Option<u64>, response: Response) -> Self {
+        Self { id, response }
     }
 }
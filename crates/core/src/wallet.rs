@@ -60,6 +60,10 @@ pub trait WalletManager {
 pub enum WalletManagerError {
     #[error("Wallet error while scale: {0}")]
     WalletError(#[from] crate::wallet::WalletError),
+
+    /// A backend failure that is not about a particular wallet, e.g. a lost connection.
+    #[error("{0}")]
+    Custom(#[from] Box<dyn std::error::Error + Send + Sync>),
 }
 
 pub trait Wallet {
@@ -110,6 +114,121 @@ pub trait Wallet {
     }
 }
 
+/// A shared handle to a wallet manager of type `Wm`, as the engine hands it to
+/// [`Monitor::start`](crate::prelude::Monitor::start).
+pub type SharedWalletManager<Wm> = std::sync::Arc<
+    dyn AsyncWalletManager<
+            Wallet = <Wm as AsyncWalletManager>::Wallet,
+            Address = <Wm as AsyncWalletManager>::Address,
+            TransactionId = <Wm as AsyncWalletManager>::TransactionId,
+        >,
+>;
+
+/// Async counterpart of [`WalletManager`] for backends that make network calls.
+///
+/// Methods take `&self`, so implementations synchronize their own state, and wallets are
+/// returned as owned [`AsyncWallet`] handles instead of references.
+#[async_trait::async_trait]
+pub trait AsyncWalletManager: Send + Sync {
+    type Wallet: AsyncWallet;
+    type Address: crate::address::Address;
+    type TransactionId: crate::transaction::TransactionId;
+
+    /// Creates a new wallet and returns it.
+    async fn create_wallet(&self) -> Result<Self::Wallet, WalletManagerError>;
+
+    /// Deletes the current wallet and transfers its balance to a specified target wallet.
+    /// Returns the target wallet on success.
+    async fn delete_and_transfer(
+        &self,
+        target_wallet: &Self::Wallet,
+    ) -> Result<Self::Wallet, WalletManagerError>;
+
+    /// Deletes the current wallet and distributes its balance across specified target wallets.
+    /// Returns the target wallets on success.
+    async fn delete_and_distribute(
+        &self,
+        target_wallets: &[Self::Wallet],
+    ) -> Result<Vec<Self::Wallet>, WalletManagerError>;
+
+    /// Scales the wallet system to a specified number of wallets, returning the new ones.
+    async fn scale_to(&self, count: u64) -> Result<Vec<Self::Wallet>, WalletManagerError>;
+
+    /// Retrieve address of the best wallet.
+    async fn retrieve_address(&self) -> Result<Self::Address, WalletManagerError>;
+
+    /// Send transaction to a specified address with a given amount.
+    async fn send_transaction(
+        &self,
+        to: &Self::Address,
+        amount: u64,
+    ) -> Result<Self::TransactionId, WalletManagerError>;
+
+    /// Send transaction from a specified address to another with a given amount.
+    async fn send_transaction_from(
+        &self,
+        from: &Self::Address,
+        to: &Self::Address,
+        amount: u64,
+    ) -> Result<Self::TransactionId, WalletManagerError>;
+
+    /// List all available wallets.
+    async fn list_wallets(&self) -> Result<Vec<Self::Wallet>, WalletManagerError>;
+
+    /// Retrieve the balance of a specified wallet.
+    async fn retrieve_balance(&self, address: &Self::Address) -> Result<u64, WalletManagerError>;
+
+    /// Retrieve balances of all wallets.
+    async fn retrieve_balances(&self) -> Result<Vec<(Self::Address, u64)>, WalletManagerError>;
+}
+
+/// Async counterpart of [`Wallet`], a handle to a wallet whose state may live in a backend.
+///
+/// The secret key is left out, since a remote backend usually never hands it out.
+#[async_trait::async_trait]
+pub trait AsyncWallet: Send + Sync {
+    type Transaction: crate::transaction::Transaction;
+    type SignedTransaction: crate::transaction::SignedTransaction;
+    type Address: crate::address::Address;
+    type PublicKey: crate::keypair::PublicKey;
+
+    /// Returns the wallet's address.
+    fn address(&self) -> &Self::Address;
+
+    /// Returns the wallet's public key.
+    fn pubkey(&self) -> &Self::PublicKey;
+
+    /// Returns the wallet's balance.
+    async fn balance(&self) -> Result<u64, WalletError>;
+
+    /// Signs a transaction and returns a signed transaction or an error.
+    async fn sign_transaction(
+        &self,
+        transaction: &Self::Transaction,
+    ) -> Result<Self::SignedTransaction, WalletError>;
+
+    /// Verifies the signature of a signed transaction.
+    async fn verify_transaction_signature(
+        &self,
+        signed_transaction: &Self::SignedTransaction,
+    ) -> Result<bool, WalletError>;
+
+    /// Transfers funds to another address, returning a transaction or an error.
+    async fn transfer_funds(
+        &self,
+        to: &Self::Address,
+        amount: u64,
+    ) -> Result<Self::Transaction, WalletError>;
+
+    /// Retrieves the transaction history for the wallet.
+    async fn transaction_history(&self) -> Result<Vec<Self::Transaction>, WalletError>;
+
+    /// Checks if the wallet has sufficient balance for a given amount.
+    async fn has_sufficient_balance(&self, amount: u64) -> Result<bool, WalletError> {
+        Ok(self.balance().await? >= amount)
+    }
+}
+
 // Define an error type for wallet-related operations.
 #[derive(Debug, Error)]
 pub enum WalletError {
@@ -124,4 +243,8 @@ pub enum WalletError {
     // Error for transaction-related issues, wrapping a TransactionError.
     #[error("Transaction error: {0}")]
     TransactionError(#[from] crate::transaction::TransactionError),
+
+    // Error for backend failures, e.g. a lost connection to a remote wallet.
+    #[error("{0}")]
+    Custom(#[from] Box<dyn std::error::Error + Send + Sync>),
 }
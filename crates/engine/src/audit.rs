@@ -0,0 +1,461 @@
+use crate::Error;
+use aum_core::prelude::{AuditAction, AuditEntry, AuditOutcome, Request, Response};
+use ring::digest;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tracing::warn;
+
+/// `prev_hash` of the first entry.
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// The last entry of a log, kept in a `.head` file next to it to detect truncation.
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct AuditHead {
+    /// Number of entries in the log.
+    pub entries: u64,
+    /// Hash of the last entry, `None` while the log is empty.
+    pub hash: Option<String>,
+}
+
+/// How an audit entry relates to an earlier one.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AuditLink {
+    #[default]
+    None,
+    /// The outcome of the action recorded as pending in the entry with this `seq`.
+    OutcomeOf(u64),
+    /// The request was answered with the outcome of an earlier one with the same
+    /// idempotency key.
+    Replay,
+}
+
+/// An append-only, hash-chained log of state-changing requests, one JSON entry per line.
+///
+/// Every append is flushed to disk before the next one. The head of the chain is mirrored
+/// in `<path>.head`, so cutting entries off the end of the log is detected as well as
+/// editing, removing or reordering them. A log whose head file is removed along with its
+/// last entries can't be told apart from a shorter log; keep the head reported by
+/// [`verify_audit_log`] elsewhere to catch that too.
+pub struct AuditLog {
+    path: PathBuf,
+    state: Mutex<State>,
+}
+
+struct State {
+    file: File,
+    head: AuditHead,
+}
+
+impl AuditLog {
+    /// Opens the log at `path`, creating it if needed.
+    ///
+    /// An existing log is verified first and refused if it was tampered with. An append cut
+    /// short by a crash is finished first: a partly written last entry is cut off, and a
+    /// head file left one entry behind the log is moved up to it.
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, Error> {
+        let path = path.into();
+        let head = if path.exists() {
+            recover(&path)?
+        } else {
+            AuditHead::default()
+        };
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        write_head(&path, &head)?;
+        Ok(Self {
+            path,
+            state: Mutex::new(State { file, head }),
+        })
+    }
+
+    /// Appends an entry for `action` taken by the key `identity`.
+    pub fn append(
+        &self,
+        identity: Option<&str>,
+        action: AuditAction,
+        outcome: AuditOutcome,
+        link: AuditLink,
+    ) -> Result<AuditEntry, Error> {
+        let mut state = self.state.lock().unwrap();
+        let mut entry = AuditEntry {
+            seq: state.head.entries + 1,
+            timestamp_ms: now_ms(),
+            identity: identity.map(str::to_owned),
+            action,
+            outcome,
+            intent: match link {
+                AuditLink::OutcomeOf(seq) => Some(seq),
+                AuditLink::None | AuditLink::Replay => None,
+            },
+            replay: link == AuditLink::Replay,
+            prev_hash: state
+                .head
+                .hash
+                .clone()
+                .unwrap_or_else(|| GENESIS_HASH.to_owned()),
+            hash: String::new(),
+        };
+        entry.hash = hash_entry(&entry)?;
+        let mut line = serde_json::to_vec(&entry).map_err(io::Error::from)?;
+        line.push(b'\n');
+        state.file.write_all(&line)?;
+        state.file.sync_data()?;
+        state.head = AuditHead {
+            entries: entry.seq,
+            hash: Some(entry.hash.clone()),
+        };
+        write_head(&self.path, &state.head)?;
+        Ok(entry)
+    }
+
+    /// Returns the entries recorded between `since` and `until` in Unix milliseconds, both
+    /// inclusive.
+    pub fn query(&self, since: Option<u64>, until: Option<u64>) -> Result<Vec<AuditEntry>, Error> {
+        // Holding the lock keeps appends from writing half a line while it is read.
+        let _state = self.state.lock().unwrap();
+        let mut entries = Vec::new();
+        for entry in read_entries(&self.path)? {
+            let entry = entry?;
+            if since.is_some_and(|since| entry.timestamp_ms < since) {
+                continue;
+            }
+            if until.is_some_and(|until| entry.timestamp_ms > until) {
+                continue;
+            }
+            entries.push(entry);
+        }
+        Ok(entries)
+    }
+
+    /// Checks the hash chain of the whole log against its head.
+    pub fn verify(&self) -> Result<AuditHead, Error> {
+        let _state = self.state.lock().unwrap();
+        verify_audit_log(&self.path)
+    }
+}
+
+/// Checks the audit log at `path` without an engine running, returning its head.
+///
+/// Fails with [`Error::AuditLogCorrupted`] naming the first entry that doesn't fit the
+/// chain, or if the log ends before the head recorded in `<path>.head`. A log whose last
+/// append was cut short by a crash fails too, until [`AuditLog::open`] finishes it.
+pub fn verify_audit_log(path: impl AsRef<Path>) -> Result<AuditHead, Error> {
+    let path = path.as_ref();
+    let chain = read_chain(path)?;
+    if chain.torn {
+        return Err(Error::AuditLogCorrupted(format!(
+            "the entry after entry {} is incomplete",
+            chain.head.entries
+        )));
+    }
+    match read_head(path)? {
+        Some(recorded) if recorded != chain.head => Err(Error::AuditLogCorrupted(format!(
+            "log ends at entry {}, but its head file records {} entries",
+            chain.head.entries, recorded.entries
+        ))),
+        _ => Ok(chain.head),
+    }
+}
+
+/// The entries of a log on disk, as far as they form a chain.
+#[derive(Default)]
+struct Chain {
+    /// Head after the last complete entry.
+    head: AuditHead,
+    /// Head before the last complete entry.
+    before_last: AuditHead,
+    /// Length of the log up to the end of the last complete entry.
+    len: u64,
+    /// Whether part of an entry follows the last complete one.
+    torn: bool,
+}
+
+fn read_chain(path: &Path) -> Result<Chain, Error> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut chain = Chain::default();
+    let mut line = Vec::new();
+    for number in 1.. {
+        line.clear();
+        let read = reader.read_until(b'\n', &mut line)?;
+        if read == 0 {
+            break;
+        }
+        if line.last() != Some(&b'\n') {
+            // Entries are written with their newline, so only an interrupted append lacks it.
+            chain.torn = true;
+            break;
+        }
+        let entry: AuditEntry = serde_json::from_slice(&line)
+            .map_err(|e| Error::AuditLogCorrupted(format!("line {}: {}", number, e)))?;
+        let head = &chain.head;
+        if entry.seq != head.entries + 1 {
+            return Err(Error::AuditLogCorrupted(format!(
+                "line {}: expected entry {}, found {}",
+                number,
+                head.entries + 1,
+                entry.seq
+            )));
+        }
+        if entry.prev_hash != head.hash.as_deref().unwrap_or(GENESIS_HASH) {
+            return Err(Error::AuditLogCorrupted(format!(
+                "entry {} does not follow entry {}",
+                entry.seq, head.entries
+            )));
+        }
+        if hash_entry(&entry)? != entry.hash {
+            return Err(Error::AuditLogCorrupted(format!(
+                "entry {} was modified",
+                entry.seq
+            )));
+        }
+        let head = AuditHead {
+            entries: entry.seq,
+            hash: Some(entry.hash),
+        };
+        chain.before_last = std::mem::replace(&mut chain.head, head);
+        chain.len += read as u64;
+    }
+    Ok(chain)
+}
+
+/// Finishes an append to the log at `path` that a crash cut short, then verifies the log.
+///
+/// An append writes the entry, then the head file. A crash while writing the entry leaves
+/// part of it behind, which is cut off; a crash before the head file was written leaves
+/// the head one entry behind, which is moved up.
+fn recover(path: &Path) -> Result<AuditHead, Error> {
+    let chain = read_chain(path)?;
+    let recorded = read_head(path)?;
+    let behind = |head: &AuditHead| recorded.as_ref().is_none_or(|r| r.entries <= head.entries);
+    if chain.torn && behind(&chain.head) {
+        warn!(
+            "Cutting an incomplete entry off the end of the audit log {}",
+            path.display()
+        );
+        let file = OpenOptions::new().write(true).open(path)?;
+        file.set_len(chain.len)?;
+        file.sync_data()?;
+    }
+    if chain.head.entries > 0 && recorded.as_ref() == Some(&chain.before_last) {
+        warn!(
+            "The head file of the audit log {} is one entry behind, moving it up",
+            path.display()
+        );
+        write_head(path, &chain.head)?;
+    }
+    verify_audit_log(path)
+}
+
+/// Returns the action to audit for `req`, if it changes state.
+pub(crate) fn action_of(req: &Request) -> Option<AuditAction> {
+    match req {
+        Request::SendTransaction {
+            to,
+            amount,
+            idempotency_key,
+        } => Some(AuditAction::SendTransaction {
+            to: to.clone(),
+            amount: *amount,
+            idempotency_key: idempotency_key.clone(),
+        }),
+        Request::SendTransactionFrom {
+            from,
+            to,
+            amount,
+            idempotency_key,
+        } => Some(AuditAction::SendTransactionFrom {
+            from: from.clone(),
+            to: to.clone(),
+            amount: *amount,
+            idempotency_key: idempotency_key.clone(),
+        }),
+        _ => None,
+    }
+}
+
+/// Returns the outcome to audit for the result of a request.
+pub(crate) fn outcome_of(result: &Result<Response, Error>) -> AuditOutcome {
+    match result {
+        Ok(Response::SendTransaction { txid } | Response::SendTransactionFrom { txid, .. }) => {
+            AuditOutcome::Success {
+                txid: Some(txid.clone()),
+            }
+        }
+        Ok(_) => AuditOutcome::Success { txid: None },
+        Err(e) => AuditOutcome::Failure {
+            code: e.code(),
+            message: e.to_string(),
+        },
+    }
+}
+
+/// Hashes every field of `entry` but `hash`.
+fn hash_entry(entry: &AuditEntry) -> Result<String, Error> {
+    let unhashed = AuditEntry {
+        hash: String::new(),
+        ..entry.clone()
+    };
+    let bytes = serde_json::to_vec(&unhashed).map_err(io::Error::from)?;
+    Ok(hex::encode(digest::digest(&digest::SHA256, &bytes)))
+}
+
+fn read_entries(path: &Path) -> Result<impl Iterator<Item = io::Result<AuditEntry>>, Error> {
+    let reader = BufReader::new(File::open(path)?);
+    Ok(reader.lines().map(|line| {
+        serde_json::from_str(&line?).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }))
+}
+
+fn head_path(path: &Path) -> PathBuf {
+    let mut head = path.as_os_str().to_owned();
+    head.push(".head");
+    PathBuf::from(head)
+}
+
+fn read_head(path: &Path) -> Result<Option<AuditHead>, Error> {
+    match fs::read(head_path(path)) {
+        Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes).map_err(|e| {
+            Error::AuditLogCorrupted(format!("unreadable head file: {}", e))
+        })?)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Replaces the head file atomically, so a crash never leaves half of it behind.
+fn write_head(path: &Path, head: &AuditHead) -> Result<(), Error> {
+    let target = head_path(path);
+    let mut tmp = target.clone().into_os_string();
+    tmp.push(".tmp");
+    let mut file = File::create(&tmp)?;
+    file.write_all(&serde_json::to_vec(head).map_err(io::Error::from)?)?;
+    file.sync_data()?;
+    fs::rename(&tmp, &target)?;
+    Ok(())
+}
+
+/// Returns the current time in Unix milliseconds.
+pub(crate) fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn created(address: &str) -> AuditAction {
+        AuditAction::WalletCreated {
+            address: address.to_owned(),
+        }
+    }
+
+    fn ok() -> AuditOutcome {
+        AuditOutcome::Success { txid: None }
+    }
+
+    /// Opens a log in a fresh directory with `count` entries in it.
+    fn log_with(count: usize) -> (tempfile::TempDir, PathBuf, AuditLog) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.log");
+        let log = AuditLog::open(&path).unwrap();
+        for i in 0..count {
+            log.append(None, created(&i.to_string()), ok(), AuditLink::None)
+                .unwrap();
+        }
+        (dir, path, log)
+    }
+
+    #[test]
+    fn appends_form_a_chain() {
+        let (_dir, path, log) = log_with(0);
+        let intent = log
+            .append(
+                Some("ops"),
+                created("a"),
+                AuditOutcome::Pending,
+                AuditLink::None,
+            )
+            .unwrap();
+        let outcome = log
+            .append(
+                Some("ops"),
+                created("a"),
+                ok(),
+                AuditLink::OutcomeOf(intent.seq),
+            )
+            .unwrap();
+        assert_eq!(outcome.intent, Some(1));
+        assert_eq!(outcome.prev_hash, intent.hash);
+
+        let head = verify_audit_log(&path).unwrap();
+        assert_eq!(head.entries, 2);
+        assert_eq!(head.hash, Some(outcome.hash));
+        assert_eq!(log.query(None, None).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn modified_entry_is_detected() {
+        let (_dir, path, log) = log_with(2);
+        drop(log);
+        let text = fs::read_to_string(&path)
+            .unwrap()
+            .replacen("\"0\"", "\"x\"", 1);
+        fs::write(&path, text).unwrap();
+        assert!(matches!(
+            verify_audit_log(&path),
+            Err(Error::AuditLogCorrupted(_))
+        ));
+        assert!(AuditLog::open(&path).is_err());
+    }
+
+    #[test]
+    fn truncation_is_detected() {
+        let (_dir, path, log) = log_with(3);
+        drop(log);
+        let text = fs::read_to_string(&path).unwrap();
+        let kept: Vec<&str> = text.lines().take(1).collect();
+        fs::write(&path, format!("{}\n", kept.join("\n"))).unwrap();
+        assert!(verify_audit_log(&path).is_err());
+        assert!(AuditLog::open(&path).is_err());
+    }
+
+    #[test]
+    fn open_cuts_off_a_torn_entry() {
+        let (_dir, path, log) = log_with(2);
+        drop(log);
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"{\"seq\":3,\"times").unwrap();
+        drop(file);
+        assert!(verify_audit_log(&path).is_err());
+
+        let log = AuditLog::open(&path).unwrap();
+        assert_eq!(log.verify().unwrap().entries, 2);
+        let next = log
+            .append(None, created("c"), ok(), AuditLink::None)
+            .unwrap();
+        assert_eq!(next.seq, 3);
+        assert_eq!(verify_audit_log(&path).unwrap().entries, 3);
+    }
+
+    #[test]
+    fn open_moves_a_lagging_head_up() {
+        let (_dir, path, log) = log_with(1);
+        let before = log.verify().unwrap();
+        log.append(None, created("b"), ok(), AuditLink::None)
+            .unwrap();
+        drop(log);
+        // As if the engine crashed after writing the entry but before the head file.
+        write_head(&path, &before).unwrap();
+        assert!(verify_audit_log(&path).is_err());
+
+        let log = AuditLog::open(&path).unwrap();
+        assert_eq!(log.verify().unwrap().entries, 2);
+    }
+}
@@ -0,0 +1,411 @@
+use crate::Error;
+use ring::{
+    digest,
+    rand::{SecureRandom, SystemRandom},
+};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{collections::HashMap, fmt, str::FromStr};
+use subtle::ConstantTimeEq;
+use tokio_tungstenite::tungstenite::http::{HeaderMap, header::AUTHORIZATION};
+
+/// Scheme prefix of the `Authorization` header carrying an API key.
+pub const AUTHORIZATION_SCHEME: &str = "Bearer ";
+
+const HASH_PREFIX: &str = "sha256";
+const SALT_LEN: usize = 16;
+const SECRET_LEN: usize = 32;
+
+/// The authenticated caller of a connection.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Identity {
+    /// Id of the API key used, or `None` when authentication is disabled.
+    pub key_id: Option<String>,
+    /// What the caller is allowed to do.
+    pub scopes: Vec<Scope>,
+    /// The process on the other end, for connections over a Unix domain socket.
+    pub peer: Option<PeerCredentials>,
+}
+
+/// Credentials of the process connected to a Unix domain socket, as reported by the OS.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct PeerCredentials {
+    pub uid: u32,
+    pub gid: u32,
+    /// Not available on every platform.
+    pub pid: Option<i32>,
+}
+
+impl Identity {
+    /// The identity used for every connection when no key store is configured.
+    ///
+    /// It holds [`Scope::Admin`], so an engine without authentication behaves as before.
+    pub fn anonymous() -> Self {
+        Self {
+            key_id: None,
+            scopes: vec![Scope::Admin],
+            peer: None,
+        }
+    }
+
+    /// Creates the identity of an API key holding `scopes`.
+    pub fn new(key_id: impl Into<String>, scopes: Vec<Scope>) -> Self {
+        Self {
+            key_id: Some(key_id.into()),
+            scopes,
+            peer: None,
+        }
+    }
+
+    pub fn has_scope(&self, scope: &Scope) -> bool {
+        self.scopes.contains(&Scope::Admin) || self.scopes.contains(scope)
+    }
+
+    /// Returns the source addresses this caller may spend from through `spend-from` scopes.
+    pub fn spendable_from(&self) -> impl Iterator<Item = &str> {
+        self.scopes.iter().filter_map(|scope| match scope {
+            Scope::SpendFrom(address) => Some(address.as_str()),
+            _ => None,
+        })
+    }
+}
+
+/// A permission granted to an API key.
+///
+/// Scopes are written as `read`, `spend`, `spend-from:<address>` and `admin`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Scope {
+    /// Read balances, wallets and deposit addresses.
+    Read,
+    /// Send transactions from any wallet.
+    Spend,
+    /// Send transactions only from the given source address.
+    SpendFrom(String),
+    /// Everything, including operational requests such as `Sync`.
+    Admin,
+}
+
+impl FromStr for Scope {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "read" => Ok(Scope::Read),
+            "spend" => Ok(Scope::Spend),
+            "admin" => Ok(Scope::Admin),
+            _ => match s.strip_prefix("spend-from:") {
+                Some(address) if !address.is_empty() => Ok(Scope::SpendFrom(address.to_owned())),
+                _ => Err(Error::InvalidKey(format!("unknown scope {:?}", s))),
+            },
+        }
+    }
+}
+
+impl Serialize for Scope {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Scope {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Scope::Read => f.write_str("read"),
+            Scope::Spend => f.write_str("spend"),
+            Scope::SpendFrom(address) => write!(f, "spend-from:{}", address),
+            Scope::Admin => f.write_str("admin"),
+        }
+    }
+}
+
+/// A source of API keys that connections are authenticated against.
+pub trait KeyStore: Send + Sync {
+    /// Checks `secret` for the key `key_id`, returning the caller's identity if it matches.
+    fn authenticate(&self, key_id: &str, secret: &str) -> Option<Identity>;
+
+    /// Authenticates a Unix domain socket connection that presented no API key by the
+    /// credentials of its peer process.
+    ///
+    /// The default trusts no peer, so every connection needs a key.
+    fn authenticate_peer(&self, peer: &PeerCredentials) -> Option<Identity> {
+        let _ = peer;
+        None
+    }
+}
+
+/// An in-memory [`KeyStore`] holding salted SHA-256 hashes of key secrets.
+///
+/// Secrets are expected to be high-entropy random strings such as the ones produced by
+/// [`generate_secret`]; they are never stored in plain text.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryKeyStore {
+    keys: HashMap<String, StoredKey>,
+    /// Scopes granted to Unix domain socket peers by user id.
+    uids: HashMap<u32, Vec<Scope>>,
+}
+
+#[derive(Clone, Debug)]
+struct StoredKey {
+    secret: HashedSecret,
+    scopes: Vec<Scope>,
+}
+
+impl MemoryKeyStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a key from a hash produced by [`hash_secret`], granting it `scopes`.
+    pub fn insert(
+        &mut self,
+        key_id: impl Into<String>,
+        hash: &str,
+        scopes: Vec<Scope>,
+    ) -> Result<(), Error> {
+        let key_id = key_id.into();
+        if key_id.is_empty() || key_id.contains(':') {
+            return Err(Error::InvalidKey(format!("invalid key id {:?}", key_id)));
+        }
+        let secret = HashedSecret::parse(hash)
+            .ok_or_else(|| Error::InvalidKey(format!("malformed hash for key {:?}", key_id)))?;
+        self.keys.insert(key_id, StoredKey { secret, scopes });
+        Ok(())
+    }
+
+    /// Removes a key, returning whether it was present.
+    pub fn remove(&mut self, key_id: &str) -> bool {
+        self.keys.remove(key_id).is_some()
+    }
+
+    /// Grants `scopes` to processes of the user `uid` connecting over a Unix domain socket
+    /// without an API key.
+    ///
+    /// Their identity has the key id `uid:<uid>`.
+    pub fn insert_uid(&mut self, uid: u32, scopes: Vec<Scope>) {
+        self.uids.insert(uid, scopes);
+    }
+
+    /// Stops trusting the user `uid`, returning whether it was trusted.
+    pub fn remove_uid(&mut self, uid: u32) -> bool {
+        self.uids.remove(&uid).is_some()
+    }
+}
+
+impl KeyStore for MemoryKeyStore {
+    fn authenticate(&self, key_id: &str, secret: &str) -> Option<Identity> {
+        let stored = self.keys.get(key_id)?;
+        let hash = digest_secret(&stored.secret.salt, secret);
+        bool::from(hash.ct_eq(&stored.secret.hash))
+            .then(|| Identity::new(key_id, stored.scopes.clone()))
+    }
+
+    fn authenticate_peer(&self, peer: &PeerCredentials) -> Option<Identity> {
+        let scopes = self.uids.get(&peer.uid)?;
+        Some(Identity::new(format!("uid:{}", peer.uid), scopes.clone()))
+    }
+}
+
+#[derive(Clone, Debug)]
+struct HashedSecret {
+    salt: Vec<u8>,
+    hash: Vec<u8>,
+}
+
+impl HashedSecret {
+    fn parse(s: &str) -> Option<Self> {
+        let mut parts = s.split('$');
+        if parts.next()? != HASH_PREFIX {
+            return None;
+        }
+        let salt = hex::decode(parts.next()?).ok()?;
+        let hash = hex::decode(parts.next()?).ok()?;
+        if parts.next().is_some() || hash.len() != digest::SHA256_OUTPUT_LEN {
+            return None;
+        }
+        Some(Self { salt, hash })
+    }
+}
+
+/// Hashes a secret with a fresh random salt, in the form accepted by
+/// [`MemoryKeyStore::insert`]: `sha256$<salt hex>$<hash hex>`.
+pub fn hash_secret(secret: &str) -> Result<String, Error> {
+    let mut salt = [0u8; SALT_LEN];
+    SystemRandom::new()
+        .fill(&mut salt)
+        .map_err(|_| Error::InvalidKey("failed to generate salt".to_owned()))?;
+    let hash = digest_secret(&salt, secret);
+    Ok(format!(
+        "{}${}${}",
+        HASH_PREFIX,
+        hex::encode(salt),
+        hex::encode(hash)
+    ))
+}
+
+/// Generates a random secret suitable for a new API key.
+pub fn generate_secret() -> Result<String, Error> {
+    let mut secret = [0u8; SECRET_LEN];
+    SystemRandom::new()
+        .fill(&mut secret)
+        .map_err(|_| Error::InvalidKey("failed to generate secret".to_owned()))?;
+    Ok(hex::encode(secret))
+}
+
+/// Splits an `Authorization` header value into a key id and a secret.
+fn parse_authorization(value: &str) -> Option<(&str, &str)> {
+    value.strip_prefix(AUTHORIZATION_SCHEME)?.split_once(':')
+}
+
+fn digest_secret(salt: &[u8], secret: &str) -> Vec<u8> {
+    let mut ctx = digest::Context::new(&digest::SHA256);
+    ctx.update(salt);
+    ctx.update(secret.as_bytes());
+    ctx.finish().as_ref().to_vec()
+}
+
+/// Authenticates a connection from its upgrade request headers, or from the credentials
+/// of its peer process if it connected over a Unix domain socket without an API key.
+///
+/// Every caller is anonymous when no key store is configured.
+pub(crate) fn authenticate(
+    key_store: Option<&dyn KeyStore>,
+    headers: &HeaderMap,
+    peer: Option<PeerCredentials>,
+) -> Result<Identity, Error> {
+    let identity = match key_store {
+        None => Some(Identity::anonymous()),
+        Some(key_store) => match headers.get(AUTHORIZATION) {
+            Some(value) => value
+                .to_str()
+                .ok()
+                .and_then(parse_authorization)
+                .and_then(|(key_id, secret)| key_store.authenticate(key_id, secret)),
+            None => peer.and_then(|peer| key_store.authenticate_peer(&peer)),
+        },
+    };
+    let mut identity = identity.ok_or(Error::Unauthorized)?;
+    identity.peer = peer;
+    Ok(identity)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PEER: PeerCredentials = PeerCredentials {
+        uid: 1000,
+        gid: 1000,
+        pid: Some(42),
+    };
+
+    fn key_store() -> MemoryKeyStore {
+        let mut store = MemoryKeyStore::new();
+        let hash = hash_secret("s3cret").unwrap();
+        store.insert("ops", &hash, vec![Scope::Admin]).unwrap();
+        store.insert_uid(PEER.uid, vec![Scope::Read]);
+        store
+    }
+
+    fn authorization(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, value.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn keys_authenticate_with_their_secret_only() {
+        let store = key_store();
+        let store = Some(&store as &dyn KeyStore);
+        let identity = authenticate(store, &authorization("Bearer ops:s3cret"), None).unwrap();
+        assert_eq!(identity, Identity::new("ops", vec![Scope::Admin]));
+
+        for value in [
+            "Bearer ops:wrong",
+            "Bearer dev:s3cret",
+            "Bearer ops",
+            "ops:s3cret",
+            "Basic ops:s3cret",
+        ] {
+            assert!(
+                matches!(
+                    authenticate(store, &authorization(value), None),
+                    Err(Error::Unauthorized)
+                ),
+                "{}",
+                value
+            );
+        }
+        assert!(authenticate(None, &authorization("Bearer ops:wrong"), None).is_ok());
+    }
+
+    #[test]
+    fn peers_without_a_key_authenticate_by_uid() {
+        let store = key_store();
+        let store = Some(&store as &dyn KeyStore);
+        let identity = authenticate(store, &HeaderMap::new(), Some(PEER)).unwrap();
+        assert_eq!(identity.key_id.as_deref(), Some("uid:1000"));
+        assert_eq!(identity.scopes, vec![Scope::Read]);
+        assert_eq!(identity.peer, Some(PEER));
+
+        let stranger = PeerCredentials { uid: 0, ..PEER };
+        assert!(authenticate(store, &HeaderMap::new(), Some(stranger)).is_err());
+        assert!(authenticate(store, &HeaderMap::new(), None).is_err());
+        // A key that is presented must be valid, whoever the peer is.
+        let wrong = authorization("Bearer ops:wrong");
+        assert!(authenticate(store, &wrong, Some(PEER)).is_err());
+    }
+
+    #[test]
+    fn scopes_round_trip_through_strings() {
+        for scope in ["read", "spend", "spend-from:addr1", "admin"] {
+            assert_eq!(scope.parse::<Scope>().unwrap().to_string(), scope);
+        }
+        for invalid in ["", "write", "spend-from", "spend-from:", "Admin"] {
+            assert!(
+                matches!(invalid.parse::<Scope>(), Err(Error::InvalidKey(_))),
+                "{:?}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn malformed_hashes_are_rejected() {
+        let hash = hash_secret("s3cret").unwrap();
+        assert!(HashedSecret::parse(&hash).is_some());
+        let digest = "ab".repeat(digest::SHA256_OUTPUT_LEN);
+        for malformed in [
+            String::new(),
+            "sha256".to_owned(),
+            format!("md5$00${}", digest),
+            format!("sha256$zz${}", digest),
+            format!("sha256$00${}", &digest[2..]),
+            format!("sha256$00${}$00", digest),
+            "sha256$00".to_owned(),
+        ] {
+            assert!(HashedSecret::parse(&malformed).is_none(), "{:?}", malformed);
+            let mut store = MemoryKeyStore::new();
+            assert!(store.insert("ops", &malformed, vec![Scope::Read]).is_err());
+        }
+    }
+
+    #[test]
+    fn admin_holds_every_scope() {
+        let admin = Identity::new("ops", vec![Scope::Admin]);
+        let reader = Identity::new("dev", vec![Scope::Read, Scope::SpendFrom("addr1".into())]);
+        for scope in [Scope::Read, Scope::Spend, Scope::SpendFrom("addr1".into())] {
+            assert!(admin.has_scope(&scope));
+        }
+        assert!(reader.has_scope(&Scope::Read));
+        assert!(reader.has_scope(&Scope::SpendFrom("addr1".into())));
+        assert!(!reader.has_scope(&Scope::SpendFrom("addr2".into())));
+        assert!(!reader.has_scope(&Scope::Spend));
+        assert_eq!(reader.spendable_from().collect::<Vec<_>>(), ["addr1"]);
+    }
+}
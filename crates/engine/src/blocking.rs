@@ -0,0 +1,318 @@
+use aum_core::errors::{WalletError, WalletManagerError};
+use aum_core::prelude::{AsyncWallet, AsyncWalletManager, Wallet, WalletManager};
+use std::sync::Arc;
+use tokio::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use tracing::Span;
+
+type WalletOf<Wm> = <Wm as WalletManager>::Wallet;
+
+/// Lifts a synchronous [`WalletManager`] to [`AsyncWalletManager`], so that it can back a
+/// [`Runtime`](crate::Runtime).
+///
+/// Every call runs on tokio's blocking thread pool, so a backend doing blocking I/O doesn't
+/// stall the worker threads. The wallet manager is behind a fair read-write lock: `&self`
+/// methods share it, `&mut self` methods wait for exclusive access. Deleting wallets hands
+/// the backend a copy of the target wallets, so their type has to be `Clone`.
+pub struct BlockingWalletManager<Wm> {
+    inner: Arc<RwLock<Wm>>,
+}
+
+/// A handle to a wallet of a [`BlockingWalletManager`], looked up by address on every call.
+pub struct BlockingWallet<Wm: WalletManager> {
+    manager: Arc<RwLock<Wm>>,
+    address: <WalletOf<Wm> as Wallet>::Address,
+    pubkey: <WalletOf<Wm> as Wallet>::PublicKey,
+}
+
+impl<Wm> BlockingWalletManager<Wm> {
+    pub fn new(wallet_manager: Wm) -> Self {
+        Self {
+            inner: Arc::new(RwLock::new(wallet_manager)),
+        }
+    }
+    /// Locks the wallet manager for reading, for calls the async API doesn't offer.
+    pub async fn read(&self) -> RwLockReadGuard<'_, Wm> {
+        self.inner.read().await
+    }
+    /// Locks the wallet manager for a change.
+    ///
+    /// Nobody else can use the wallet manager until the guard is dropped, so it should not
+    /// be held across long waits.
+    pub async fn write(&self) -> RwLockWriteGuard<'_, Wm> {
+        self.inner.write().await
+    }
+}
+
+impl<Wm> Clone for BlockingWalletManager<Wm> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+}
+
+impl<Wm: WalletManager> Clone for BlockingWallet<Wm> {
+    fn clone(&self) -> Self {
+        Self {
+            manager: Arc::clone(&self.manager),
+            address: self.address.clone(),
+            pubkey: self.pubkey.clone(),
+        }
+    }
+}
+
+/// Runs `f` on the blocking thread pool, inside the caller's span.
+async fn run_blocking<T, E, F>(f: F) -> Result<T, E>
+where
+    T: Send + 'static,
+    E: From<Box<dyn std::error::Error + Send + Sync>> + Send + 'static,
+    F: FnOnce() -> Result<T, E> + Send + 'static,
+{
+    let span = Span::current();
+    match tokio::task::spawn_blocking(move || span.in_scope(f)).await {
+        Ok(result) => result,
+        Err(e) => match e.try_into_panic() {
+            // Surface a panicking backend the same way a direct call would.
+            Ok(panic) => std::panic::resume_unwind(panic),
+            // The runtime is shutting down and dropped the call before it ran.
+            Err(e) => Err(E::from(Box::new(e))),
+        },
+    }
+}
+
+/// Finds the wallets at the addresses of `handles`, copied so they outlive the lock.
+fn targets<Wm>(
+    scale: &Wm,
+    handles: &[BlockingWallet<Wm>],
+) -> Result<Vec<WalletOf<Wm>>, WalletManagerError>
+where
+    Wm: WalletManager,
+    WalletOf<Wm>: Clone,
+{
+    let wallets = scale.list_wallets()?;
+    handles
+        .iter()
+        .map(|target| {
+            wallets
+                .iter()
+                .find(|wallet| wallet.address() == &target.address)
+                .map(|&wallet| wallet.clone())
+                .ok_or_else(|| WalletError::InvalidAddress.into())
+        })
+        .collect()
+}
+
+fn handle<Wm>(manager: &Arc<RwLock<Wm>>, wallet: &WalletOf<Wm>) -> BlockingWallet<Wm>
+where
+    Wm: WalletManager,
+{
+    BlockingWallet {
+        manager: Arc::clone(manager),
+        address: wallet.address().clone(),
+        pubkey: wallet.pubkey().clone(),
+    }
+}
+
+#[async_trait::async_trait]
+impl<Wm> AsyncWalletManager for BlockingWalletManager<Wm>
+where
+    Wm: WalletManager + Send + Sync + 'static,
+    WalletOf<Wm>: Clone,
+{
+    type Wallet = BlockingWallet<Wm>;
+    type Address = Wm::Address;
+    type TransactionId = Wm::TransactionId;
+
+    async fn create_wallet(&self) -> Result<Self::Wallet, WalletManagerError> {
+        let manager = Arc::clone(&self.inner);
+        run_blocking(move || {
+            let mut scale = manager.blocking_write();
+            Ok(handle(&manager, scale.create_wallet()))
+        })
+        .await
+    }
+
+    async fn delete_and_transfer(
+        &self,
+        target_wallet: &Self::Wallet,
+    ) -> Result<Self::Wallet, WalletManagerError> {
+        let manager = Arc::clone(&self.inner);
+        let target = target_wallet.clone();
+        run_blocking(move || {
+            let mut scale = manager.blocking_write();
+            let target = targets(&*scale, std::slice::from_ref(&target))?.remove(0);
+            Ok(handle(&manager, scale.delete_and_transfer(&target)?))
+        })
+        .await
+    }
+
+    async fn delete_and_distribute(
+        &self,
+        target_wallets: &[Self::Wallet],
+    ) -> Result<Vec<Self::Wallet>, WalletManagerError> {
+        let manager = Arc::clone(&self.inner);
+        let handles = target_wallets.to_vec();
+        run_blocking(move || {
+            let mut scale = manager.blocking_write();
+            let targets = targets(&*scale, &handles)?;
+            Ok(scale
+                .delete_and_distribute(&targets)?
+                .into_iter()
+                .map(|wallet| handle(&manager, wallet))
+                .collect())
+        })
+        .await
+    }
+
+    async fn scale_to(&self, count: u64) -> Result<Vec<Self::Wallet>, WalletManagerError> {
+        let manager = Arc::clone(&self.inner);
+        run_blocking(move || {
+            let mut scale = manager.blocking_write();
+            let wallets = scale.scale_to(count)?;
+            Ok(wallets
+                .into_iter()
+                .map(|wallet| handle(&manager, wallet))
+                .collect())
+        })
+        .await
+    }
+
+    async fn retrieve_address(&self) -> Result<Self::Address, WalletManagerError> {
+        let manager = Arc::clone(&self.inner);
+        run_blocking(move || manager.blocking_read().retrieve_address()).await
+    }
+
+    async fn send_transaction(
+        &self,
+        to: &Self::Address,
+        amount: u64,
+    ) -> Result<Self::TransactionId, WalletManagerError> {
+        let manager = Arc::clone(&self.inner);
+        let to = to.clone();
+        run_blocking(move || manager.blocking_read().send_transaction(&to, amount)).await
+    }
+
+    async fn send_transaction_from(
+        &self,
+        from: &Self::Address,
+        to: &Self::Address,
+        amount: u64,
+    ) -> Result<Self::TransactionId, WalletManagerError> {
+        let manager = Arc::clone(&self.inner);
+        let (from, to) = (from.clone(), to.clone());
+        run_blocking(move || {
+            manager
+                .blocking_read()
+                .send_transaction_from(&from, &to, amount)
+        })
+        .await
+    }
+
+    async fn list_wallets(&self) -> Result<Vec<Self::Wallet>, WalletManagerError> {
+        let manager = Arc::clone(&self.inner);
+        run_blocking(move || {
+            let scale = manager.blocking_read();
+            Ok(scale
+                .list_wallets()?
+                .into_iter()
+                .map(|wallet| handle(&manager, wallet))
+                .collect())
+        })
+        .await
+    }
+
+    async fn retrieve_balance(&self, address: &Self::Address) -> Result<u64, WalletManagerError> {
+        let manager = Arc::clone(&self.inner);
+        let address = address.clone();
+        run_blocking(move || manager.blocking_read().retrieve_balance(&address)).await
+    }
+
+    async fn retrieve_balances(&self) -> Result<Vec<(Self::Address, u64)>, WalletManagerError> {
+        let manager = Arc::clone(&self.inner);
+        run_blocking(move || manager.blocking_read().retrieve_balances()).await
+    }
+}
+
+impl<Wm> BlockingWallet<Wm>
+where
+    Wm: WalletManager + Send + Sync + 'static,
+{
+    /// Runs `f` on the wallet with this handle's address, on the blocking thread pool.
+    async fn with_wallet<T, F>(&self, f: F) -> Result<T, WalletError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&WalletOf<Wm>) -> Result<T, WalletError> + Send + 'static,
+    {
+        let manager = Arc::clone(&self.manager);
+        let address = self.address.clone();
+        run_blocking(move || {
+            let scale = manager.blocking_read();
+            let wallets = scale.list_wallets().map_err(|e| match e {
+                WalletManagerError::WalletError(e) => e,
+                WalletManagerError::Custom(e) => WalletError::Custom(e),
+            })?;
+            let wallet = wallets
+                .into_iter()
+                .find(|wallet| wallet.address() == &address)
+                .ok_or(WalletError::InvalidAddress)?;
+            f(wallet)
+        })
+        .await
+    }
+}
+
+#[async_trait::async_trait]
+impl<Wm> AsyncWallet for BlockingWallet<Wm>
+where
+    Wm: WalletManager + Send + Sync + 'static,
+{
+    type Transaction = <WalletOf<Wm> as Wallet>::Transaction;
+    type SignedTransaction = <WalletOf<Wm> as Wallet>::SignedTransaction;
+    type Address = <WalletOf<Wm> as Wallet>::Address;
+    type PublicKey = <WalletOf<Wm> as Wallet>::PublicKey;
+
+    fn address(&self) -> &Self::Address {
+        &self.address
+    }
+
+    fn pubkey(&self) -> &Self::PublicKey {
+        &self.pubkey
+    }
+
+    async fn balance(&self) -> Result<u64, WalletError> {
+        self.with_wallet(|wallet| Ok(wallet.balance())).await
+    }
+
+    async fn sign_transaction(
+        &self,
+        transaction: &Self::Transaction,
+    ) -> Result<Self::SignedTransaction, WalletError> {
+        let transaction = transaction.clone();
+        self.with_wallet(move |wallet| wallet.sign_transaction(&transaction))
+            .await
+    }
+
+    async fn verify_transaction_signature(
+        &self,
+        signed_transaction: &Self::SignedTransaction,
+    ) -> Result<bool, WalletError> {
+        let signed_transaction = signed_transaction.clone();
+        self.with_wallet(move |wallet| wallet.verify_transaction_signature(&signed_transaction))
+            .await
+    }
+
+    async fn transfer_funds(
+        &self,
+        to: &Self::Address,
+        amount: u64,
+    ) -> Result<Self::Transaction, WalletError> {
+        let to = to.clone();
+        self.with_wallet(move |wallet| wallet.transfer_funds(&to, amount))
+            .await
+    }
+
+    async fn transaction_history(&self) -> Result<Vec<Self::Transaction>, WalletError> {
+        self.with_wallet(|wallet| Ok(wallet.transaction_history()))
+            .await
+    }
+}
@@ -0,0 +1,697 @@
+use crate::{
+    ConnectionLimits, EngineOptions, Error, JobOptions, KeyStore, MemoryKeyStore, RateLimits,
+    Scope, SupervisorOptions, TlsConfig, transport::UNIX_PREFIX,
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, path::Path, path::PathBuf, sync::Arc, time::Duration};
+use toml::{Table, Value};
+use tracing::level_filters::LevelFilter;
+
+/// Prefix of the environment variables that override config values, see
+/// [`EngineConfig::load`].
+pub const ENV_PREFIX: &str = "AUM_";
+/// Separates the keys of an environment override, e.g. `AUM_LISTEN__BIND`.
+const ENV_SEPARATOR: &str = "__";
+/// Stands in for secrets in [`EngineConfig::redacted`].
+const REDACTED: &str = "<redacted>";
+
+/// Settings of an engine, usually loaded from a TOML file with [`EngineConfig::load`].
+///
+/// Every section and key is optional and defaults to the same as [`EngineOptions`].
+/// Durations are written like `30s`, `5m` or `1h30m`, schedules like `@every 5m` or as
+/// cron expressions, see [`Schedule`](crate::Schedule).
+///
+/// ```toml
+/// [listen]
+/// bind = "0.0.0.0:9000"
+/// http = "unix:/run/aum/rest.sock"
+/// metrics = "127.0.0.1:9100"
+///
+/// [tls]
+/// cert_path = "/etc/aum/cert.pem"
+/// key_path = "/etc/aum/key.pem"
+///
+/// [[auth.keys]]
+/// id = "billing"
+/// hash = "sha256$…$…"
+/// scopes = ["read", "spend"]
+///
+/// [limits]
+/// max_connections = 1000
+/// idle_timeout = "5m"
+///
+/// [rate_limits.per_key]
+/// spend = { per_second = 1.0, burst = 5 }
+///
+/// [scheduler.jobs]
+/// sync = "@every 5m"
+/// balance_snapshot = "0 * * * *"
+///
+/// [policies]
+/// audit_log = "/var/lib/aum/audit.log"
+///
+/// [logging]
+/// level = "debug"
+/// ```
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EngineConfig {
+    pub listen: ListenConfig,
+    /// Serve `wss://` and `https://` with this certificate.
+    pub tls: Option<TlsConfig>,
+    pub auth: AuthConfig,
+    /// Resource limits of the WebSocket server, partly also applied to the other listeners.
+    pub limits: ConnectionLimits,
+    pub rate_limits: RateLimits,
+    pub scheduler: SchedulerConfig,
+    /// How the monitor is started and restarted.
+    pub supervisor: SupervisorOptions,
+    pub policies: PoliciesConfig,
+    pub logging: LoggingConfig,
+    /// Environment variables with the `AUM_` prefix that [`EngineConfig::load`] ignored
+    /// because they name no config key, e.g. ones meant for another program.
+    #[serde(skip)]
+    pub ignored_env: Vec<String>,
+}
+
+/// Addresses the engine listens on.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ListenConfig {
+    /// Address of the WebSocket server, e.g. `127.0.0.1:9000` or `unix:/run/aum/engine.sock`.
+    pub bind: String,
+    /// Address of the REST gateway, if any.
+    pub http: Option<String>,
+    /// Address of the Prometheus metrics listener, if any.
+    pub metrics: Option<String>,
+    /// File permissions of Unix domain sockets, e.g. `0o660`.
+    pub unix_socket_mode: u32,
+    /// How long in-flight requests may keep running after shutdown is triggered.
+    #[serde(with = "duration")]
+    pub shutdown_timeout: Duration,
+}
+
+/// API keys and trusted Unix domain socket peers. With neither, connections are not
+/// authenticated.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    pub keys: Vec<KeyConfig>,
+    pub peers: Vec<PeerConfig>,
+}
+
+/// An API key, see [`MemoryKeyStore::insert`].
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct KeyConfig {
+    pub id: String,
+    /// Hash of the key's secret, as produced by [`hash_secret`](crate::hash_secret).
+    pub hash: String,
+    pub scopes: Vec<Scope>,
+}
+
+/// Processes of a user connecting over a Unix domain socket, see
+/// [`MemoryKeyStore::insert_uid`].
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct PeerConfig {
+    pub uid: u32,
+    pub scopes: Vec<Scope>,
+}
+
+/// Recurring work of the engine.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SchedulerConfig {
+    /// How often wallets are rescanned for balance and wallet events.
+    #[serde(with = "duration")]
+    pub event_poll_interval: Duration,
+    pub jobs: JobOptions,
+}
+
+/// How requests are handled.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PoliciesConfig {
+    /// Name of the network reported to clients.
+    pub network: Option<String>,
+    /// How long the outcome of a spend request is remembered by its idempotency key after
+    /// the request completed.
+    #[serde(with = "duration")]
+    pub idempotency_window: Duration,
+    /// Path of the hash-chained audit log, if any.
+    pub audit_log: Option<PathBuf>,
+}
+
+/// How the engine logs, applied by [`LoggingConfig::install`].
+///
+/// [`Engine::start_with_config`](crate::Engine::start_with_config) installs it unless the
+/// application installed a `tracing` subscriber of its own first.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    /// Most verbose level to log: `off`, `error`, `warn`, `info`, `debug` or `trace`.
+    pub level: String,
+    pub format: LogFormat,
+    /// Also export spans to the OpenTelemetry collector at this address, e.g.
+    /// `http://127.0.0.1:4317`. Needs the `otlp` feature.
+    pub otlp_endpoint: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl Default for ListenConfig {
+    fn default() -> Self {
+        let options = EngineOptions::default();
+        Self {
+            bind: "127.0.0.1:9000".to_owned(),
+            http: options.http_bind,
+            metrics: options.metrics_bind,
+            unix_socket_mode: options.unix_socket_mode,
+            shutdown_timeout: options.shutdown_timeout,
+        }
+    }
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        let options = EngineOptions::default();
+        Self {
+            event_poll_interval: options.event_poll_interval,
+            jobs: options.jobs,
+        }
+    }
+}
+
+impl Default for PoliciesConfig {
+    fn default() -> Self {
+        let options = EngineOptions::default();
+        Self {
+            network: options.network,
+            idempotency_window: options.idempotency_window,
+            audit_log: options.audit_log,
+        }
+    }
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            level: "info".to_owned(),
+            format: LogFormat::default(),
+            otlp_endpoint: None,
+        }
+    }
+}
+
+impl LoggingConfig {
+    /// Returns the configured level, or `INFO` if it is not a valid one.
+    pub fn level_filter(&self) -> LevelFilter {
+        self.level.parse().unwrap_or(LevelFilter::INFO)
+    }
+}
+
+impl EngineConfig {
+    /// Reads the TOML file at `path`, applies environment overrides and validates the
+    /// result.
+    ///
+    /// An environment variable `AUM_<SECTION>__<KEY>` overrides `key` in `[section]`, e.g.
+    /// `AUM_LISTEN__BIND=0.0.0.0:9000` or `AUM_SCHEDULER__JOBS__SYNC="@every 1m"`. Values
+    /// are read as TOML values, falling back to plain strings, so `AUM_LIMITS__MAX_CONNECTIONS=100`
+    /// sets a number and `AUM_LIMITS__IDLE_TIMEOUT=5m` a string. Variables naming a key the
+    /// config doesn't have are ignored and listed in [`EngineConfig::ignored_env`].
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let toml = std::fs::read_to_string(path)
+            .map_err(|e| Error::InvalidConfig(format!("{}: {}", path.display(), e)))?;
+        Self::from_toml(&toml, std::env::vars()).map_err(|e| match e {
+            Error::InvalidConfig(e) => Error::InvalidConfig(format!("{}: {}", path.display(), e)),
+            e => e,
+        })
+    }
+
+    /// Parses `toml`, applies the overrides among the environment variables `env` and
+    /// validates the result, see [`EngineConfig::load`].
+    pub fn from_toml(
+        toml: &str,
+        env: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<Self, Error> {
+        let document: Table = toml
+            .parse()
+            .map_err(|e: toml::de::Error| Error::InvalidConfig(e.to_string()))?;
+        let mut overrides: Vec<EnvOverride> = env
+            .into_iter()
+            .filter_map(|(name, value)| EnvOverride::new(name, value))
+            .collect();
+        let mut ignored = Vec::new();
+        loop {
+            let mut merged = document.clone();
+            for o in &overrides {
+                override_value(&mut merged, &o.keys, o.value.clone())
+                    .map_err(|e| Error::InvalidConfig(format!("{}: {}", o.name, e)))?;
+            }
+            let e = match serde_path_to_error::deserialize(Value::Table(merged)) {
+                Ok(config) => {
+                    let config = Self {
+                        ignored_env: ignored,
+                        ..config
+                    };
+                    config.validate()?;
+                    return Ok(config);
+                }
+                Err(e) => e,
+            };
+            // Other programs may use the same prefix, so an override of a key the config
+            // doesn't have is dropped rather than failing the whole config.
+            let path = e.path().to_string();
+            let unknown = e.inner().to_string().starts_with("unknown field");
+            match overrides.iter().position(|o| unknown && o.overrides(&path)) {
+                Some(i) => ignored.push(overrides.remove(i).name),
+                None => {
+                    return Err(Error::InvalidConfig(format!("{}: {}", e.path(), e.inner())));
+                }
+            }
+        }
+    }
+
+    /// Checks the config for values the engine would reject or misbehave with, reporting
+    /// every problem found.
+    pub fn validate(&self) -> Result<(), Error> {
+        let mut problems = Vec::new();
+        let mut problem =
+            |key: &str, message: String| problems.push(format!("{}: {}", key, message));
+
+        let listen = &self.listen;
+        let binds = [
+            ("listen.bind", Some(&listen.bind)),
+            ("listen.http", listen.http.as_ref()),
+            ("listen.metrics", listen.metrics.as_ref()),
+        ];
+        // Port 0 picks a free port, so only fixed ports can clash.
+        let mut bound = HashSet::new();
+        for (key, bind) in binds {
+            let Some(bind) = bind else { continue };
+            if let Err(e) = check_bind(bind) {
+                problem(key, e);
+            } else if !bind.ends_with(":0") && !bound.insert(bind) {
+                problem(
+                    key,
+                    format!("{:?} is already used by another listener", bind),
+                );
+            }
+        }
+        if listen.unix_socket_mode > 0o777 {
+            problem(
+                "listen.unix_socket_mode",
+                format!("{:#o} is not a file mode", listen.unix_socket_mode),
+            );
+        }
+
+        if let Some(tls) = &self.tls {
+            for (key, path) in [
+                ("tls.cert_path", &tls.cert_path),
+                ("tls.key_path", &tls.key_path),
+            ] {
+                if !path.is_file() {
+                    problem(key, format!("{} is not a readable file", path.display()));
+                }
+            }
+        }
+
+        let mut ids = HashSet::new();
+        for (i, key) in self.auth.keys.iter().enumerate() {
+            let name = format!("auth.keys[{}]", i);
+            if !ids.insert(&key.id) {
+                problem(&name, format!("key id {:?} is used twice", key.id));
+            }
+            if key.scopes.is_empty() {
+                problem(&name, "grants no scopes".to_owned());
+            }
+            if let Err(e) = MemoryKeyStore::new().insert(&key.id, &key.hash, Vec::new()) {
+                problem(&name, e.to_string());
+            }
+        }
+        for (i, peer) in self.auth.peers.iter().enumerate() {
+            if peer.scopes.is_empty() {
+                problem(&format!("auth.peers[{}]", i), "grants no scopes".to_owned());
+            }
+        }
+
+        for (key, message) in self.options_with(None).problems() {
+            problem(key, message);
+        }
+
+        if let Some(audit_log) = &self.policies.audit_log {
+            let directory = audit_log.parent().filter(|dir| !dir.as_os_str().is_empty());
+            if directory.is_some_and(|dir| !dir.is_dir()) {
+                problem(
+                    "policies.audit_log",
+                    format!("the directory of {} does not exist", audit_log.display()),
+                );
+            }
+        }
+
+        if self.logging.level.parse::<LevelFilter>().is_err() {
+            problem(
+                "logging.level",
+                format!(
+                    "{:?} is not one of off, error, warn, info, debug or trace",
+                    self.logging.level
+                ),
+            );
+        }
+        if cfg!(not(feature = "otlp")) && self.logging.otlp_endpoint.is_some() {
+            problem(
+                "logging.otlp_endpoint",
+                "needs aum-engine built with the `otlp` feature".to_owned(),
+            );
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::InvalidConfig(problems.join("; ")))
+        }
+    }
+
+    /// Builds the options to start an engine with on `listen.bind`, see
+    /// [`Engine::start_with_config`](crate::Engine::start_with_config).
+    pub fn engine_options(&self) -> Result<EngineOptions, Error> {
+        let key_store = if self.auth.keys.is_empty() && self.auth.peers.is_empty() {
+            None
+        } else {
+            let mut store = MemoryKeyStore::new();
+            for key in &self.auth.keys {
+                store.insert(&key.id, &key.hash, key.scopes.clone())?;
+            }
+            for peer in &self.auth.peers {
+                store.insert_uid(peer.uid, peer.scopes.clone());
+            }
+            Some(Arc::new(store) as Arc<dyn KeyStore>)
+        };
+        Ok(self.options_with(key_store))
+    }
+
+    fn options_with(&self, key_store: Option<Arc<dyn KeyStore>>) -> EngineOptions {
+        EngineOptions {
+            shutdown_timeout: self.listen.shutdown_timeout,
+            tls: self.tls.clone(),
+            key_store,
+            event_poll_interval: self.scheduler.event_poll_interval,
+            http_bind: self.listen.http.clone(),
+            metrics_bind: self.listen.metrics.clone(),
+            unix_socket_mode: self.listen.unix_socket_mode,
+            rate_limits: self.rate_limits,
+            connection_limits: self.limits,
+            network: self.policies.network.clone(),
+            idempotency_window: self.policies.idempotency_window,
+            audit_log: self.policies.audit_log.clone(),
+            monitor_supervisor: self.supervisor,
+            jobs: self.scheduler.jobs.clone(),
+        }
+    }
+
+    /// Returns the effective config as TOML, with the hashes of API keys redacted, e.g. to
+    /// log at startup.
+    pub fn redacted(&self) -> String {
+        let mut config = self.clone();
+        for key in &mut config.auth.keys {
+            key.hash = REDACTED.to_owned();
+        }
+        let toml = toml::to_string(&config).expect("the config always serializes to TOML");
+        // File modes read best in octal, which TOML has literals for.
+        let mode = self.listen.unix_socket_mode;
+        toml.replacen(
+            &format!("unix_socket_mode = {}\n", mode),
+            &format!("unix_socket_mode = {:#o}\n", mode),
+            1,
+        )
+    }
+}
+
+/// An `AUM_<SECTION>__<KEY>` environment variable.
+struct EnvOverride {
+    name: String,
+    keys: Vec<String>,
+    value: Value,
+}
+
+impl EnvOverride {
+    fn new(name: String, value: String) -> Option<Self> {
+        let path = name.strip_prefix(ENV_PREFIX)?;
+        if !path.contains(ENV_SEPARATOR) {
+            return None;
+        }
+        let keys = path
+            .split(ENV_SEPARATOR)
+            .map(str::to_ascii_lowercase)
+            .collect();
+        let value = value.parse().unwrap_or(Value::String(value));
+        Some(Self { name, keys, value })
+    }
+
+    /// Returns whether the key at the dotted `path` is this override's or one of its tables.
+    fn overrides(&self, path: &str) -> bool {
+        let path: Vec<&str> = path.split('.').collect();
+        self.keys.len() >= path.len() && self.keys.iter().zip(&path).all(|(key, part)| key == part)
+    }
+}
+
+/// Sets the value at `keys` in `document`, creating tables on the way.
+fn override_value(document: &mut Table, keys: &[String], value: Value) -> Result<(), String> {
+    let (last, parents) = keys.split_last().expect("split always yields a key");
+    let mut table = document;
+    for key in parents {
+        let child = table
+            .entry(key.clone())
+            .or_insert_with(|| Value::Table(Table::new()));
+        table = child
+            .as_table_mut()
+            .ok_or_else(|| format!("`{}` is not a table", key))?;
+    }
+    table.insert(last.clone(), value);
+    Ok(())
+}
+
+/// Checks that `bind` is a `host:port` address or a `unix:` socket path.
+fn check_bind(bind: &str) -> Result<(), String> {
+    if let Some(path) = bind.strip_prefix(UNIX_PREFIX) {
+        return if path.is_empty() {
+            Err("the Unix domain socket path is empty".to_owned())
+        } else {
+            Ok(())
+        };
+    }
+    match bind.rsplit_once(':') {
+        Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => Ok(()),
+        _ => Err(format!(
+            "{:?} is neither host:port nor a unix: socket path",
+            bind
+        )),
+    }
+}
+
+/// (De)serializes durations as strings such as `30s` or `1h30m`.
+pub(crate) mod duration {
+    use crate::scheduler::{format_duration, parse_duration};
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&format_duration(*duration))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        parse_duration(&s).ok_or_else(|| {
+            serde::de::Error::custom(format!("{:?} is not a duration such as 30s or 1h30m", s))
+        })
+    }
+
+    pub(crate) mod option {
+        use serde::{Deserialize, Deserializer, Serializer};
+        use std::time::Duration;
+
+        pub fn serialize<S: Serializer>(
+            duration: &Option<Duration>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            match duration {
+                Some(duration) => super::serialize(duration, serializer),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Option<Duration>, D::Error> {
+            #[derive(Deserialize)]
+            struct Wrapper(#[serde(with = "super")] Duration);
+            Ok(Option::<Wrapper>::deserialize(deserializer)?.map(|Wrapper(duration)| duration))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RateLimit;
+
+    const CONFIG: &str = r#"
+[listen]
+bind = "127.0.0.1:9000"
+http = "127.0.0.1:8080"
+unix_socket_mode = 0o600
+
+[[auth.keys]]
+id = "billing"
+hash = "HASH"
+scopes = ["read", "spend"]
+
+[limits]
+max_connections = 100
+idle_timeout = "5m"
+
+[rate_limits.per_key]
+spend = { per_second = 1.0, burst = 5 }
+
+[scheduler.jobs]
+sync = "@every 5m"
+
+[logging]
+level = "debug"
+format = "json"
+"#;
+
+    fn config(env: &[(&str, &str)]) -> Result<EngineConfig, Error> {
+        let hash = crate::hash_secret("secret").unwrap();
+        let env = env
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()));
+        EngineConfig::from_toml(&CONFIG.replace("HASH", &hash), env)
+    }
+
+    fn invalid(result: Result<EngineConfig, Error>) -> String {
+        match result {
+            Err(Error::InvalidConfig(e)) => e,
+            Err(e) => panic!("unexpected error {}", e),
+            Ok(_) => panic!("the config was accepted"),
+        }
+    }
+
+    #[test]
+    fn file_is_read_into_every_section() {
+        let config = config(&[]).unwrap();
+        assert_eq!(config.listen.http.as_deref(), Some("127.0.0.1:8080"));
+        assert_eq!(config.listen.unix_socket_mode, 0o600);
+        assert_eq!(config.auth.keys[0].scopes, vec![Scope::Read, Scope::Spend]);
+        assert_eq!(config.limits.max_connections, Some(100));
+        assert_eq!(config.limits.idle_timeout, Some(Duration::from_secs(300)));
+        assert_eq!(
+            config.rate_limits.per_key.spend,
+            Some(RateLimit::new(1.0, 5))
+        );
+        assert_eq!(config.scheduler.jobs.sync.unwrap().to_string(), "@every 5m");
+        assert_eq!(config.logging.format, LogFormat::Json);
+        // Unset keys keep their defaults.
+        assert_eq!(config.limits.max_in_flight, 64);
+        assert_eq!(config.listen.shutdown_timeout, Duration::from_secs(30));
+    }
+
+    #[test]
+    fn env_overrides_replace_and_add_keys() {
+        let config = config(&[
+            ("AUM_LISTEN__BIND", "0.0.0.0:9100"),
+            ("AUM_LIMITS__MAX_CONNECTIONS", "7"),
+            ("AUM_LIMITS__HANDSHAKE_TIMEOUT", "3s"),
+            ("AUM_POLICIES__NETWORK", "testnet"),
+            ("AUM_SCHEDULER__JOBS__BALANCE_SNAPSHOT", "@daily"),
+            ("PATH", "/usr/bin"),
+            ("AUM_API_KEY", "not a config key"),
+        ])
+        .unwrap();
+        assert_eq!(config.listen.bind, "0.0.0.0:9100");
+        assert_eq!(config.limits.max_connections, Some(7));
+        assert_eq!(config.limits.handshake_timeout, Duration::from_secs(3));
+        assert_eq!(config.policies.network.as_deref(), Some("testnet"));
+        assert!(config.scheduler.jobs.balance_snapshot.is_some());
+        assert!(config.ignored_env.is_empty());
+    }
+
+    #[test]
+    fn unknown_env_overrides_are_ignored() {
+        let config = config(&[
+            ("AUM_CLIENT__URL", "ws://example"),
+            ("AUM_LIMITS__MAX_CONECTIONS", "7"),
+            ("AUM_LIMITS__MAX_IN_FLIGHT", "8"),
+        ])
+        .unwrap();
+        let mut ignored = config.ignored_env.clone();
+        ignored.sort();
+        assert_eq!(ignored, ["AUM_CLIENT__URL", "AUM_LIMITS__MAX_CONECTIONS"]);
+        assert_eq!(config.limits.max_connections, Some(100));
+        assert_eq!(config.limits.max_in_flight, 8);
+    }
+
+    #[test]
+    fn bad_env_values_are_errors() {
+        let e = invalid(config(&[("AUM_LIMITS__MAX_CONNECTIONS", "many")]));
+        assert!(e.starts_with("limits.max_connections"), "{}", e);
+        let e = invalid(config(&[("AUM_LISTEN__BIND__PORT", "1")]));
+        assert!(e.starts_with("AUM_LISTEN__BIND__PORT"), "{}", e);
+    }
+
+    #[test]
+    fn unknown_keys_in_the_file_are_errors() {
+        let e = invalid(EngineConfig::from_toml(
+            "[limits]\nmax_conections = 1\n",
+            [],
+        ));
+        assert!(e.contains("max_conections"), "{}", e);
+        let e = invalid(EngineConfig::from_toml("[listen\n", []));
+        assert!(e.contains("line 1"), "{}", e);
+    }
+
+    #[test]
+    fn every_problem_is_reported() {
+        let toml = r#"
+[listen]
+bind = "nowhere"
+unix_socket_mode = 0o1777
+
+[limits]
+max_in_flight = 0
+
+[logging]
+level = "loud"
+"#;
+        let e = invalid(EngineConfig::from_toml(toml, []));
+        for key in [
+            "listen.bind",
+            "listen.unix_socket_mode",
+            "limits.max_in_flight",
+            "logging.level",
+        ] {
+            assert!(e.contains(key), "{} in {}", key, e);
+        }
+    }
+
+    #[test]
+    fn redacted_dump_hides_secrets_and_reads_back() {
+        let config = config(&[]).unwrap();
+        let dump = config.redacted();
+        assert!(!dump.contains(&config.auth.keys[0].hash), "{}", dump);
+        assert!(dump.contains(REDACTED));
+        assert!(dump.contains("unix_socket_mode = 0o600\n"), "{}", dump);
+
+        let dump = dump.replace(REDACTED, &config.auth.keys[0].hash);
+        let read_back = EngineConfig::from_toml(&dump, []).unwrap();
+        assert_eq!(read_back.redacted(), config.redacted());
+    }
+}
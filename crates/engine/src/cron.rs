@@ -0,0 +1,329 @@
+/// A five-field cron expression, `minute hour day-of-month month day-of-week`, in UTC.
+///
+/// Fields take `*`, numbers, ranges such as `1-5`, steps such as `*/15` or `0-30/10` and
+/// comma-separated lists of those. Days of the week run from 0 (Sunday) to 6, and 7 is
+/// Sunday as well. As in cron, a day matches if either day field does when both are
+/// restricted.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct CronSchedule {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+const MINUTE_MS: u64 = 60 * 1000;
+const DAY_MINUTES: u64 = 24 * 60;
+/// How far ahead to look for a match; `0 0 29 2 *` may skip up to eight years.
+const SEARCH_DAYS: u64 = 8 * 366;
+
+impl CronSchedule {
+    pub(crate) fn parse(expression: &str) -> Result<Self, String> {
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err(format!(
+                "expected 5 fields in {:?}, found {}",
+                expression,
+                fields.len()
+            ));
+        };
+        let mut weekdays = parse_field(weekday, 0, 7)?;
+        // 7 is another name for Sunday.
+        if weekdays & 1 << 7 != 0 {
+            weekdays = (weekdays | 1) & !(1 << 7);
+        }
+        let schedule = Self {
+            minutes: parse_field(minute, 0, 59)?,
+            hours: parse_field(hour, 0, 23)?,
+            days: parse_field(day, 1, 31)?,
+            months: parse_field(month, 1, 12)?,
+            weekdays,
+            any_day: day.starts_with('*'),
+            any_weekday: weekday.starts_with('*'),
+        };
+        // The search window spans every combination of date and weekday, so a schedule
+        // that matches nothing in it never will.
+        if schedule.next_after(0).is_none() {
+            return Err(format!("{:?} matches no date", expression));
+        }
+        Ok(schedule)
+    }
+
+    /// Returns the first matching minute strictly after `now_ms`, in Unix milliseconds.
+    pub(crate) fn next_after(&self, now_ms: u64) -> Option<u64> {
+        let start = now_ms / MINUTE_MS + 1;
+        let first_day = start / DAY_MINUTES;
+        for day in first_day..first_day + SEARCH_DAYS {
+            if !self.matches_day(day) {
+                continue;
+            }
+            let from = if day == first_day {
+                start % DAY_MINUTES
+            } else {
+                0
+            };
+            for minute_of_day in from..DAY_MINUTES {
+                let (hour, minute) = (minute_of_day / 60, minute_of_day % 60);
+                if self.hours & 1 << hour != 0 && self.minutes & 1 << minute != 0 {
+                    return Some((day * DAY_MINUTES + minute_of_day) * MINUTE_MS);
+                }
+            }
+        }
+        None
+    }
+
+    fn matches_day(&self, days_since_epoch: u64) -> bool {
+        let (month, day) = month_and_day(days_since_epoch);
+        if self.months & 1 << month == 0 {
+            return false;
+        }
+        // 1970-01-01 was a Thursday.
+        let weekday = (days_since_epoch + 4) % 7;
+        let by_day = self.days & 1 << day != 0;
+        let by_weekday = self.weekdays & 1 << weekday != 0;
+        if self.any_day || self.any_weekday {
+            by_day && by_weekday
+        } else {
+            by_day || by_weekday
+        }
+    }
+}
+
+/// Parses one field into a bit set of the values it matches.
+fn parse_field(field: &str, min: u64, max: u64) -> Result<u64, String> {
+    let mut bits = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, Some(parse_value(step, 1, max)?)),
+            None => (part, None),
+        };
+        let (first, last) = if range == "*" {
+            (min, max)
+        } else if let Some((first, last)) = range.split_once('-') {
+            (parse_value(first, min, max)?, parse_value(last, min, max)?)
+        } else {
+            let value = parse_value(range, min, max)?;
+            // `5/15` means from 5 to the end in steps of 15.
+            (value, if step.is_some() { max } else { value })
+        };
+        if first > last {
+            return Err(format!("range {:?} is backwards", range));
+        }
+        for value in (first..=last).step_by(step.unwrap_or(1) as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+fn parse_value(value: &str, min: u64, max: u64) -> Result<u64, String> {
+    match value.parse() {
+        Ok(value) if (min..=max).contains(&value) => Ok(value),
+        _ => Err(format!(
+            "{:?} is not a number from {} to {}",
+            value, min, max
+        )),
+    }
+}
+
+/// Returns the month (1 to 12) and day of the month of a day counted from 1970-01-01.
+fn month_and_day(days_since_epoch: u64) -> (u64, u64) {
+    // Howard Hinnant's `civil_from_days`, with years starting in March.
+    let z = days_since_epoch + 719_468;
+    let day_of_era = z % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = if month_from_march < 10 {
+        month_from_march + 3
+    } else {
+        month_from_march - 9
+    };
+    (month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Unix milliseconds of a UTC date and time, computed independently of `month_and_day`.
+    fn at(year: i64, month: i64, day: i64, hour: u64, minute: u64) -> u64 {
+        let days = (1970..year)
+            .map(|y| if is_leap(y) { 366 } else { 365 })
+            .sum::<i64>()
+            + (1..month).map(|m| month_length(year, m)).sum::<i64>()
+            + day
+            - 1;
+        ((days as u64 * DAY_MINUTES) + hour * 60 + minute) * MINUTE_MS
+    }
+
+    fn is_leap(year: i64) -> bool {
+        year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+    }
+
+    fn month_length(year: i64, month: i64) -> i64 {
+        match month {
+            2 if is_leap(year) => 29,
+            2 => 28,
+            4 | 6 | 9 | 11 => 30,
+            _ => 31,
+        }
+    }
+
+    fn next(expression: &str, after: u64) -> u64 {
+        CronSchedule::parse(expression)
+            .unwrap()
+            .next_after(after)
+            .unwrap()
+    }
+
+    #[test]
+    fn fields_accept_lists_ranges_and_steps() {
+        assert_eq!(parse_field("*", 0, 59).unwrap(), (1 << 60) - 1);
+        assert_eq!(
+            parse_field("*/15", 0, 59).unwrap(),
+            1 | 1 << 15 | 1 << 30 | 1 << 45
+        );
+        assert_eq!(
+            parse_field("5/20", 0, 59).unwrap(),
+            1 << 5 | 1 << 25 | 1 << 45
+        );
+        assert_eq!(
+            parse_field("0-30/10", 0, 59).unwrap(),
+            1 | 1 << 10 | 1 << 20 | 1 << 30
+        );
+        assert_eq!(
+            parse_field("1,3-4", 1, 12).unwrap(),
+            1 << 1 | 1 << 3 | 1 << 4
+        );
+    }
+
+    #[test]
+    fn fields_out_of_range_are_rejected() {
+        for (field, min, max) in [
+            ("60", 0, 59),
+            ("24", 0, 23),
+            ("0", 1, 31),
+            ("13", 1, 12),
+            ("8", 0, 7),
+            ("5-1", 0, 59),
+            ("*/0", 0, 59),
+            ("x", 0, 59),
+            ("", 0, 59),
+        ] {
+            assert!(parse_field(field, min, max).is_err(), "{:?}", field);
+        }
+        assert!(CronSchedule::parse("* * * *").is_err());
+        assert!(CronSchedule::parse("* * * * * *").is_err());
+    }
+
+    #[test]
+    fn sunday_is_0_and_7() {
+        assert_eq!(
+            CronSchedule::parse("0 0 * * 7").unwrap(),
+            CronSchedule::parse("0 0 * * 0").unwrap()
+        );
+        // 2024-06-12 was a Wednesday.
+        assert_eq!(
+            next("0 0 * * 7", at(2024, 6, 12, 0, 0)),
+            at(2024, 6, 16, 0, 0)
+        );
+    }
+
+    #[test]
+    fn next_run_is_strictly_later() {
+        let now = at(2024, 6, 12, 10, 15);
+        assert_eq!(next("15 10 * * *", now), at(2024, 6, 13, 10, 15));
+        assert_eq!(next("* * * * *", now + 30_000), at(2024, 6, 12, 10, 16));
+        assert_eq!(next("*/20 * * * *", now), at(2024, 6, 12, 10, 20));
+    }
+
+    #[test]
+    fn runs_roll_over_days_months_and_years() {
+        assert_eq!(
+            next("30 1 * * *", at(2024, 1, 31, 23, 59)),
+            at(2024, 2, 1, 1, 30)
+        );
+        assert_eq!(
+            next("0 0 1 * *", at(2024, 4, 30, 12, 0)),
+            at(2024, 5, 1, 0, 0)
+        );
+        assert_eq!(
+            next("0 0 1 1 *", at(2024, 12, 31, 23, 59)),
+            at(2025, 1, 1, 0, 0)
+        );
+        // Months without a 31st are skipped.
+        assert_eq!(
+            next("0 0 31 * *", at(2024, 3, 31, 0, 0)),
+            at(2024, 5, 31, 0, 0)
+        );
+    }
+
+    #[test]
+    fn leap_days_are_found() {
+        assert_eq!(
+            next("0 0 29 2 *", at(2023, 1, 1, 0, 0)),
+            at(2024, 2, 29, 0, 0)
+        );
+        assert_eq!(
+            next("0 0 29 2 *", at(2024, 3, 1, 0, 0)),
+            at(2028, 2, 29, 0, 0)
+        );
+        // 2100 is not a leap year.
+        assert_eq!(
+            next("0 0 29 2 *", at(2096, 3, 1, 0, 0)),
+            at(2104, 2, 29, 0, 0)
+        );
+    }
+
+    #[test]
+    fn restricted_day_fields_match_either() {
+        // The 13th, or any Friday: 2024-09-06 is the first Friday after 2024-09-01.
+        assert_eq!(
+            next("0 0 13 * 5", at(2024, 9, 1, 0, 0)),
+            at(2024, 9, 6, 0, 0)
+        );
+        assert_eq!(
+            next("0 0 13 * 5", at(2024, 9, 6, 0, 0)),
+            at(2024, 9, 13, 0, 0)
+        );
+        // With one day field unrestricted, the other one decides.
+        assert_eq!(
+            next("0 0 13 * *", at(2024, 9, 1, 0, 0)),
+            at(2024, 9, 13, 0, 0)
+        );
+        assert_eq!(
+            next("0 0 * * 5", at(2024, 9, 1, 0, 0)),
+            at(2024, 9, 6, 0, 0)
+        );
+    }
+
+    #[test]
+    fn schedules_that_never_match_are_rejected() {
+        assert!(CronSchedule::parse("0 0 31 2 *").is_err());
+        assert!(CronSchedule::parse("0 0 30 2 *").is_err());
+        assert!(CronSchedule::parse("0 0 31 4,6,9,11 *").is_err());
+        // A weekday restriction makes it match on Mondays in February.
+        assert!(CronSchedule::parse("0 0 31 2 1").is_ok());
+    }
+
+    #[test]
+    fn civil_dates_match_a_day_count() {
+        for (year, month, day) in [
+            (1970, 1, 1),
+            (1972, 2, 29),
+            (2000, 2, 29),
+            (2000, 3, 1),
+            (2023, 12, 31),
+            (2100, 3, 1),
+        ] {
+            let days = at(year, month, day, 0, 0) / MINUTE_MS / DAY_MINUTES;
+            assert_eq!(month_and_day(days), (month as u64, day as u64));
+        }
+    }
+}
@@ -1,3 +1,5 @@
+use aum_core::prelude::{ErrorCode, Response};
+
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error(transparent)]
@@ -5,7 +7,112 @@ pub enum Error {
     #[error("I/O Error: {0}")]
     IoError(#[from] std::io::Error),
     #[error("WebSocket Error: {0}")]
-    WsError(#[from] tokio_tungstenite::tungstenite::Error),
+    WsError(Box<tokio_tungstenite::tungstenite::Error>),
+    #[error("TLS Error: {0}")]
+    TlsError(#[from] tokio_rustls::rustls::Error),
     #[error("Wrong request.")]
     WrongRequest,
+    #[error("Not executed, another request of the atomic batch failed.")]
+    Aborted,
+    #[error("Idempotency key {0:?} was already used with different parameters.")]
+    IdempotencyConflict(String),
+    #[error("The first request with idempotency key {0:?} may or may not have been sent.")]
+    OutcomeUnknown(String),
+    /// The failure of an earlier request with the same idempotency key.
+    #[error("{message}")]
+    Replayed { code: ErrorCode, message: String },
+    #[error("Authentication failed.")]
+    Unauthorized,
+    #[error("Not permitted: {0}")]
+    Forbidden(String),
+    #[error("Invalid API key: {0}")]
+    InvalidKey(String),
+    #[error(
+        "Protocol version {0} is not supported, expected {min} to {max}.",
+        min = aum_core::prelude::MIN_PROTOCOL_VERSION,
+        max = aum_core::prelude::PROTOCOL_VERSION
+    )]
+    UnsupportedProtocolVersion(u32),
+    #[error("Rate limit exceeded, retry in {} ms.", retry_after_ms(.0))]
+    RateLimited(std::time::Duration),
+    #[error(
+        "The request needs {tokens} tokens, but its rate limit allows at most {burst} at once."
+    )]
+    OverBudget { tokens: u32, burst: u32 },
+    #[error("The audit log is not enabled.")]
+    AuditDisabled,
+    #[error("Audit log is corrupted: {0}")]
+    AuditLogCorrupted(String),
+    #[error("Invalid configuration: {0}")]
+    InvalidConfig(String),
+    #[error("Invalid schedule: {0}")]
+    InvalidSchedule(String),
+    #[error("No job named {0:?} is scheduled.")]
+    UnknownJob(String),
+    #[error("Job {0:?} failed: {1}")]
+    JobFailed(String, String),
+    #[error("Task Error: {0}")]
+    TaskError(#[from] tokio::task::JoinError),
+}
+
+impl From<tokio_tungstenite::tungstenite::Error> for Error {
+    fn from(e: tokio_tungstenite::tungstenite::Error) -> Self {
+        Error::WsError(Box::new(e))
+    }
+}
+
+impl Error {
+    /// Returns the error code reported to clients for this error.
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Error::CoreError(e) => e.code(),
+            Error::IoError(_)
+            | Error::WsError(_)
+            | Error::TlsError(_)
+            | Error::TaskError(_)
+            | Error::InvalidKey(_)
+            | Error::InvalidConfig(_) => ErrorCode::Internal,
+            Error::WrongRequest | Error::InvalidSchedule(_) | Error::OverBudget { .. } => {
+                ErrorCode::InvalidRequest
+            }
+            Error::Aborted => ErrorCode::Aborted,
+            Error::IdempotencyConflict(_) => ErrorCode::IdempotencyConflict,
+            Error::OutcomeUnknown(_) => ErrorCode::OutcomeUnknown,
+            Error::Replayed { code, .. } => *code,
+            Error::Unauthorized => ErrorCode::Unauthorized,
+            Error::Forbidden(_) => ErrorCode::Forbidden,
+            Error::RateLimited(_) => ErrorCode::RateLimited,
+            Error::UnsupportedProtocolVersion(_) => ErrorCode::UnsupportedProtocolVersion,
+            Error::AuditDisabled => ErrorCode::AuditDisabled,
+            Error::AuditLogCorrupted(_) => ErrorCode::AuditLogCorrupted,
+            Error::UnknownJob(_) => ErrorCode::UnknownJob,
+            Error::JobFailed(..) => ErrorCode::JobFailed,
+        }
+    }
+
+    /// Converts the error into a [`Response::Error`] that can be sent to the client.
+    pub fn into_response(self) -> Response {
+        let details = match &self {
+            Error::RateLimited(retry_after) => Some(serde_json::json!({
+                "retry_after_ms": retry_after_ms(retry_after),
+            })),
+            Error::UnsupportedProtocolVersion(_) => Some(serde_json::json!({
+                "min_protocol_version": aum_core::prelude::MIN_PROTOCOL_VERSION,
+                "protocol_version": aum_core::prelude::PROTOCOL_VERSION,
+            })),
+            _ => None,
+        };
+        Response::Error {
+            code: self.code(),
+            message: self.to_string(),
+            details,
+        }
+    }
+}
+
+/// Rounds a retry delay up to whole milliseconds, so that clients never retry too early.
+fn retry_after_ms(retry_after: &std::time::Duration) -> u64 {
+    u64::try_from(retry_after.as_micros().div_ceil(1000))
+        .unwrap_or(u64::MAX)
+        .max(1)
 }
@@ -0,0 +1,111 @@
+use crate::executor::Executor;
+use aum_core::prelude::{AsyncWalletManager, Event};
+use std::{collections::HashMap, sync::Arc, time::Duration};
+use tokio::sync::{Notify, broadcast};
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
+
+const CHANNEL_CAPACITY: usize = 1024;
+
+/// Fans engine events out to every subscribed connection.
+pub struct EventBus {
+    sender: broadcast::Sender<Event>,
+    changed: Notify,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        Self {
+            sender: broadcast::channel(CHANNEL_CAPACITY).0,
+            changed: Notify::new(),
+        }
+    }
+
+    pub fn publish(&self, event: Event) {
+        // Sending only fails when nobody is subscribed.
+        let _ = self.sender.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.sender.subscribe()
+    }
+
+    /// Asks the watcher to rescan wallets now instead of waiting for the next tick.
+    pub fn notify_changed(&self) {
+        self.changed.notify_one();
+    }
+}
+
+/// Watches the wallet manager and publishes balance and wallet events.
+///
+/// Wallets are rescanned every `interval`, and right away after a sync or a sent
+/// transaction. Changes are found by comparing each scan with the previous one, starting
+/// from the first scan that succeeds.
+pub async fn watch<S, Wm, M>(
+    executor: Arc<Executor<S, Wm, M>>,
+    interval: Duration,
+    shutdown: CancellationToken,
+) where
+    S: aum_core::prelude::Storage + Send + 'static + Sync,
+    Wm: aum_core::prelude::AsyncWalletManager + Send + 'static + Sync,
+    M: aum_core::prelude::Monitor<WalletManager = Wm> + Send + 'static + Sync,
+{
+    let events = executor.events();
+    let mut previous = None;
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        tokio::select! {
+            _ = shutdown.cancelled() => break,
+            _ = ticker.tick() => {}
+            _ = events.changed.notified() => {}
+        }
+        let current = match snapshot(&executor.runtime().wallets()).await {
+            Ok(current) => current,
+            Err(e) => {
+                warn!("Failed to scan wallets for events: {}", e);
+                continue;
+            }
+        };
+        let Some(previous) = previous.replace(current.clone()) else {
+            continue;
+        };
+
+        for (address, &new_balance) in &current {
+            match previous.get(address) {
+                None => events.publish(Event::WalletCreated {
+                    address: address.clone(),
+                }),
+                Some(&old_balance) if old_balance != new_balance => {
+                    events.publish(Event::BalanceChanged {
+                        address: address.clone(),
+                        old_balance,
+                        new_balance,
+                    });
+                    if new_balance > old_balance {
+                        events.publish(Event::IncomingTransaction {
+                            address: address.clone(),
+                            amount: new_balance - old_balance,
+                        });
+                    }
+                }
+                Some(_) => {}
+            }
+        }
+        for address in previous.keys().filter(|a| !current.contains_key(*a)) {
+            events.publish(Event::WalletDeleted {
+                address: address.clone(),
+            });
+        }
+    }
+}
+
+async fn snapshot<Wm: AsyncWalletManager>(
+    wallet_manager: &Wm,
+) -> Result<HashMap<String, u64>, aum_core::errors::WalletManagerError> {
+    Ok(wallet_manager
+        .retrieve_balances()
+        .await?
+        .into_iter()
+        .map(|(address, balance)| (address.to_string(), balance))
+        .collect())
+}
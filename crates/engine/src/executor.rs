@@ -1,130 +1,873 @@
-use crate::Error;
+use crate::audit::{self, AuditLink, AuditLog};
+use crate::events::EventBus;
+use crate::idempotency::{Attempt, IdempotencyStore, SpendParams};
+use crate::metrics::{Metrics, code_label};
+use crate::supervisor::MonitorStatus;
+use crate::{
+    Error,
+    auth::{Identity, Scope},
+};
+use aum_core::errors::WalletError;
 use aum_core::prelude::{
-    Address, Error as CoreError, Monitor, Request, Response, Storage, Wallet, WalletManager,
+    Address, AsyncWallet, AsyncWalletManager, AuditAction, AuditOutcome, Error as CoreError, Event,
+    MIN_PROTOCOL_VERSION, Monitor, PROTOCOL_VERSION, Request, Response, Storage,
+};
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    sync::{
+        Arc, OnceLock,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
 };
-use std::{str::FromStr, sync::Arc};
+use tokio::sync::RwLock;
+use tracing::{Instrument, debug, debug_span, error, field, info_span};
+
+/// What the engine reports about itself in [`Response::Hello`].
+#[derive(Clone, Debug, Default)]
+pub struct Capabilities {
+    pub network: Option<String>,
+    pub features: Vec<String>,
+    /// Whether the engine runs and restarts the monitor itself.
+    pub supervised: bool,
+}
 
 pub struct Executor<S, Wm, M>
 where
     S: Storage + Send + 'static,
-    Wm: WalletManager + Send + 'static,
+    Wm: AsyncWalletManager + Send + 'static,
     M: Monitor<WalletManager = Wm> + Send + 'static,
 {
     runtime: crate::runtime::Runtime<S, Wm, M>,
+    events: EventBus,
+    capabilities: Capabilities,
+    /// Held shared by requests that move funds and exclusively by batches, so that a batch
+    /// runs as one unit.
+    batches: RwLock<()>,
+    idempotency: IdempotencyStore,
+    metrics: Metrics,
+    monitor_status: MonitorStatus,
+    /// Id of the next request, to tell requests apart in traces.
+    next_request_id: AtomicU64,
+}
+
+/// Funds already claimed by earlier sends of an atomic batch being validated.
+struct Reserved<A> {
+    by_wallet: HashMap<A, u64>,
+    total: u64,
 }
 
 impl<S, Wm, M> Executor<S, Wm, M>
 where
-    S: Storage + Send + 'static,
-    Wm: WalletManager + Send + 'static,
-    M: Monitor<WalletManager = Wm> + Send + 'static,
+    S: Storage + Send + 'static + Sync,
+    Wm: AsyncWalletManager + Send + 'static + Sync,
+    M: Monitor<WalletManager = Wm> + Send + 'static + Sync,
 {
-    pub fn new(runtime: crate::runtime::Runtime<S, Wm, M>) -> Arc<Self> {
-        Arc::new(Self { runtime })
+    pub fn new(
+        runtime: crate::runtime::Runtime<S, Wm, M>,
+        capabilities: Capabilities,
+        idempotency_window: Duration,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            runtime,
+            events: EventBus::new(),
+            capabilities,
+            batches: RwLock::new(()),
+            idempotency: IdempotencyStore::new(idempotency_window),
+            metrics: Metrics::default(),
+            monitor_status: MonitorStatus::default(),
+            next_request_id: AtomicU64::new(1),
+        })
+    }
+    pub fn runtime(&self) -> &crate::runtime::Runtime<S, Wm, M> {
+        &self.runtime
+    }
+    pub fn events(&self) -> &EventBus {
+        &self.events
+    }
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+    pub fn monitor_status(&self) -> &MonitorStatus {
+        &self.monitor_status
+    }
+    /// Executes `req` on behalf of `identity` inside a `request` span, which records the
+    /// duration and outcome of the request once it is done.
+    ///
+    /// `client_id` is the id the client gave the request, e.g. its envelope id. It is
+    /// recorded next to the engine's own `request_id`, so that client and engine traces can
+    /// be matched up.
+    pub async fn execute(
+        &self,
+        identity: &Identity,
+        client_id: Option<String>,
+        req: Request,
+    ) -> Result<Response, Error> {
+        let kind = req.kind();
+        let span = info_span!(
+            "request",
+            request_id = self.next_request_id.fetch_add(1, Ordering::Relaxed),
+            client_id = client_id,
+            kind,
+            identity = identity.key_id.as_deref().unwrap_or("<anonymous>"),
+            duration_ms = field::Empty,
+            outcome = field::Empty,
+        );
+        let started = Instant::now();
+        let result = self.dispatch(identity, req).instrument(span.clone()).await;
+        let elapsed = started.elapsed();
+        let code = result.as_ref().err().map(Error::code);
+        span.record("duration_ms", elapsed.as_secs_f64() * 1000.0);
+        span.record("outcome", code.map_or("ok".to_owned(), code_label).as_str());
+        span.in_scope(|| match &result {
+            Ok(_) => debug!("Request handled"),
+            Err(e) => debug!("Request failed: {}", e),
+        });
+        self.metrics.record(kind, elapsed, code);
+        result
+    }
+    async fn dispatch(&self, identity: &Identity, req: Request) -> Result<Response, Error> {
+        match req {
+            Request::Batch { requests, atomic } => {
+                let _batch = self.batches.write().await;
+                let responses = self.execute_batch(identity, requests, atomic).await;
+                Ok(Response::Batch { responses })
+            }
+            req => {
+                let _batch = match req {
+                    Request::SendTransaction { .. }
+                    | Request::SendTransactionFrom { .. }
+                    | Request::Sync => Some(self.batches.read().await),
+                    _ => None,
+                };
+                self.execute_one(identity, req).await
+            }
+        }
+    }
+    /// Executes the requests of a batch in order while holding the batch lock.
+    ///
+    /// Atomic batches are validated first and not executed at all if any request would
+    /// fail; if one still fails while executing, e.g. in the backend, the requests after it
+    /// are skipped. Transactions already sent by then are not undone.
+    async fn execute_batch(
+        &self,
+        identity: &Identity,
+        requests: Vec<Request>,
+        atomic: bool,
+    ) -> Vec<Response> {
+        if atomic {
+            let errors = self.validate_batch(identity, &requests).await;
+            if errors.iter().any(Option::is_some) {
+                return errors
+                    .into_iter()
+                    .map(|error| error.unwrap_or(Error::Aborted).into_response())
+                    .collect();
+            }
+        }
+        let mut responses = Vec::with_capacity(requests.len());
+        let mut failed = false;
+        for (index, req) in requests.into_iter().enumerate() {
+            let response = if failed {
+                Error::Aborted.into_response()
+            } else if !batchable(&req) {
+                failed = atomic;
+                Error::WrongRequest.into_response()
+            } else {
+                let span = debug_span!("batch_request", index, kind = req.kind());
+                self.execute_one(identity, req)
+                    .instrument(span)
+                    .await
+                    .unwrap_or_else(|e| {
+                        failed = atomic;
+                        e.into_response()
+                    })
+            };
+            responses.push(response);
+        }
+        responses
+    }
+    /// Checks every request of an atomic batch without executing any of them, returning
+    /// the error each one would fail with.
+    ///
+    /// Amounts are added up per source wallet, so sends that only fit a wallet's balance one
+    /// at a time fail from the first one that doesn't. Sends that let the wallet manager
+    /// pick the source are checked against the total balance of all wallets.
+    async fn validate_batch(
+        &self,
+        identity: &Identity,
+        requests: &[Request],
+    ) -> Vec<Option<Error>> {
+        let mut reserved = Reserved {
+            by_wallet: HashMap::new(),
+            total: 0,
+        };
+        let mut errors = Vec::with_capacity(requests.len());
+        for req in requests {
+            errors.push(self.validate(identity, req, &mut reserved).await.err());
+        }
+        errors
+    }
+    async fn validate(
+        &self,
+        identity: &Identity,
+        req: &Request,
+        reserved: &mut Reserved<<Wm as AsyncWalletManager>::Address>,
+    ) -> Result<(), Error> {
+        self.authorize(identity, req)?;
+        if !batchable(req) {
+            return Err(Error::WrongRequest);
+        }
+        let insufficient = || Error::from(CoreError::from(WalletError::InsufficientBalance));
+        match req {
+            Request::SendTransaction { to, amount, .. } => {
+                self.parse_address(to)?;
+                let balance: u64 = self
+                    .retrieve_balances()
+                    .await?
+                    .iter()
+                    .map(|(_, balance)| balance)
+                    .sum();
+                let total = reserved.total.saturating_add(*amount);
+                if total > balance {
+                    return Err(insufficient());
+                }
+                reserved.total = total;
+            }
+            Request::SendTransactionFrom {
+                from, to, amount, ..
+            } => {
+                let from = self.parse_address(from)?;
+                self.parse_address(to)?;
+                let balance = self.retrieve_balance(&from).await?;
+                let spent = reserved.by_wallet.entry(from).or_default();
+                if spent.saturating_add(*amount) > balance {
+                    return Err(insufficient());
+                }
+                *spent += amount;
+                reserved.total = reserved.total.saturating_add(*amount);
+            }
+            Request::RetrieveBalance { address } => {
+                self.parse_address(address)?;
+            }
+            Request::Batch { .. }
+            | Request::Subscribe { .. }
+            | Request::Unsubscribe { .. }
+            | Request::RunJob { .. }
+            | Request::Hello { .. }
+            | Request::Health
+            | Request::ListJobs
+            | Request::RetrieveAddress
+            | Request::RetrieveBalances
+            | Request::ListWallets
+            | Request::Sync
+            | Request::QueryAudit { .. }
+            | Request::VerifyAudit => {}
+        }
+        Ok(())
     }
-    pub async fn execute(&self, req: Request) -> Result<Response, Error> {
+    /// Executes a single request, recording it in the audit log if it changes state.
+    async fn execute_one(&self, identity: &Identity, req: Request) -> Result<Response, Error> {
+        match audit::action_of(&req) {
+            Some(action) => self.execute_spend(identity, req, action).await,
+            None => self.execute_unaudited(identity, req).await,
+        }
+    }
+    /// Executes a spend, recording it in the audit log.
+    ///
+    /// A pending entry is written before the backend is called, and the spend fails if it
+    /// can't be; an entry with the outcome follows. A spend rejected before reaching the
+    /// backend, or answered by an earlier one with the same idempotency key, gets a single
+    /// entry.
+    async fn execute_spend(
+        &self,
+        identity: &Identity,
+        req: Request,
+        action: AuditAction,
+    ) -> Result<Response, Error> {
+        let intent = OnceLock::new();
+        let (result, link) = match self.spend(identity, req, &action, &intent).await {
+            Ok(Attempt::Sent(result)) => {
+                let link = intent
+                    .get()
+                    .map_or(AuditLink::None, |&seq| AuditLink::OutcomeOf(seq));
+                (result, link)
+            }
+            Ok(Attempt::Replayed(result)) => (result, AuditLink::Replay),
+            Err(e) => (Err(e), AuditLink::None),
+        };
+        self.audit(identity, action, audit::outcome_of(&result), link);
+        result
+    }
+    /// Sends the transaction of a spend request, once per idempotency key.
+    ///
+    /// Right before the backend is called, the intent is recorded in the audit log and its
+    /// `seq` stored in `intent`.
+    async fn spend(
+        &self,
+        identity: &Identity,
+        req: Request,
+        action: &AuditAction,
+        intent: &OnceLock<u64>,
+    ) -> Result<Attempt, Error> {
+        self.authorize(identity, &req)?;
+        let (from, to, amount, idempotency_key) = match req {
+            Request::SendTransaction {
+                to,
+                amount,
+                idempotency_key,
+            } => (None, to, amount, idempotency_key),
+            Request::SendTransactionFrom {
+                from,
+                to,
+                amount,
+                idempotency_key,
+            } => (Some(from), to, amount, idempotency_key),
+            _ => return Err(Error::WrongRequest),
+        };
+        let params = SpendParams {
+            from: from.clone(),
+            to: to.clone(),
+            amount,
+        };
+        let send = move || async move {
+            let from_address = from.as_deref().map(|from| self.parse_address(from));
+            let from_address = from_address.transpose()?;
+            let to_address = self.parse_address(&to)?;
+            if let Some(log) = self.runtime.audit_log() {
+                let pending = log.append(
+                    identity.key_id.as_deref(),
+                    action.clone(),
+                    AuditOutcome::Pending,
+                    AuditLink::None,
+                )?;
+                let _ = intent.set(pending.seq);
+            }
+            let txid = match &from_address {
+                Some(from_address) => {
+                    self.process_send_transaction_from(from_address, &to_address, amount)
+                        .await?
+                }
+                None => self.process_send_transaction(&to_address, amount).await?,
+            };
+            self.publish_outgoing(from.clone(), to, amount, &txid);
+            Ok(match from {
+                Some(from) => Response::SendTransactionFrom { from, txid },
+                None => Response::SendTransaction { txid },
+            })
+        };
+        match idempotency_key {
+            Some(key) => self.idempotency.run(identity, key, params, send).await,
+            None => Ok(Attempt::Sent(send().await)),
+        }
+    }
+    async fn execute_unaudited(
+        &self,
+        identity: &Identity,
+        req: Request,
+    ) -> Result<Response, Error> {
+        self.authorize(identity, &req)?;
         match req {
+            Request::Hello {
+                protocol_version,
+                client_name,
+            } => {
+                if protocol_version < MIN_PROTOCOL_VERSION {
+                    return Err(Error::UnsupportedProtocolVersion(protocol_version));
+                }
+                debug!(
+                    "Hello from {} speaking protocol version {}",
+                    client_name.as_deref().unwrap_or("<unnamed client>"),
+                    protocol_version
+                );
+                Ok(Response::Hello {
+                    protocol_version: PROTOCOL_VERSION,
+                    server_version: env!("CARGO_PKG_VERSION").to_owned(),
+                    requests: Request::KINDS.iter().map(|&kind| kind.to_owned()).collect(),
+                    network: self.capabilities.network.clone(),
+                    features: self.capabilities.features.clone(),
+                })
+            }
+            Request::Health => {
+                let (status, monitor) = self.monitor_status.health(
+                    self.capabilities.supervised,
+                    self.runtime.monitor().is_running(),
+                );
+                Ok(Response::Health { status, monitor })
+            }
             Request::RetrieveAddress => {
-                let address = self.process_retrieve_address()?;
+                let address = self.process_retrieve_address().await?;
                 Ok(Response::RetrieveAddress {
                     address: address.to_string(),
                 })
             }
 
-            Request::SendTransaction { to, amount } => {
-                let address = self.parse_address(&to)?;
-                let txid = self.process_send_transaction(&address, amount)?;
-                Ok(Response::SendTransaction { txid })
-            }
-            Request::SendTransactionFrom { from, to, amount } => {
-                let from_address = self.parse_address(&from)?;
-                let to_address = self.parse_address(&to)?;
-                let txid =
-                    self.process_send_transaction_from(&from_address, &to_address, amount)?;
-                Ok(Response::SendTransactionFrom { from, txid })
+            // Spends are recorded in the audit log, see `execute_spend`.
+            Request::SendTransaction { .. } | Request::SendTransactionFrom { .. } => {
+                Err(Error::WrongRequest)
             }
             Request::Sync => {
-                let success = self.process_sync().await?;
+                let success = self.sync().await?;
                 Ok(Response::Sync { success })
             }
             Request::RetrieveBalance { address } => {
                 let address = self.parse_address(&address)?;
-                let balance = self.retrieve_balance(&address)?;
+                let balance = self.retrieve_balance(&address).await?;
                 Ok(Response::RetrieveBalance {
                     address: address.to_string(),
                     balance,
                 })
             }
             Request::ListWallets => {
-                let wallets = self.process_list_wallets()?;
-                let wallets = wallets
-                    .iter()
-                    .map(|w| w.address().to_string())
-                    .collect::<Vec<_>>();
+                let wallets = self.process_list_wallets().await?;
                 Ok(Response::ListWallets { wallets })
             }
             Request::RetrieveBalances => {
-                let balances = self.retrieve_balances()?;
+                let balances = self.retrieve_balances().await?;
                 let balances = balances
                     .iter()
                     .map(|(addr, balance)| (addr.to_string(), *balance))
                     .collect::<Vec<_>>();
                 Ok(Response::RetrieveBalances { balances })
             }
+            Request::QueryAudit { since, until } => {
+                let entries = self.audit_log()?.query(since, until)?;
+                Ok(Response::AuditEntries { entries })
+            }
+            Request::VerifyAudit => {
+                let head = self.audit_log()?.verify()?;
+                Ok(Response::AuditVerified {
+                    entries: head.entries,
+                    head: head.hash,
+                })
+            }
+            Request::ListJobs => Ok(Response::Jobs {
+                jobs: self.runtime.scheduler().jobs(),
+            }),
+            Request::RunJob { name } => {
+                let job = self.runtime.on_behalf_of(identity).run_job(&name).await?;
+                Ok(Response::JobRun { job })
+            }
+            // Subscriptions belong to a connection and are handled by the interface.
+            Request::Subscribe { .. } | Request::Unsubscribe { .. } => Err(Error::WrongRequest),
+            // Batches can't be nested.
+            Request::Batch { .. } => Err(Error::WrongRequest),
+        }
+    }
+    /// Checks that `identity` holds a scope permitting `req`.
+    ///
+    /// `spend-from:<address>` scopes are compared against the parsed source address, so
+    /// equivalent spellings of the same address match.
+    pub(crate) fn authorize(&self, identity: &Identity, req: &Request) -> Result<(), Error> {
+        let allowed = match req {
+            // Each request of a batch is authorized on its own.
+            Request::Hello { .. } | Request::Health | Request::Batch { .. } => true,
+            Request::RetrieveAddress
+            | Request::RetrieveBalance { .. }
+            | Request::RetrieveBalances
+            | Request::ListWallets
+            | Request::Subscribe { .. }
+            | Request::Unsubscribe { .. } => identity.has_scope(&Scope::Read),
+            Request::SendTransaction { .. } => identity.has_scope(&Scope::Spend),
+            Request::SendTransactionFrom { from, .. } => {
+                identity.has_scope(&Scope::Spend) || self.may_spend_from(identity, from)
+            }
+            Request::Sync
+            | Request::QueryAudit { .. }
+            | Request::VerifyAudit
+            | Request::ListJobs
+            | Request::RunJob { .. } => identity.has_scope(&Scope::Admin),
+        };
+        if allowed {
+            Ok(())
+        } else {
+            Err(Error::Forbidden(format!(
+                "{} is not allowed for key {}",
+                req.kind(),
+                identity.key_id.as_deref().unwrap_or("<anonymous>")
+            )))
+        }
+    }
+    /// Appends an entry to the audit log, if it is enabled.
+    ///
+    /// A failed append is logged rather than returned, since the action already happened.
+    fn audit(
+        &self,
+        identity: &Identity,
+        action: AuditAction,
+        outcome: AuditOutcome,
+        link: AuditLink,
+    ) {
+        let Some(log) = self.runtime.audit_log() else {
+            return;
+        };
+        let identity = identity.key_id.as_deref();
+        if let Err(e) = log.append(identity, action.clone(), outcome, link) {
+            error!("Failed to record {:?} in the audit log: {}", action, e);
         }
     }
+    fn audit_log(&self) -> Result<Arc<AuditLog>, Error> {
+        self.runtime.audit_log().ok_or(Error::AuditDisabled)
+    }
+    fn publish_outgoing(&self, from: Option<String>, to: String, amount: u64, txid: &str) {
+        self.events.publish(Event::OutgoingConfirmed {
+            from,
+            to,
+            amount,
+            txid: txid.to_owned(),
+        });
+        self.events.notify_changed();
+    }
+    fn may_spend_from(&self, identity: &Identity, from: &str) -> bool {
+        let Ok(from) = self.parse_address(from) else {
+            return false;
+        };
+        identity
+            .spendable_from()
+            .filter_map(|address| self.parse_address(address).ok())
+            .any(|address| address == from)
+    }
     fn parse_address(
         &self,
         address_str: &str,
-    ) -> Result<<Wm as WalletManager>::Address, CoreError> {
-        match <Wm as WalletManager>::Address::from_str(address_str) {
+    ) -> Result<<Wm as AsyncWalletManager>::Address, CoreError> {
+        match <Wm as AsyncWalletManager>::Address::from_str(address_str) {
             Ok(addr) => Ok(addr),
             Err(_) => Err(CoreError::AddressError(
                 aum_core::errors::AddressError::ParseError,
             )),
         }
     }
-    fn process_retrieve_address(&self) -> Result<impl Address, CoreError> {
-        let address = self.runtime.wallet_manager().retrieve_address()?;
+    async fn process_retrieve_address(&self) -> Result<impl Address, CoreError> {
+        let address = self
+            .runtime
+            .wallets()
+            .retrieve_address()
+            .instrument(backend_span("retrieve_address"))
+            .await?;
         Ok(address)
     }
-    fn process_send_transaction(
+    async fn process_send_transaction(
         &self,
-        to: &<Wm as WalletManager>::Address,
+        to: &<Wm as AsyncWalletManager>::Address,
         amount: u64,
     ) -> Result<String, CoreError> {
         let txid = self
             .runtime
-            .wallet_manager()
-            .send_transaction(&to, amount)?;
+            .wallets()
+            .send_transaction(to, amount)
+            .instrument(backend_span("send_transaction"))
+            .await?;
         Ok(txid.to_string())
     }
-    fn process_send_transaction_from(
+    async fn process_send_transaction_from(
         &self,
-        from: &<Wm as WalletManager>::Address,
-        to: &<Wm as WalletManager>::Address,
+        from: &<Wm as AsyncWalletManager>::Address,
+        to: &<Wm as AsyncWalletManager>::Address,
         amount: u64,
     ) -> Result<String, CoreError> {
         let txid = self
             .runtime
-            .wallet_manager()
-            .send_transaction_from(&from, &to, amount)?;
+            .wallets()
+            .send_transaction_from(from, to, amount)
+            .instrument(backend_span("send_transaction_from"))
+            .await?;
         Ok(txid.to_string())
     }
+    /// Syncs the monitor with the backend and rescans wallets for events.
+    pub(crate) async fn sync(&self) -> Result<bool, CoreError> {
+        let success = self.process_sync().await?;
+        self.metrics.record_sync();
+        self.events.notify_changed();
+        Ok(success)
+    }
+    /// Syncs like a `Sync` request, after a batch in progress, for callers outside of
+    /// [`Executor::execute`].
+    pub(crate) async fn sync_between_batches(&self) -> Result<bool, CoreError> {
+        let _batch = self.batches.read().await;
+        self.sync().await
+    }
     async fn process_sync(&self) -> Result<bool, CoreError> {
-        self.runtime.monitor().sync().await?;
+        self.runtime
+            .monitor()
+            .sync()
+            .instrument(backend_span("sync"))
+            .await?;
         Ok(true)
     }
-    fn retrieve_balance(&self, address: &<Wm as WalletManager>::Address) -> Result<u64, CoreError> {
-        let balance = self.runtime.wallet_manager().retrieve_balance(&address)?;
+    async fn retrieve_balance(
+        &self,
+        address: &<Wm as AsyncWalletManager>::Address,
+    ) -> Result<u64, CoreError> {
+        let balance = self
+            .runtime
+            .wallets()
+            .retrieve_balance(address)
+            .instrument(backend_span("retrieve_balance"))
+            .await?;
         Ok(balance)
     }
-    fn retrieve_balances(&self) -> Result<Vec<(impl Address, u64)>, CoreError> {
-        let balances = self.runtime.wallet_manager().retrieve_balances()?;
+    async fn retrieve_balances(&self) -> Result<Vec<(impl Address, u64)>, CoreError> {
+        let balances = self
+            .runtime
+            .wallets()
+            .retrieve_balances()
+            .instrument(backend_span("retrieve_balances"))
+            .await?;
         Ok(balances)
     }
-    fn process_list_wallets(&self) -> Result<Vec<&<Wm as WalletManager>::Wallet>, CoreError> {
-        let wallets = self.runtime.wallet_manager().list_wallets()?;
-        Ok(wallets)
+    /// Returns the addresses of all wallets.
+    async fn process_list_wallets(&self) -> Result<Vec<String>, CoreError> {
+        let wallets = self
+            .runtime
+            .wallets()
+            .list_wallets()
+            .instrument(backend_span("list_wallets"))
+            .await?;
+        Ok(wallets.iter().map(|w| w.address().to_string()).collect())
+    }
+}
+
+/// Returns whether a batch may contain `req`.
+///
+/// Subscriptions belong to a connection and batches can't be nested. Job runs are left out
+/// because a job may wait for the batch lock the batch holds, as `sync` does.
+fn batchable(req: &Request) -> bool {
+    !matches!(
+        req,
+        Request::Batch { .. }
+            | Request::Subscribe { .. }
+            | Request::Unsubscribe { .. }
+            | Request::RunJob { .. }
+    )
+}
+
+/// Creates the span of a call into the wallet manager or monitor, a child of the
+/// request's span.
+fn backend_span(call: &'static str) -> tracing::Span {
+    debug_span!("backend", call)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scheduler::{self, JobOptions, Schedule};
+    use crate::testing::{self, TestAddress, TestMonitor, TestStorage, TestWallets};
+    use aum_core::prelude::ErrorCode;
+
+    type TestExecutor = Executor<TestStorage, TestWallets, TestMonitor>;
+
+    async fn executor(balances: &[(&str, u64)]) -> Arc<TestExecutor> {
+        let runtime = testing::runtime(balances).await;
+        Executor::new(runtime, Capabilities::default(), Duration::from_secs(60))
+    }
+
+    fn admin() -> Identity {
+        Identity::new("ops", vec![Scope::Admin, Scope::Read, Scope::Spend])
+    }
+
+    /// Executes `req`, failing the test if it doesn't finish.
+    async fn execute(
+        executor: &TestExecutor,
+        identity: &Identity,
+        req: Request,
+    ) -> Result<Response, Error> {
+        let execute = executor.execute(identity, None, req);
+        tokio::time::timeout(Duration::from_secs(5), execute)
+            .await
+            .expect("the request didn't finish")
+    }
+
+    async fn batch(executor: &TestExecutor, requests: Vec<Request>, atomic: bool) -> Vec<Response> {
+        match execute(executor, &admin(), Request::Batch { requests, atomic }).await {
+            Ok(Response::Batch { responses }) => responses,
+            other => panic!("expected batch responses, got {:?}", other),
+        }
+    }
+
+    fn code(response: &Response) -> Option<ErrorCode> {
+        match response {
+            Response::Error { code, .. } => Some(*code),
+            _ => None,
+        }
+    }
+
+    #[tokio::test]
+    async fn batches_refuse_job_runs() {
+        let executor = executor(&[("addr0", 10)]).await;
+        let jobs = JobOptions {
+            sync: Some(Schedule::every(Duration::from_secs(3600)).unwrap()),
+            ..JobOptions::default()
+        };
+        scheduler::register_engine_jobs(&executor, &jobs);
+        let run_sync = || Request::RunJob {
+            name: "sync".to_owned(),
+        };
+
+        for atomic in [false, true] {
+            let responses = batch(&executor, vec![Request::ListJobs, run_sync()], atomic).await;
+            assert_eq!(code(&responses[1]), Some(ErrorCode::InvalidRequest));
+        }
+        // The batch lock was released, so syncing still works.
+        let sync = execute(&executor, &admin(), run_sync()).await;
+        assert!(matches!(sync, Ok(Response::JobRun { .. })));
+        let syncs = executor.runtime().monitor().syncs.load(Ordering::SeqCst);
+        assert_eq!(syncs, 1);
+    }
+
+    #[tokio::test]
+    async fn spend_from_scopes_allow_only_their_source_address() {
+        let executor = executor(&[("addr0", 10), ("addr1", 10)]).await;
+        let identity = Identity::new("payouts", vec![Scope::SpendFrom("addr1".to_owned())]);
+        let allowed = send_from("addr1", 1);
+        assert!(executor.authorize(&identity, &allowed).is_ok());
+        for req in [
+            send_from("addr0", 1),
+            send_from("elsewhere", 1),
+            Request::SendTransaction {
+                to: "addr9".to_owned(),
+                amount: 1,
+                idempotency_key: None,
+            },
+            Request::RetrieveBalances,
+        ] {
+            assert!(
+                matches!(
+                    executor.authorize(&identity, &req),
+                    Err(Error::Forbidden(_))
+                ),
+                "{:?}",
+                req
+            );
+        }
+        let sent = execute(&executor, &identity, allowed).await;
+        assert!(
+            matches!(sent, Ok(Response::SendTransactionFrom { .. })),
+            "{:?}",
+            sent
+        );
+        assert!(
+            execute(&executor, &identity, send_from("addr0", 1))
+                .await
+                .is_err()
+        );
+    }
+
+    fn send_from(from: &str, amount: u64) -> Request {
+        Request::SendTransactionFrom {
+            from: from.to_owned(),
+            to: "addr9".to_owned(),
+            amount,
+            idempotency_key: None,
+        }
+    }
+
+    async fn balance(executor: &TestExecutor, address: &str) -> u64 {
+        let address = TestAddress(address.to_owned());
+        executor.retrieve_balance(&address).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn atomic_batches_send_nothing_if_one_send_would_fail() {
+        let executor = executor(&[("addr0", 10), ("addr1", 5)]).await;
+        let responses = batch(
+            &executor,
+            vec![
+                send_from("addr0", 5),
+                send_from("addr1", 6),
+                Request::ListWallets,
+            ],
+            true,
+        )
+        .await;
+        let codes: Vec<_> = responses.iter().map(code).collect();
+        assert_eq!(
+            codes,
+            [
+                Some(ErrorCode::Aborted),
+                Some(ErrorCode::InsufficientBalance),
+                Some(ErrorCode::Aborted)
+            ]
+        );
+        assert_eq!(balance(&executor, "addr0").await, 10);
+        assert_eq!(balance(&executor, "addr1").await, 5);
+    }
+
+    #[tokio::test]
+    async fn atomic_batches_add_up_amounts_per_source_wallet() {
+        let executor = executor(&[("addr0", 10), ("addr1", 5)]).await;
+        let responses = batch(
+            &executor,
+            vec![
+                send_from("addr0", 6),
+                send_from("addr1", 5),
+                send_from("addr0", 6),
+            ],
+            true,
+        )
+        .await;
+        assert_eq!(code(&responses[0]), Some(ErrorCode::Aborted));
+        assert_eq!(code(&responses[2]), Some(ErrorCode::InsufficientBalance));
+
+        let responses = batch(
+            &executor,
+            vec![
+                send_from("addr0", 6),
+                send_from("addr1", 5),
+                send_from("addr0", 4),
+            ],
+            true,
+        )
+        .await;
+        assert!(responses.iter().all(|response| code(response).is_none()));
+        assert_eq!(balance(&executor, "addr0").await, 0);
+        assert_eq!(balance(&executor, "addr1").await, 0);
+    }
+
+    #[tokio::test]
+    async fn atomic_batches_check_unsourced_sends_against_all_wallets() {
+        let executor = executor(&[("addr0", 10), ("addr1", 5)]).await;
+        let send = |amount| Request::SendTransaction {
+            to: "addr9".to_owned(),
+            amount,
+            idempotency_key: None,
+        };
+        let responses = batch(&executor, vec![send(8), send(8)], true).await;
+        assert_eq!(code(&responses[1]), Some(ErrorCode::InsufficientBalance));
+        assert_eq!(balance(&executor, "addr0").await, 10);
+    }
+
+    #[tokio::test]
+    async fn other_batches_go_on_after_a_failure() {
+        let executor = executor(&[("addr0", 10)]).await;
+        let responses = batch(
+            &executor,
+            vec![send_from("addr0", 20), send_from("addr0", 3)],
+            false,
+        )
+        .await;
+        assert_eq!(code(&responses[0]), Some(ErrorCode::InsufficientBalance));
+        assert!(matches!(
+            &responses[1],
+            Response::SendTransactionFrom { from, .. } if from == "addr0"
+        ));
+        assert_eq!(balance(&executor, "addr0").await, 7);
+    }
+
+    #[tokio::test]
+    async fn batches_refuse_nested_batches_and_subscriptions() {
+        let executor = executor(&[("addr0", 10)]).await;
+        let nested = || Request::Batch {
+            requests: vec![Request::ListWallets],
+            atomic: false,
+        };
+        let subscribe = || Request::Subscribe { topics: Vec::new() };
+        for (atomic, first) in [(false, None), (true, Some(ErrorCode::Aborted))] {
+            let requests = vec![Request::ListWallets, nested(), subscribe()];
+            let responses = batch(&executor, requests, atomic).await;
+            let codes: Vec<_> = responses.iter().map(code).collect();
+            let wrong = Some(ErrorCode::InvalidRequest);
+            assert_eq!(codes, [first, wrong, wrong], "atomic: {}", atomic);
+        }
     }
 }
@@ -0,0 +1,94 @@
+use crate::{Error, ListenAddr, LoggingGuard};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+/// A handle to a running engine.
+///
+/// Dropping the handle does not stop the engine; call [`EngineHandle::shutdown`] and then
+/// await [`EngineHandle::join`] to stop it gracefully. Spans exported over OTLP as set up
+/// by [`Engine::start_with_config`](crate::Engine::start_with_config) stop with the handle.
+pub struct EngineHandle {
+    local_addr: ListenAddr,
+    http_addr: Option<ListenAddr>,
+    metrics_addr: Option<ListenAddr>,
+    shutdown: ShutdownTrigger,
+    join: JoinHandle<Result<(), Error>>,
+    logging: Option<LoggingGuard>,
+}
+
+impl EngineHandle {
+    pub(crate) fn new(
+        local_addr: ListenAddr,
+        http_addr: Option<ListenAddr>,
+        metrics_addr: Option<ListenAddr>,
+        shutdown: CancellationToken,
+        join: JoinHandle<Result<(), Error>>,
+    ) -> Self {
+        Self {
+            local_addr,
+            http_addr,
+            metrics_addr,
+            shutdown: ShutdownTrigger(shutdown),
+            join,
+            logging: None,
+        }
+    }
+
+    /// Keeps the logging the engine installed going as long as the handle lives.
+    pub(crate) fn with_logging(self, logging: Option<LoggingGuard>) -> Self {
+        Self { logging, ..self }
+    }
+
+    /// Returns the address the server is bound to, e.g. the port picked for `:0`.
+    ///
+    /// Its string form can be passed to `AumAPI::new`.
+    pub fn local_addr(&self) -> ListenAddr {
+        self.local_addr.clone()
+    }
+
+    /// Returns the address of the REST gateway, if [`EngineOptions::http_bind`] was set.
+    ///
+    /// [`EngineOptions::http_bind`]: crate::EngineOptions::http_bind
+    pub fn http_addr(&self) -> Option<ListenAddr> {
+        self.http_addr.clone()
+    }
+
+    /// Returns the address of the metrics endpoint, if [`EngineOptions::metrics_bind`] was
+    /// set.
+    ///
+    /// [`EngineOptions::metrics_bind`]: crate::EngineOptions::metrics_bind
+    pub fn metrics_addr(&self) -> Option<ListenAddr> {
+        self.metrics_addr.clone()
+    }
+
+    /// Returns a trigger that can stop the engine from elsewhere, e.g. a signal handler.
+    pub fn shutdown_trigger(&self) -> ShutdownTrigger {
+        self.shutdown.clone()
+    }
+
+    /// Stops accepting connections and starts draining in-flight requests.
+    pub fn shutdown(&self) {
+        self.shutdown.shutdown();
+    }
+
+    /// Waits until the engine has stopped.
+    pub async fn join(self) -> Result<(), Error> {
+        // Spans keep being exported until the engine has stopped.
+        let _logging = self.logging;
+        self.join.await?
+    }
+}
+
+/// A cloneable trigger that initiates a graceful engine shutdown.
+#[derive(Clone, Debug)]
+pub struct ShutdownTrigger(CancellationToken);
+
+impl ShutdownTrigger {
+    pub fn shutdown(&self) {
+        self.0.cancel();
+    }
+
+    pub fn is_shutdown(&self) -> bool {
+        self.0.is_cancelled()
+    }
+}
@@ -0,0 +1,338 @@
+use crate::{Error, auth::Identity};
+use aum_core::prelude::{ErrorCode, Response};
+use std::{
+    collections::{HashMap, VecDeque},
+    future::Future,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tokio::sync::watch;
+
+/// The parameters of a spend request, which a retry with the same key must repeat.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SpendParams {
+    pub from: Option<String>,
+    pub to: String,
+    pub amount: u64,
+}
+
+/// Idempotency keys are scoped to the API key that used them.
+type Scope = (Option<String>, String);
+
+/// How the first request with a key ended.
+#[derive(Clone, Debug)]
+enum Outcome {
+    Succeeded(Response),
+    /// The request failed without moving funds.
+    Failed {
+        code: ErrorCode,
+        message: String,
+    },
+    /// The request failed or was dropped in a way that leaves open whether funds moved.
+    Unknown,
+}
+
+struct Entry {
+    params: SpendParams,
+    /// `None` while the first request is in flight; retries wait for it.
+    outcome: watch::Sender<Option<Outcome>>,
+}
+
+#[derive(Default)]
+struct Entries {
+    by_key: HashMap<Scope, Entry>,
+    /// Keys of completed requests in the order they completed, for expiring them.
+    order: VecDeque<(Instant, Scope)>,
+}
+
+/// What [`IdempotencyStore::run`] did for a request.
+pub enum Attempt {
+    /// The request was the first with its key and was sent.
+    Sent(Result<Response, Error>),
+    /// An earlier request with the same key answered this one, which wasn't sent.
+    Replayed(Result<Response, Error>),
+}
+
+/// Remembers how spend requests ended by idempotency key, for `window` after they did.
+pub struct IdempotencyStore {
+    window: Duration,
+    entries: Mutex<Entries>,
+}
+
+impl IdempotencyStore {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            entries: Mutex::default(),
+        }
+    }
+
+    /// Runs `send` once per key: retries with the same key and parameters get the outcome
+    /// of the first run, waiting for it if it is still in flight.
+    ///
+    /// A retry after a failure that didn't move funds gets the same failure. If the first
+    /// run failed in the backend in a way that may have moved funds, or was dropped before
+    /// it finished, retries fail with [`Error::OutcomeUnknown`] instead of sending again.
+    pub async fn run<F, Fut>(
+        &self,
+        identity: &Identity,
+        key: String,
+        params: SpendParams,
+        send: F,
+    ) -> Result<Attempt, Error>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Response, Error>>,
+    {
+        let scope = (identity.key_id.clone(), key);
+        let mut outcome = match self.claim(&scope, params)? {
+            Claim::First => {
+                let mut pending = Pending {
+                    store: self,
+                    scope,
+                    outcome: Outcome::Unknown,
+                };
+                let result = send().await;
+                pending.outcome = Outcome::of(&result);
+                return Ok(Attempt::Sent(result));
+            }
+            Claim::Retry(outcome) => outcome,
+        };
+        let outcome = outcome
+            .wait_for(Option::is_some)
+            .await
+            .ok()
+            .and_then(|outcome| outcome.clone())
+            .unwrap_or(Outcome::Unknown);
+        Ok(Attempt::Replayed(outcome.replay(scope.1)))
+    }
+
+    fn claim(&self, scope: &Scope, params: SpendParams) -> Result<Claim, Error> {
+        let mut entries = self.entries.lock().unwrap();
+        entries.expire(Instant::now(), self.window);
+
+        if let Some(entry) = entries.by_key.get(scope) {
+            if entry.params != params {
+                return Err(Error::IdempotencyConflict(scope.1.clone()));
+            }
+            return Ok(Claim::Retry(entry.outcome.subscribe()));
+        }
+        entries.by_key.insert(
+            scope.clone(),
+            Entry {
+                params,
+                outcome: watch::Sender::new(None),
+            },
+        );
+        Ok(Claim::First)
+    }
+
+    /// Records how the first request with a key ended and starts its window.
+    fn complete(&self, scope: &Scope, outcome: Outcome) {
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(entry) = entries.by_key.get(scope) {
+            entry.outcome.send_replace(Some(outcome));
+            entries.order.push_back((Instant::now(), scope.clone()));
+        }
+    }
+}
+
+enum Claim {
+    First,
+    Retry(watch::Receiver<Option<Outcome>>),
+}
+
+/// Completes the entry of a request in flight, also when the request is dropped.
+struct Pending<'a> {
+    store: &'a IdempotencyStore,
+    scope: Scope,
+    outcome: Outcome,
+}
+
+impl Drop for Pending<'_> {
+    fn drop(&mut self) {
+        let outcome = std::mem::replace(&mut self.outcome, Outcome::Unknown);
+        self.store.complete(&self.scope, outcome);
+    }
+}
+
+impl Outcome {
+    fn of(result: &Result<Response, Error>) -> Self {
+        match result {
+            Ok(response) => Outcome::Succeeded(response.clone()),
+            // The backend may have sent the transaction before failing.
+            Err(Error::CoreError(e))
+                if matches!(e.code(), ErrorCode::TransactionFailed | ErrorCode::Internal) =>
+            {
+                Outcome::Unknown
+            }
+            Err(Error::TaskError(_)) => Outcome::Unknown,
+            Err(e) => Outcome::Failed {
+                code: e.code(),
+                message: e.to_string(),
+            },
+        }
+    }
+
+    fn replay(self, key: String) -> Result<Response, Error> {
+        match self {
+            Outcome::Succeeded(response) => Ok(response),
+            Outcome::Failed { code, message } => Err(Error::Replayed { code, message }),
+            Outcome::Unknown => Err(Error::OutcomeUnknown(key)),
+        }
+    }
+}
+
+impl Entries {
+    fn expire(&mut self, now: Instant, window: Duration) {
+        while let Some((completed, _)) = self.order.front() {
+            if now.saturating_duration_since(*completed) < window {
+                break;
+            }
+            let (_, scope) = self.order.pop_front().unwrap();
+            self.by_key.remove(&scope);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aum_core::errors::{WalletError, WalletManagerError};
+
+    fn params(amount: u64) -> SpendParams {
+        SpendParams {
+            from: None,
+            to: "addr".to_owned(),
+            amount,
+        }
+    }
+
+    fn sent(txid: &str) -> Result<Response, Error> {
+        Ok(Response::SendTransaction {
+            txid: txid.to_owned(),
+        })
+    }
+
+    fn backend_error() -> Error {
+        let e: Box<dyn std::error::Error + Send + Sync> = "connection reset".into();
+        Error::CoreError(WalletManagerError::from(e).into())
+    }
+
+    async fn run(
+        store: &IdempotencyStore,
+        key: &str,
+        amount: u64,
+        result: Result<Response, Error>,
+    ) -> Result<Attempt, Error> {
+        let identity = Identity::new("ops", Vec::new());
+        store
+            .run(&identity, key.to_owned(), params(amount), || async {
+                result
+            })
+            .await
+    }
+
+    fn txid(attempt: Attempt) -> (bool, String) {
+        match attempt {
+            Attempt::Sent(Ok(Response::SendTransaction { txid })) => (false, txid),
+            Attempt::Replayed(Ok(Response::SendTransaction { txid })) => (true, txid),
+            _ => panic!("expected a txid"),
+        }
+    }
+
+    #[tokio::test]
+    async fn retry_replays_the_first_response() {
+        let store = IdempotencyStore::new(Duration::from_secs(60));
+        let first = run(&store, "k", 5, sent("tx1")).await.unwrap();
+        let retry = run(&store, "k", 5, sent("tx2")).await.unwrap();
+        assert_eq!(txid(first), (false, "tx1".to_owned()));
+        assert_eq!(txid(retry), (true, "tx1".to_owned()));
+    }
+
+    #[tokio::test]
+    async fn different_parameters_conflict() {
+        let store = IdempotencyStore::new(Duration::from_secs(60));
+        run(&store, "k", 5, sent("tx1")).await.unwrap();
+        let conflict = run(&store, "k", 6, sent("tx2")).await;
+        assert!(matches!(conflict, Err(Error::IdempotencyConflict(key)) if key == "k"));
+    }
+
+    #[tokio::test]
+    async fn definite_failure_is_replayed() {
+        let store = IdempotencyStore::new(Duration::from_secs(60));
+        let insufficient = Error::CoreError(WalletError::InsufficientBalance.into());
+        run(&store, "k", 5, Err(insufficient)).await.unwrap();
+        match run(&store, "k", 5, sent("tx1")).await.unwrap() {
+            Attempt::Replayed(Err(e)) => assert_eq!(e.code(), ErrorCode::InsufficientBalance),
+            _ => panic!("expected the first failure"),
+        }
+    }
+
+    #[tokio::test]
+    async fn ambiguous_failure_is_not_resent() {
+        let store = IdempotencyStore::new(Duration::from_secs(60));
+        run(&store, "k", 5, Err(backend_error())).await.unwrap();
+        let retry = run(&store, "k", 5, sent("tx1")).await.unwrap();
+        assert!(matches!(
+            retry,
+            Attempt::Replayed(Err(Error::OutcomeUnknown(_)))
+        ));
+    }
+
+    #[tokio::test]
+    async fn dropped_request_leaves_the_outcome_unknown() {
+        let store = IdempotencyStore::new(Duration::from_secs(60));
+        let identity = Identity::new("ops", Vec::new());
+        let dropped = store.run(&identity, "k".to_owned(), params(5), || {
+            std::future::pending::<Result<Response, Error>>()
+        });
+        assert!(
+            tokio::time::timeout(Duration::from_millis(10), dropped)
+                .await
+                .is_err()
+        );
+        let retry = run(&store, "k", 5, sent("tx1")).await.unwrap();
+        assert!(matches!(
+            retry,
+            Attempt::Replayed(Err(Error::OutcomeUnknown(_)))
+        ));
+    }
+
+    #[tokio::test]
+    async fn retry_waits_for_the_request_in_flight() {
+        let store = IdempotencyStore::new(Duration::from_secs(60));
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        let identity = Identity::new("ops", Vec::new());
+        let first = store.run(&identity, "k".to_owned(), params(5), || async {
+            rx.await.unwrap();
+            sent("tx1")
+        });
+        let retry = async {
+            tokio::task::yield_now().await;
+            let retry = run(&store, "k", 5, sent("tx2"));
+            tx.send(()).unwrap();
+            retry.await
+        };
+        let (first, retry) = tokio::join!(first, retry);
+        assert_eq!(txid(first.unwrap()), (false, "tx1".to_owned()));
+        assert_eq!(txid(retry.unwrap()), (true, "tx1".to_owned()));
+    }
+
+    #[tokio::test]
+    async fn window_starts_when_the_request_completes() {
+        let store = IdempotencyStore::new(Duration::from_millis(50));
+        let identity = Identity::new("ops", Vec::new());
+        let slow = store.run(&identity, "k".to_owned(), params(5), || async {
+            tokio::time::sleep(Duration::from_millis(80)).await;
+            sent("tx1")
+        });
+        assert_eq!(txid(slow.await.unwrap()), (false, "tx1".to_owned()));
+        let retry = run(&store, "k", 5, sent("tx2")).await.unwrap();
+        assert_eq!(txid(retry), (true, "tx1".to_owned()));
+
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        let later = run(&store, "k", 5, sent("tx2")).await.unwrap();
+        assert_eq!(txid(later), (false, "tx2".to_owned()));
+    }
+}
@@ -1,75 +1,689 @@
-use crate::{Error, executor};
-use aum_core::prelude::{Request, Response};
-use futures_util::{SinkExt, StreamExt};
-use std::sync::Arc;
-use tokio::net::TcpListener;
-use tokio_tungstenite::{accept_async, tungstenite::protocol::Message};
-use tracing::error;
-pub struct Server;
+use crate::{
+    Error,
+    auth::{self, Identity, KeyStore, PeerCredentials},
+    executor::Executor,
+    options::ConnectionLimits,
+    ratelimit::{ConnectionLimiter, RateLimiter},
+    transport::{self, ListenAddr, Listener},
+};
+use aum_core::errors::ProtocolError;
+use aum_core::jsonrpc::{self, Id, Incoming, RpcResponse};
+use aum_core::prelude::{
+    ErrorCode, Event, Protocol, Request, RequestEnvelope, Response, ResponseEnvelope, Topic,
+};
+use futures_util::{SinkExt, StreamExt, stream};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    future::Future,
+    io,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context as TaskContext, Poll, ready},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    sync::{broadcast, mpsc},
+    task::{JoinHandle, JoinSet},
+    time::{Instant, Sleep},
+};
+use tokio_rustls::TlsAcceptor;
+use tokio_tungstenite::{
+    accept_async, accept_hdr_async_with_config,
+    tungstenite::{
+        Error as WsError,
+        handshake::server::{ErrorResponse, Request as WsRequest, Response as WsResponse},
+        http::{StatusCode, header},
+        protocol::{CloseFrame, Message, WebSocketConfig, frame::coding::CloseCode},
+    },
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{Instrument, Span, debug, error, warn};
+pub struct Server {
+    listener: Listener,
+    tls: Option<TlsAcceptor>,
+    key_store: Option<Arc<dyn KeyStore>>,
+}
+
+/// State shared by every connection of a server.
+struct Context<S, Wm, M>
+where
+    S: aum_core::prelude::Storage + Send + 'static,
+    Wm: aum_core::prelude::AsyncWalletManager + Send + 'static,
+    M: aum_core::prelude::Monitor<WalletManager = Wm> + Send + 'static,
+{
+    executor: Arc<Executor<S, Wm, M>>,
+    key_store: Option<Arc<dyn KeyStore>>,
+    limiter: Arc<RateLimiter>,
+    limits: ConnectionLimits,
+    shutdown: CancellationToken,
+}
 
 impl Server {
-    pub async fn new<S, Wm, M>(
+    /// Binds `bind`, a TCP address or a `unix:` socket path created with `unix_mode`.
+    pub async fn bind(
         bind: &str,
-        executor: Arc<crate::executor::Executor<S, Wm, M>>,
-    ) -> Result<Self, Error>
-    where
+        unix_mode: u32,
+        tls: Option<TlsAcceptor>,
+        key_store: Option<Arc<dyn KeyStore>>,
+    ) -> Result<Self, Error> {
+        let listener = Listener::bind(bind, unix_mode).await?;
+        Ok(Self {
+            listener,
+            tls,
+            key_store,
+        })
+    }
+
+    pub fn local_addr(&self) -> std::io::Result<ListenAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Serves WebSocket connections until `shutdown` is cancelled, then drains them.
+    pub async fn serve<S, Wm, M>(
+        self,
+        executor: Arc<Executor<S, Wm, M>>,
+        limiter: Arc<RateLimiter>,
+        limits: ConnectionLimits,
+        shutdown: CancellationToken,
+        drain_timeout: Duration,
+    ) where
         S: aum_core::prelude::Storage + Send + 'static + Sync,
-        Wm: aum_core::prelude::WalletManager + Send + 'static + Sync,
+        Wm: aum_core::prelude::AsyncWalletManager + Send + 'static + Sync,
         M: aum_core::prelude::Monitor<WalletManager = Wm> + Send + 'static + Sync,
     {
-        let listener = TcpListener::bind(bind).await?;
-
-        while let Ok((stream, _)) = listener.accept().await {
-            let executor = Arc::clone(&executor);
-            tokio::spawn(async move {
-                let ws_stream = match accept_async(stream).await {
-                    Ok(ws_stream) => ws_stream,
-                    Err(e) => {
-                        eprintln!("Error during WebSocket handshake: {}", e);
-                        return;
+        let context = Arc::new(Context {
+            executor,
+            key_store: self.key_store.clone(),
+            limiter,
+            limits,
+            shutdown: shutdown.clone(),
+        });
+        let tls = self.tls.clone();
+        let reject_tls = self.tls.clone();
+        transport::accept_loop(
+            self.listener,
+            &shutdown,
+            drain_timeout,
+            limits.max_connections,
+            |stream| {
+                let tls = tls.clone();
+                let context = Arc::clone(&context);
+                async move {
+                    // One deadline covers the TLS and WebSocket handshakes.
+                    let deadline = Instant::now() + limits.handshake_timeout;
+                    let peer = stream.peer_credentials();
+                    match transport::accept_tls(stream, tls.as_ref(), limits.handshake_timeout)
+                        .await
+                    {
+                        Ok(stream) => handle_connection(stream, peer, deadline, context).await,
+                        Err(e) => error!("Error during TLS handshake: {}", e),
                     }
-                };
-                let (mut write, mut read) = ws_stream.split();
-                while let Some(Ok(msg)) = read.next().await {
-                    match msg {
-                        Message::Text(text) => {
-                            let string = text.to_string();
-                            let req: Request = match serde_json::from_str(&string) {
-                                Ok(req) => req,
-                                Err(_) => {
-                                    if let Err(e) =
-                                        write.send(error_into_message(Error::WrongRequest)).await
-                                    {
-                                        error_websocket(e);
-                                    }
-                                    continue;
-                                }
-                            };
-                            let response = match executor.execute(req).await {
-                                Ok(response) => response_into_message(response),
-                                Err(e) => error_into_message(e),
-                            };
-                        }
-                        Message::Ping(_) => {
-                            if let Err(e) = write.send(Message::Pong((&[] as &[u8]).into())).await {
-                                error_websocket(e);
+                }
+            },
+            |stream| reject_connection(stream, reject_tls.clone(), limits.handshake_timeout),
+        )
+        .await;
+    }
+}
+
+/// Turns away a client over the connection limit: completes the handshakes without
+/// authenticating, then closes with "Try Again Later" (1013).
+async fn reject_connection(stream: transport::Socket, tls: Option<TlsAcceptor>, timeout: Duration) {
+    let deadline = Instant::now() + timeout;
+    let stream = match transport::accept_tls(stream, tls.as_ref(), timeout).await {
+        Ok(stream) => stream,
+        Err(e) => {
+            debug!("Error during TLS handshake: {}", e);
+            return;
+        }
+    };
+    let handshake = accept_async(HandshakeDeadline::new(stream, deadline));
+    let Ok(Ok(mut ws_stream)) = tokio::time::timeout_at(deadline, handshake).await else {
+        return;
+    };
+    ws_stream.get_mut().disarm();
+    let close = close_frame(CloseCode::Again, "Too many connections, try again later");
+    // Waits for the client to acknowledge the close, so that requests it sent meanwhile
+    // don't reset the connection before it read the close frame.
+    let closing = async {
+        ws_stream.close(Some(close)).await?;
+        while ws_stream.next().await.transpose()?.is_some() {}
+        Ok::<_, WsError>(())
+    };
+    let _ = tokio::time::timeout(timeout, closing).await;
+}
+
+/// The response to a client that didn't send its upgrade request in time.
+const REQUEST_TIMEOUT: &[u8] =
+    b"HTTP/1.1 408 Request Timeout\r\nConnection: close\r\nContent-Length: 0\r\n\r\n";
+
+/// A stream that answers a client which hasn't sent its WebSocket upgrade request by a
+/// deadline with "408 Request Timeout", then fails the handshake.
+struct HandshakeDeadline<T> {
+    stream: T,
+    /// `None` once the handshake is done.
+    deadline: Option<Pin<Box<Sleep>>>,
+    /// How much of the 408 response has been written.
+    written: usize,
+}
+
+impl<T> HandshakeDeadline<T> {
+    fn new(stream: T, deadline: Instant) -> Self {
+        Self {
+            stream,
+            deadline: Some(Box::pin(tokio::time::sleep_until(deadline))),
+            written: 0,
+        }
+    }
+
+    /// Stops the deadline, once the handshake succeeded.
+    fn disarm(&mut self) {
+        self.deadline = None;
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> AsyncRead for HandshakeDeadline<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let expired = match &mut this.deadline {
+            Some(deadline) => deadline.as_mut().poll(cx).is_ready(),
+            None => false,
+        };
+        if !expired {
+            return Pin::new(&mut this.stream).poll_read(cx, buf);
+        }
+        while this.written < REQUEST_TIMEOUT.len() {
+            let unwritten = &REQUEST_TIMEOUT[this.written..];
+            match ready!(Pin::new(&mut this.stream).poll_write(cx, unwritten))? {
+                0 => break,
+                written => this.written += written,
+            }
+        }
+        ready!(Pin::new(&mut this.stream).poll_flush(cx))?;
+        Poll::Ready(Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "the WebSocket handshake timed out",
+        )))
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for HandshakeDeadline<T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_shutdown(cx)
+    }
+}
+
+/// Serves a single WebSocket connection.
+///
+/// Every request is executed on its own task, so replies are written back as soon as they
+/// are ready and may arrive out of order; the envelope id tells the client which is which.
+async fn handle_connection<T, S, Wm, M>(
+    stream: T,
+    peer: Option<PeerCredentials>,
+    handshake_deadline: Instant,
+    context: Arc<Context<S, Wm, M>>,
+) where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    S: aum_core::prelude::Storage + Send + 'static + Sync,
+    Wm: aum_core::prelude::AsyncWalletManager + Send + 'static + Sync,
+    M: aum_core::prelude::Monitor<WalletManager = Wm> + Send + 'static + Sync,
+{
+    let mut identity = Identity::anonymous();
+    let mut protocol = Protocol::Native;
+    // The error type is dictated by tungstenite's handshake callback.
+    #[allow(clippy::result_large_err)]
+    let authenticate = |req: &WsRequest, mut res: WsResponse| {
+        identity = auth::authenticate(context.key_store.as_deref(), req.headers(), peer)
+            .map_err(|e| error_into_http(StatusCode::UNAUTHORIZED, e))?;
+        if let Some(negotiated) = negotiate_protocol(req) {
+            protocol = negotiated;
+            res.headers_mut().insert(
+                header::SEC_WEBSOCKET_PROTOCOL,
+                header::HeaderValue::from_static(negotiated.subprotocol()),
+            );
+        }
+        Ok(res)
+    };
+    let limits = context.limits;
+    let config = WebSocketConfig::default()
+        .max_message_size(Some(limits.max_message_size))
+        .max_frame_size(Some(limits.max_frame_size));
+    let stream = HandshakeDeadline::new(stream, handshake_deadline);
+    let handshake = accept_hdr_async_with_config(stream, authenticate, Some(config));
+    let mut ws_stream = match tokio::time::timeout_at(handshake_deadline, handshake).await {
+        Ok(Ok(ws_stream)) => ws_stream,
+        Ok(Err(e)) => {
+            warn!("Error during WebSocket handshake: {}", e);
+            return;
+        }
+        Err(_) => {
+            warn!("Closing connection, the WebSocket handshake timed out");
+            return;
+        }
+    };
+    ws_stream.get_mut().disarm();
+    let _active = context.executor.metrics().connection();
+    let span = Span::current();
+    span.record(
+        "identity",
+        identity.key_id.as_deref().unwrap_or("<anonymous>"),
+    );
+    span.record("protocol", protocol.subprotocol());
+    debug!("Connection authenticated");
+    let (mut write, mut read) = ws_stream.split();
+    let (tx, mut rx) = mpsc::channel::<Message>(limits.send_queue);
+    let writer = tokio::spawn(
+        async move {
+            while let Some(msg) = rx.recv().await {
+                if let Err(e) = write.send(msg).await {
+                    error_websocket(e);
+                    break;
+                }
+            }
+        }
+        .in_current_span(),
+    );
+
+    let mut session = Session {
+        context: Arc::clone(&context),
+        identity: Arc::new(identity),
+        protocol,
+        rate_limiter: context.limiter.connection(),
+        tx,
+        topics: Arc::new(Mutex::new(HashSet::new())),
+        forwarder: None,
+        requests: JoinSet::new(),
+    };
+    let shutdown = &context.shutdown;
+    let mut deadline = limits.idle_timeout.map(|timeout| Instant::now() + timeout);
+    let close = loop {
+        // Reading stops while the connection has too many requests running, which pushes
+        // back on the client instead of queueing its requests without bound.
+        let saturated = session.requests.len() >= limits.max_in_flight;
+        let msg = tokio::select! {
+            _ = shutdown.cancelled() => {
+                break Some(close_frame(CloseCode::Away, "Server is shutting down"));
+            }
+            Some(_) = session.requests.join_next(), if !session.requests.is_empty() => {
+                deadline = limits.idle_timeout.map(|timeout| Instant::now() + timeout);
+                continue;
+            }
+            _ = idle(deadline), if session.requests.is_empty() => {
+                break Some(close_frame(CloseCode::Away, "Idle timeout"));
+            }
+            msg = read.next(), if !saturated => msg,
+        };
+        let msg = match msg {
+            Some(Ok(msg)) => msg,
+            Some(Err(WsError::Capacity(e))) => {
+                debug!("Closing connection: {}", e);
+                break Some(close_frame(CloseCode::Size, "Message too big"));
+            }
+            Some(Err(_)) | None => break None,
+        };
+        deadline = limits.idle_timeout.map(|timeout| Instant::now() + timeout);
+        match msg {
+            Message::Text(text) => session.on_message(text.as_bytes()).await,
+            Message::Binary(data) => session.on_message(&data).await,
+            Message::Ping(_) => session.send(Message::Pong((&[] as &[u8]).into())).await,
+            _ => continue,
+        }
+    };
+
+    // Subscriptions end with the connection.
+    if let Some(forwarder) = session.forwarder.take() {
+        forwarder.abort();
+    }
+    while session.requests.join_next().await.is_some() {}
+    if let Some(close) = close {
+        session.send(Message::Close(Some(close))).await;
+    }
+    // The writer finishes once the last sender is dropped and the queue is flushed.
+    drop(session);
+    let _ = writer.await;
+}
+
+/// Completes at `deadline`, or never if there is none.
+async fn idle(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+fn close_frame(code: CloseCode, reason: &'static str) -> CloseFrame {
+    CloseFrame {
+        code,
+        reason: reason.into(),
+    }
+}
+
+/// Picks the first protocol offered by the client that the server speaks.
+fn negotiate_protocol(req: &WsRequest) -> Option<Protocol> {
+    req.headers()
+        .get_all(header::SEC_WEBSOCKET_PROTOCOL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .find_map(Protocol::from_subprotocol)
+}
+
+/// The per-connection state of a WebSocket client.
+struct Session<S, Wm, M>
+where
+    S: aum_core::prelude::Storage + Send + 'static,
+    Wm: aum_core::prelude::AsyncWalletManager + Send + 'static,
+    M: aum_core::prelude::Monitor<WalletManager = Wm> + Send + 'static,
+{
+    context: Arc<Context<S, Wm, M>>,
+    identity: Arc<Identity>,
+    protocol: Protocol,
+    rate_limiter: ConnectionLimiter,
+    tx: mpsc::Sender<Message>,
+    topics: Arc<Mutex<HashSet<Topic>>>,
+    forwarder: Option<JoinHandle<()>>,
+    requests: JoinSet<()>,
+}
+
+impl<S, Wm, M> Session<S, Wm, M>
+where
+    S: aum_core::prelude::Storage + Send + 'static + Sync,
+    Wm: aum_core::prelude::AsyncWalletManager + Send + 'static + Sync,
+    M: aum_core::prelude::Monitor<WalletManager = Wm> + Send + 'static + Sync,
+{
+    /// Handles a text or binary message in the connection's protocol.
+    async fn on_message(&mut self, data: &[u8]) {
+        match self.protocol {
+            Protocol::JsonRpc => self.on_jsonrpc(&String::from_utf8_lossy(data)).await,
+            _ => self.on_envelope(data).await,
+        }
+    }
+
+    /// Queues a message for the client, waiting while the send queue is full.
+    async fn send(&self, message: Message) {
+        // Sending only fails once the writer has stopped, i.e. the client is gone.
+        let _ = self.tx.send(message).await;
+    }
+
+    async fn on_envelope(&mut self, data: &[u8]) {
+        let protocol = self.protocol;
+        let envelope: RequestEnvelope = match protocol.decode(data) {
+            Ok(envelope) => envelope,
+            Err(e) => {
+                // Reply to the request's id if it has one, e.g. when a newer client sends a
+                // request this engine doesn't know, so the client isn't left waiting.
+                let id = protocol.decode::<EnvelopeId>(data).ok().map(|e| e.id);
+                self.send(parse_error_into_message(protocol, id, e)).await;
+                return;
+            }
+        };
+        if let Err(e) = self.rate_limiter.check(&self.identity, &envelope.request) {
+            let metrics = self.context.executor.metrics();
+            metrics.record_rejected(envelope.request.kind(), e.code());
+            self.send(envelope_into_message(
+                protocol,
+                ResponseEnvelope::new(Some(envelope.id), e.into_response()),
+            ))
+            .await;
+            return;
+        }
+        if let Some(response) = self.subscription(&envelope.request) {
+            self.send(envelope_into_message(
+                protocol,
+                ResponseEnvelope::new(Some(envelope.id), response),
+            ))
+            .await;
+            return;
+        }
+        let context = Arc::clone(&self.context);
+        let identity = Arc::clone(&self.identity);
+        let tx = self.tx.clone();
+        self.requests.spawn(
+            async move {
+                let response = context
+                    .executor
+                    .execute(&identity, Some(envelope.id.to_string()), envelope.request)
+                    .await
+                    .unwrap_or_else(Error::into_response);
+                let _ = tx
+                    .send(envelope_into_message(
+                        protocol,
+                        ResponseEnvelope::new(Some(envelope.id), response),
+                    ))
+                    .await;
+            }
+            .in_current_span(),
+        );
+    }
+
+    /// Handles a JSON-RPC call, notification or batch.
+    ///
+    /// The calls of a batch run concurrently, at most `max_in_flight` at a time, and are
+    /// answered with a single array once all of them are done; notifications are executed
+    /// but never answered.
+    async fn on_jsonrpc(&mut self, text: &str) {
+        let (calls, batch) = match jsonrpc::parse(text) {
+            Ok(Incoming::Single(call)) => (vec![call], false),
+            Ok(Incoming::Batch(calls)) => (calls, true),
+            Err(e) => {
+                self.send(rpc_into_message(&RpcResponse::error(Id::Null, e)))
+                    .await;
+                return;
+            }
+        };
+
+        let mut replies = Vec::with_capacity(calls.len());
+        for call in calls {
+            let call = match call {
+                Ok(call) => call,
+                Err(e) => {
+                    replies.push(Reply::Ready(RpcResponse::error(Id::Null, e)));
+                    continue;
+                }
+            };
+            let request = match call.to_request() {
+                Ok(request) => request,
+                Err(e) => {
+                    if let Some(id) = call.id {
+                        replies.push(Reply::Ready(RpcResponse::error(id, e)));
+                    }
+                    continue;
+                }
+            };
+            let response = match self.rate_limiter.check(&self.identity, &request) {
+                Ok(()) => self.subscription(&request),
+                Err(e) => {
+                    let metrics = self.context.executor.metrics();
+                    metrics.record_rejected(request.kind(), e.code());
+                    Some(e.into_response())
+                }
+            };
+            match response {
+                Some(response) => replies.extend(
+                    call.id
+                        .map(|id| Reply::Ready(RpcResponse::new(id, response))),
+                ),
+                None => replies.push(Reply::Pending(call.id, request)),
+            }
+        }
+
+        let context = Arc::clone(&self.context);
+        let identity = Arc::clone(&self.identity);
+        let tx = self.tx.clone();
+        let max_in_flight = context.limits.max_in_flight;
+        self.requests.spawn(
+            async move {
+                let replies: Vec<_> = stream::iter(replies)
+                    .map(|reply| async {
+                        match reply {
+                            Reply::Ready(response) => Some(response),
+                            Reply::Pending(id, request) => {
+                                let client_id = id.as_ref().and_then(rpc_id);
+                                let response = context
+                                    .executor
+                                    .execute(&identity, client_id, request)
+                                    .await
+                                    .unwrap_or_else(Error::into_response);
+                                id.map(|id| RpcResponse::new(id, response))
                             }
-                            continue;
                         }
-                        _ => continue,
-                    }
+                    })
+                    .buffered(max_in_flight)
+                    .collect()
+                    .await;
+                let mut replies: Vec<RpcResponse> = replies.into_iter().flatten().collect();
+                let message = match replies.len() {
+                    0 => return,
+                    1 if !batch => rpc_into_message(&replies.remove(0)),
+                    _ => rpc_into_message(&replies),
+                };
+                let _ = tx.send(message).await;
+            }
+            .in_current_span(),
+        );
+    }
+
+    /// Applies `Subscribe` and `Unsubscribe` requests, returning `None` for other requests.
+    ///
+    /// The event forwarder is started on the first subscription of the connection.
+    fn subscription(&mut self, request: &Request) -> Option<Response> {
+        if !matches!(
+            request,
+            Request::Subscribe { .. } | Request::Unsubscribe { .. }
+        ) {
+            return None;
+        }
+        if let Err(e) = self.context.executor.authorize(&self.identity, request) {
+            return Some(e.into_response());
+        }
+        if self.forwarder.is_none() {
+            self.forwarder = Some(tokio::spawn(
+                forward_events(
+                    self.context.executor.events().subscribe(),
+                    Arc::clone(&self.topics),
+                    self.protocol,
+                    self.tx.clone(),
+                )
+                .in_current_span(),
+            ));
+        }
+        Some(update_topics(&self.topics, request.clone()))
+    }
+}
+
+/// A JSON-RPC reply that is either known up front or waits for the executor.
+enum Reply {
+    Ready(RpcResponse),
+    /// A request to execute, answered unless it is a notification without an id.
+    Pending(Option<Id>, Request),
+}
+
+/// Returns a JSON-RPC call id as the client id of its request.
+fn rpc_id(id: &Id) -> Option<String> {
+    match id {
+        Id::Number(n) => Some(n.to_string()),
+        Id::String(s) => Some(s.clone()),
+        Id::Null => None,
+    }
+}
+
+/// Applies a `Subscribe` or `Unsubscribe` request and returns the resulting topics.
+fn update_topics(topics: &Mutex<HashSet<Topic>>, req: Request) -> Response {
+    let mut topics = topics.lock().unwrap();
+    match req {
+        Request::Subscribe { topics: added } => topics.extend(added),
+        Request::Unsubscribe { topics: removed } => topics.retain(|topic| !removed.contains(topic)),
+        _ => {}
+    }
+    Response::Subscribed {
+        topics: topics.iter().copied().collect(),
+    }
+}
+
+/// Pushes events for the connection's subscribed topics until the connection goes away.
+async fn forward_events(
+    mut events: broadcast::Receiver<Event>,
+    topics: Arc<Mutex<HashSet<Topic>>>,
+    protocol: Protocol,
+    tx: mpsc::Sender<Message>,
+) {
+    loop {
+        match events.recv().await {
+            Ok(event) => {
+                if !topics.lock().unwrap().contains(&event.topic()) {
+                    continue;
+                }
+                let message = match protocol {
+                    Protocol::JsonRpc => rpc_into_message(&jsonrpc::event_notification(&event)),
+                    protocol => envelope_into_message(
+                        protocol,
+                        ResponseEnvelope::new(None, Response::Event { event }),
+                    ),
+                };
+                if tx.send(message).await.is_err() {
+                    break;
                 }
-            });
+            }
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                warn!("Subscriber lagged behind, {} event(s) dropped", skipped);
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
         }
-        Ok(Self {})
     }
 }
 
-fn error_into_message(e: Error) -> Message {
-    Message::Text(e.to_string().into())
+/// The id of a request envelope whose request could not be parsed.
+#[derive(Deserialize)]
+struct EnvelopeId {
+    id: u64,
+}
+
+fn parse_error_into_message(protocol: Protocol, id: Option<u64>, e: ProtocolError) -> Message {
+    let response = Response::Error {
+        code: ErrorCode::InvalidRequest,
+        message: Error::WrongRequest.to_string(),
+        details: Some(serde_json::json!({ "reason": e.to_string() })),
+    };
+    envelope_into_message(protocol, ResponseEnvelope::new(id, response))
+}
+/// Builds the HTTP reply that rejects a WebSocket upgrade, with the error as a JSON body.
+fn error_into_http(status: StatusCode, e: Error) -> ErrorResponse {
+    let mut res = ErrorResponse::new(Some(e.into_response().to_string()));
+    *res.status_mut() = status;
+    res.headers_mut().insert(
+        header::CONTENT_TYPE,
+        header::HeaderValue::from_static("application/json"),
+    );
+    res
+}
+fn envelope_into_message(protocol: Protocol, envelope: ResponseEnvelope) -> Message {
+    let data = protocol.encode(&envelope).unwrap();
+    if protocol.is_binary() {
+        Message::Binary(data.into())
+    } else {
+        Message::Text(String::from_utf8(data).unwrap().into())
+    }
 }
-fn response_into_message(res: Response) -> Message {
-    Message::Text(res.to_string().into())
+fn rpc_into_message<T: Serialize>(message: &T) -> Message {
+    Message::Text(serde_json::to_string(message).unwrap().into())
 }
 fn error_websocket(e: tokio_tungstenite::tungstenite::Error) {
     error!("WebSocket error: {}", e);
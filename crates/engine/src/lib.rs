@@ -1,27 +1,269 @@
+mod audit;
+mod auth;
+mod blocking;
+mod config;
+mod cron;
 mod errors;
+mod events;
 mod executor;
+mod handle;
+mod idempotency;
 mod interface;
+mod metrics;
+mod options;
+mod ratelimit;
+mod rest;
 mod runtime;
+mod scheduler;
+mod supervisor;
+mod telemetry;
+#[cfg(test)]
+mod testing;
+mod tls;
+mod transport;
+mod wallets;
+pub use audit::{AuditHead, AuditLink, AuditLog, verify_audit_log};
+pub use auth::{
+    AUTHORIZATION_SCHEME, Identity, KeyStore, MemoryKeyStore, PeerCredentials, Scope,
+    generate_secret, hash_secret,
+};
+pub use blocking::{BlockingWallet, BlockingWalletManager};
+pub use config::{
+    AuthConfig, ENV_PREFIX, EngineConfig, KeyConfig, ListenConfig, LogFormat, LoggingConfig,
+    PeerConfig, PoliciesConfig, SchedulerConfig,
+};
 pub use errors::Error;
+pub use handle::{EngineHandle, ShutdownTrigger};
+pub use options::{ConnectionLimits, EngineOptions};
+pub use ratelimit::{BudgetLimits, RateLimit, RateLimits};
+pub use runtime::Runtime;
+pub use scheduler::{JobError, JobOptions, Schedule, Scheduler};
+pub use supervisor::SupervisorOptions;
+pub use telemetry::LoggingGuard;
+#[cfg(feature = "otlp")]
+pub use telemetry::OtlpExporter;
+pub use tls::TlsConfig;
+pub use transport::ListenAddr;
+pub use wallets::Wallets;
 
+use std::sync::Arc;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, warn};
+
+/// Serves the AUM protocol over WebSocket, and optionally over HTTP.
+///
+/// Connections and requests are traced with `tracing` spans: `connection`, `request` with
+/// its kind, id, the client's envelope id, caller, duration and outcome, and `backend` for
+/// each wallet manager or monitor call. With the `otlp` feature, `OtlpExporter` exports
+/// them to an OpenTelemetry collector.
 pub struct Engine;
 
 impl Engine {
+    /// Starts the engine on `bind`, either a TCP address such as `127.0.0.1:9000` or a Unix
+    /// domain socket path prefixed with `unix:`, e.g. `unix:/run/aum/engine.sock`.
+    ///
+    /// The engine starts the monitor of `runtime` and restarts it when it fails, see
+    /// [`SupervisorOptions`].
     pub async fn start<S, Wm, M>(
         bind: &str,
         runtime: runtime::Runtime<S, Wm, M>,
-    ) -> Result<Self, Error>
+    ) -> Result<EngineHandle, Error>
     where
         S: aum_core::prelude::Storage + Send + 'static + Sync,
-        Wm: aum_core::prelude::WalletManager + Send + 'static + Sync,
+        Wm: aum_core::prelude::AsyncWalletManager + Send + 'static + Sync,
         M: aum_core::prelude::Monitor<WalletManager = Wm> + Send + 'static + Sync,
     {
-        let executor = executor::Executor::new(runtime);
-        interface::Server::new(bind, executor).await?;
-        Ok(Self {})
+        Self::start_with(bind, runtime, EngineOptions::default()).await
+    }
+
+    /// Starts the engine on `config.listen.bind` with the options described by `config`.
+    ///
+    /// Logging is set up as `config.logging` describes, unless the application installed a
+    /// `tracing` subscriber before; the handle keeps it going until it is dropped.
+    pub async fn start_with_config<S, Wm, M>(
+        runtime: runtime::Runtime<S, Wm, M>,
+        config: &EngineConfig,
+    ) -> Result<EngineHandle, Error>
+    where
+        S: aum_core::prelude::Storage + Send + 'static + Sync,
+        Wm: aum_core::prelude::AsyncWalletManager + Send + 'static + Sync,
+        M: aum_core::prelude::Monitor<WalletManager = Wm> + Send + 'static + Sync,
+    {
+        let options = config.engine_options()?;
+        let logging = config.logging.install()?;
+        for name in &config.ignored_env {
+            warn!(
+                "Ignoring the environment variable {}, it names no config key",
+                name
+            );
+        }
+        debug!("Effective config:\n{}", config.redacted());
+        let handle = Self::start_with(&config.listen.bind, runtime, options).await?;
+        Ok(handle.with_logging(logging))
+    }
+
+    /// Starts the engine on `bind` with `options`, failing if they don't pass
+    /// [`EngineOptions::validate`].
+    pub async fn start_with<S, Wm, M>(
+        bind: &str,
+        runtime: runtime::Runtime<S, Wm, M>,
+        options: EngineOptions,
+    ) -> Result<EngineHandle, Error>
+    where
+        S: aum_core::prelude::Storage + Send + 'static + Sync,
+        Wm: aum_core::prelude::AsyncWalletManager + Send + 'static + Sync,
+        M: aum_core::prelude::Monitor<WalletManager = Wm> + Send + 'static + Sync,
+    {
+        options.validate()?;
+        let tls = options.tls.as_ref().map(TlsConfig::acceptor).transpose()?;
+        let server = interface::Server::bind(
+            bind,
+            options.unix_socket_mode,
+            tls.clone(),
+            options.key_store.clone(),
+        )
+        .await?;
+        let local_addr = server.local_addr()?;
+        let http = match &options.http_bind {
+            Some(http_bind) => Some(
+                rest::HttpServer::bind(
+                    http_bind,
+                    options.unix_socket_mode,
+                    tls,
+                    options.key_store.clone(),
+                )
+                .await?,
+            ),
+            None => None,
+        };
+        let http_addr = http
+            .as_ref()
+            .map(rest::HttpServer::local_addr)
+            .transpose()?;
+        let metrics = match &options.metrics_bind {
+            Some(metrics_bind) => {
+                Some(metrics::MetricsServer::bind(metrics_bind, options.unix_socket_mode).await?)
+            }
+            None => None,
+        };
+        let metrics_addr = metrics
+            .as_ref()
+            .map(metrics::MetricsServer::local_addr)
+            .transpose()?;
+        let supervised = options.monitor_supervisor.enabled;
+        let capabilities = executor::Capabilities {
+            network: options.network.clone(),
+            features: options.features(),
+            supervised,
+        };
+        let audit = options
+            .audit_log
+            .as_ref()
+            .map(audit::AuditLog::open)
+            .transpose()?;
+        let audited = audit.is_some();
+        if supervised {
+            runtime.set_supervised(true)?;
+        }
+        if let Err(e) = runtime.set_audit_log(audit.map(Arc::new)) {
+            if supervised {
+                runtime.set_supervised(false)?;
+            }
+            return Err(e);
+        }
+        let executor = executor::Executor::new(runtime, capabilities, options.idempotency_window);
+        let engine_jobs = scheduler::register_engine_jobs(&executor, &options.jobs);
+        let limiter = ratelimit::RateLimiter::new(options.rate_limits);
+        let shutdown = CancellationToken::new();
+
+        let join = tokio::spawn({
+            let shutdown = shutdown.clone();
+            async move {
+                let supervisor = supervised.then(|| {
+                    tokio::spawn(supervisor::supervise(
+                        Arc::clone(&executor),
+                        options.monitor_supervisor,
+                        shutdown.clone(),
+                    ))
+                });
+                let jobs = tokio::spawn(scheduler::run(
+                    executor.runtime().clone(),
+                    shutdown.clone(),
+                    options.shutdown_timeout,
+                ));
+                let watcher = tokio::spawn(events::watch(
+                    Arc::clone(&executor),
+                    options.event_poll_interval,
+                    shutdown.clone(),
+                ));
+                let http = http.map(|http| {
+                    tokio::spawn(http.serve(
+                        Arc::clone(&executor),
+                        Arc::clone(&limiter),
+                        options.connection_limits,
+                        shutdown.clone(),
+                        options.shutdown_timeout,
+                    ))
+                });
+                let metrics = metrics.map(|metrics| {
+                    tokio::spawn(metrics.serve(
+                        Arc::clone(&executor),
+                        options.connection_limits,
+                        shutdown.clone(),
+                        options.shutdown_timeout,
+                    ))
+                });
+                server
+                    .serve(
+                        Arc::clone(&executor),
+                        limiter,
+                        options.connection_limits,
+                        shutdown,
+                        options.shutdown_timeout,
+                    )
+                    .await;
+                // A task that panicked doesn't stop the others from being awaited or the
+                // runtime from being released; the first failure is returned afterwards.
+                let tasks = http
+                    .into_iter()
+                    .chain(metrics)
+                    .chain([watcher, jobs])
+                    .chain(supervisor);
+                let mut result = Ok(());
+                for task in tasks {
+                    result = result.and(task.await.map_err(Error::from));
+                }
+                for name in engine_jobs {
+                    executor.runtime().scheduler().unregister(name);
+                }
+                if supervised {
+                    result = result.and(executor.runtime().set_supervised(false));
+                }
+                if audited {
+                    result = result.and(executor.runtime().set_audit_log(None));
+                }
+                let monitor = executor.runtime().monitor();
+                if monitor.is_running() {
+                    let stopped = monitor.stop().map_err(aum_core::prelude::Error::from);
+                    result = result.and(stopped.map_err(Error::from));
+                }
+                result
+            }
+        });
+        Ok(EngineHandle::new(
+            local_addr,
+            http_addr,
+            metrics_addr,
+            shutdown,
+            join,
+        ))
     }
 }
 
+/// Creates the runtime an engine serves.
+///
+/// A synchronous [`WalletManager`](aum_core::prelude::WalletManager) is passed wrapped in a
+/// [`BlockingWalletManager`].
 pub async fn create_runtime<S, Wm, M>(
     storage: S,
     wallet_manager: Wm,
@@ -29,7 +271,7 @@ pub async fn create_runtime<S, Wm, M>(
 ) -> runtime::Runtime<S, Wm, M>
 where
     S: aum_core::prelude::Storage + Send + 'static + Sync,
-    Wm: aum_core::prelude::WalletManager + Send + 'static + Sync,
+    Wm: aum_core::prelude::AsyncWalletManager + Send + 'static + Sync,
     M: aum_core::prelude::Monitor<WalletManager = Wm> + Send + 'static + Sync,
 {
     runtime::Runtime::new(storage, wallet_manager, monitor).await
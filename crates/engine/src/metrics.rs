@@ -0,0 +1,289 @@
+use crate::{
+    Error,
+    executor::Executor,
+    options::ConnectionLimits,
+    rest,
+    transport::{ListenAddr, Listener},
+};
+use aum_core::prelude::{AsyncWalletManager, ErrorCode};
+use axum::{Router, extract::State, http::header, response::IntoResponse, routing::get};
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
+
+/// Upper bounds in seconds of the request latency histogram buckets.
+const LATENCY_BUCKETS: [f64; 12] = [
+    0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Counters of one request kind.
+#[derive(Default)]
+struct RequestStats {
+    count: u64,
+    /// Failures by error code.
+    errors: BTreeMap<String, u64>,
+    /// Requests per latency bucket, not cumulative; the last one is `+Inf`.
+    latency: [u64; LATENCY_BUCKETS.len() + 1],
+    latency_sum: f64,
+    /// Requests that were timed; rejected requests are counted but not timed.
+    latency_count: u64,
+}
+
+/// Request and connection metrics of an engine, rendered in the Prometheus text format.
+#[derive(Default)]
+pub struct Metrics {
+    requests: Mutex<BTreeMap<&'static str, RequestStats>>,
+    connections: AtomicUsize,
+    last_sync: Mutex<Option<SystemTime>>,
+}
+
+/// Counts a connection as active until it is dropped.
+pub struct ConnectionGuard<'a>(&'a Metrics);
+
+impl Drop for ConnectionGuard<'_> {
+    fn drop(&mut self) {
+        self.0.connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Metrics {
+    /// Records an executed request of `kind` that took `elapsed`.
+    pub fn record(&self, kind: &'static str, elapsed: Duration, error: Option<ErrorCode>) {
+        let mut requests = self.requests.lock().unwrap();
+        let stats = requests.entry(kind).or_default();
+        stats.count += 1;
+        if let Some(code) = error {
+            *stats.errors.entry(code_label(code)).or_default() += 1;
+        }
+        let seconds = elapsed.as_secs_f64();
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|&le| seconds <= le)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        stats.latency[bucket] += 1;
+        stats.latency_sum += seconds;
+        stats.latency_count += 1;
+    }
+
+    /// Records a request of `kind` that was rejected before it was executed, e.g. because
+    /// the caller is rate limited.
+    pub fn record_rejected(&self, kind: &'static str, code: ErrorCode) {
+        let mut requests = self.requests.lock().unwrap();
+        let stats = requests.entry(kind).or_default();
+        stats.count += 1;
+        *stats.errors.entry(code_label(code)).or_default() += 1;
+    }
+
+    /// Records that the backend finished a sync.
+    pub fn record_sync(&self) {
+        *self.last_sync.lock().unwrap() = Some(SystemTime::now());
+    }
+
+    /// Counts a new connection as active until the returned guard is dropped.
+    pub fn connection(&self) -> ConnectionGuard<'_> {
+        self.connections.fetch_add(1, Ordering::Relaxed);
+        ConnectionGuard(self)
+    }
+
+    /// Renders the request and connection metrics.
+    fn render(&self, out: &mut String) {
+        let requests = self.requests.lock().unwrap();
+        describe(
+            out,
+            "aum_requests_total",
+            "counter",
+            "Requests handled, by request kind.",
+        );
+        for (kind, stats) in requests.iter() {
+            let _ = writeln!(
+                out,
+                "aum_requests_total{{kind=\"{}\"}} {}",
+                kind, stats.count
+            );
+        }
+        describe(
+            out,
+            "aum_request_errors_total",
+            "counter",
+            "Failed requests, by request kind and error code.",
+        );
+        for (kind, stats) in requests.iter() {
+            for (code, count) in &stats.errors {
+                let _ = writeln!(
+                    out,
+                    "aum_request_errors_total{{kind=\"{}\",code=\"{}\"}} {}",
+                    kind, code, count
+                );
+            }
+        }
+        describe(
+            out,
+            "aum_request_duration_seconds",
+            "histogram",
+            "Time taken to execute requests, by request kind.",
+        );
+        for (kind, stats) in requests.iter() {
+            let mut cumulative = 0;
+            for (i, count) in stats.latency.iter().enumerate() {
+                cumulative += count;
+                let le = LATENCY_BUCKETS
+                    .get(i)
+                    .map_or("+Inf".to_owned(), |le| le.to_string());
+                let _ = writeln!(
+                    out,
+                    "aum_request_duration_seconds_bucket{{kind=\"{}\",le=\"{}\"}} {}",
+                    kind, le, cumulative
+                );
+            }
+            let _ = writeln!(
+                out,
+                "aum_request_duration_seconds_sum{{kind=\"{}\"}} {}",
+                kind, stats.latency_sum
+            );
+            let _ = writeln!(
+                out,
+                "aum_request_duration_seconds_count{{kind=\"{}\"}} {}",
+                kind, stats.latency_count
+            );
+        }
+        drop(requests);
+
+        describe(
+            out,
+            "aum_connections_active",
+            "gauge",
+            "Open WebSocket connections.",
+        );
+        let _ = writeln!(
+            out,
+            "aum_connections_active {}",
+            self.connections.load(Ordering::Relaxed)
+        );
+        describe(
+            out,
+            "aum_last_sync_timestamp_seconds",
+            "gauge",
+            "Unix time of the last successful sync, 0 if there was none.",
+        );
+        let last_sync = self
+            .last_sync
+            .lock()
+            .unwrap()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map_or(0.0, |since| since.as_secs_f64());
+        let _ = writeln!(out, "aum_last_sync_timestamp_seconds {}", last_sync);
+    }
+}
+
+fn describe(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+/// Returns the `snake_case` name a code is serialized as, e.g. `insufficient_balance`.
+pub(crate) fn code_label(code: ErrorCode) -> String {
+    serde_json::to_value(code)
+        .ok()
+        .and_then(|value| value.as_str().map(str::to_owned))
+        .unwrap_or_default()
+}
+
+/// Serves the metrics of an engine at `GET /metrics` in the Prometheus text format.
+pub struct MetricsServer {
+    listener: Listener,
+}
+
+impl MetricsServer {
+    pub async fn bind(bind: &str, unix_mode: u32) -> Result<Self, Error> {
+        let listener = Listener::bind(bind, unix_mode).await?;
+        Ok(Self { listener })
+    }
+
+    pub fn local_addr(&self) -> std::io::Result<ListenAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Serves scrapes until `shutdown` is cancelled.
+    pub async fn serve<S, Wm, M>(
+        self,
+        executor: Arc<Executor<S, Wm, M>>,
+        limits: ConnectionLimits,
+        shutdown: CancellationToken,
+        drain_timeout: Duration,
+    ) where
+        S: aum_core::prelude::Storage + Send + 'static + Sync,
+        Wm: aum_core::prelude::AsyncWalletManager + Send + 'static + Sync,
+        M: aum_core::prelude::Monitor<WalletManager = Wm> + Send + 'static + Sync,
+    {
+        let router = Router::new()
+            .route("/metrics", get(scrape))
+            .with_state(executor);
+        rest::serve_router(self.listener, None, router, limits, shutdown, drain_timeout).await;
+    }
+}
+
+async fn scrape<S, Wm, M>(State(executor): State<Arc<Executor<S, Wm, M>>>) -> impl IntoResponse
+where
+    S: aum_core::prelude::Storage + Send + 'static + Sync,
+    Wm: aum_core::prelude::AsyncWalletManager + Send + 'static + Sync,
+    M: aum_core::prelude::Monitor<WalletManager = Wm> + Send + 'static + Sync,
+{
+    let mut out = String::new();
+    executor.metrics().render(&mut out);
+
+    let runtime = executor.runtime();
+    describe(
+        &mut out,
+        "aum_monitor_running",
+        "gauge",
+        "Whether the backend monitor is running.",
+    );
+    let _ = writeln!(
+        out,
+        "aum_monitor_running {}",
+        u8::from(runtime.monitor().is_running())
+    );
+    let status = executor.monitor_status();
+    describe(
+        &mut out,
+        "aum_monitor_restarts_total",
+        "counter",
+        "Times the supervisor restarted the monitor after a failure.",
+    );
+    let _ = writeln!(out, "aum_monitor_restarts_total {}", status.restarts());
+    describe(
+        &mut out,
+        "aum_degraded",
+        "gauge",
+        "Whether the engine is degraded because the monitor keeps failing.",
+    );
+    let _ = writeln!(out, "aum_degraded {}", u8::from(status.is_degraded()));
+    let balances = runtime.wallets().retrieve_balances().await;
+    match balances {
+        Ok(balances) => {
+            describe(&mut out, "aum_wallets", "gauge", "Wallets managed.");
+            let _ = writeln!(out, "aum_wallets {}", balances.len());
+            describe(
+                &mut out,
+                "aum_balance_total",
+                "gauge",
+                "Total balance of all wallets.",
+            );
+            let total: u128 = balances
+                .iter()
+                .map(|(_, balance)| u128::from(*balance))
+                .sum();
+            let _ = writeln!(out, "aum_balance_total {}", total);
+        }
+        Err(e) => warn!("Leaving wallet metrics out of the scrape: {}", e),
+    }
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], out)
+}
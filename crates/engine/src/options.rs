@@ -0,0 +1,275 @@
+use crate::{Error, JobOptions, KeyStore, RateLimit, RateLimits, SupervisorOptions, TlsConfig};
+use aum_core::prelude::{Network, Protocol};
+use serde::{Deserialize, Serialize};
+use std::{path::PathBuf, sync::Arc, time::Duration};
+
+/// Settings for [`Engine::start_with`](crate::Engine::start_with).
+#[derive(Clone)]
+pub struct EngineOptions {
+    /// How long in-flight requests may keep running after shutdown is triggered.
+    pub shutdown_timeout: Duration,
+    /// Serve `wss://` with this certificate instead of plain `ws://`.
+    pub tls: Option<TlsConfig>,
+    /// Require every connection to authenticate with a key from this store.
+    pub key_store: Option<Arc<dyn KeyStore>>,
+    /// How often wallets are rescanned for balance and wallet events.
+    pub event_poll_interval: Duration,
+    /// Also serve the REST gateway on this address, e.g. `127.0.0.1:8080` or
+    /// `unix:/run/aum/rest.sock`.
+    ///
+    /// It shares the TLS and key store settings of the WebSocket listener.
+    pub http_bind: Option<String>,
+    /// Serve Prometheus metrics at `GET /metrics` on this address, e.g. `127.0.0.1:9100`.
+    ///
+    /// It is plain HTTP without authentication, so it should not be reachable from outside.
+    pub metrics_bind: Option<String>,
+    /// File permissions of the Unix domain sockets the engine binds; `0o660` by default,
+    /// so only the engine's user and group can connect.
+    pub unix_socket_mode: u32,
+    /// Token-bucket limits on requests; unlimited by default. The engine refuses to start
+    /// with a limit that never refills or holds no requests.
+    pub rate_limits: RateLimits,
+    /// Resource limits of the WebSocket server.
+    pub connection_limits: ConnectionLimits,
+    /// Name of the network reported to clients, see [`EngineOptions::with_network`].
+    pub network: Option<String>,
+    /// How long the outcome of a spend request is remembered by its idempotency key after
+    /// the request completed.
+    pub idempotency_window: Duration,
+    /// Record sends, wallet creations and wallet deletions in a hash-chained audit log at
+    /// this path, readable with the `QueryAudit` and `VerifyAudit` requests.
+    pub audit_log: Option<PathBuf>,
+    /// Start the monitor with the engine and restart it with backoff when it fails.
+    pub monitor_supervisor: SupervisorOptions,
+    /// Recurring jobs the engine runs itself, next to those registered with the
+    /// [`Scheduler`](crate::Scheduler) of the runtime.
+    pub jobs: JobOptions,
+}
+
+impl EngineOptions {
+    /// Reports the network `N` to clients in the `Hello` handshake.
+    pub fn with_network<N: Network>(mut self) -> Self {
+        self.network = Some(N::NAME.to_owned());
+        self
+    }
+
+    /// Fails with [`Error::InvalidConfig`] if the engine would reject or misbehave with
+    /// these options, naming every problem found by its key in the config file.
+    pub fn validate(&self) -> Result<(), Error> {
+        let problems: Vec<_> = self
+            .problems()
+            .into_iter()
+            .map(|(key, message)| format!("{}: {}", key, message))
+            .collect();
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::InvalidConfig(problems.join("; ")))
+        }
+    }
+
+    /// Returns the problems [`EngineOptions::validate`] reports, each with its key in the
+    /// config file.
+    pub(crate) fn problems(&self) -> Vec<(&'static str, String)> {
+        let mut problems = Vec::new();
+        let mut problem = |key, message: &str| problems.push((key, message.to_owned()));
+
+        let limits = &self.connection_limits;
+        if limits.max_connections == Some(0) {
+            problem("limits.max_connections", "must be at least 1");
+        }
+        for (key, value) in [
+            ("limits.max_message_size", limits.max_message_size),
+            ("limits.max_frame_size", limits.max_frame_size),
+            ("limits.max_in_flight", limits.max_in_flight),
+            ("limits.send_queue", limits.send_queue),
+        ] {
+            if value == 0 {
+                problem(key, "must be at least 1");
+            }
+        }
+        if limits.max_frame_size > limits.max_message_size {
+            problem(
+                "limits.max_frame_size",
+                "must not be larger than limits.max_message_size",
+            );
+        }
+
+        for (key, limit) in self.rate_limits.limits() {
+            if let Some(Err(e)) = limit.map(RateLimit::check) {
+                problem(key, &e);
+            }
+        }
+
+        if self.jobs.scaling_check.is_some() && self.jobs.min_wallets == 0 {
+            problem(
+                "scheduler.jobs.scaling_check",
+                "needs scheduler.jobs.min_wallets to be at least 1",
+            );
+        }
+
+        let supervisor = &self.monitor_supervisor;
+        if supervisor.enabled {
+            if supervisor.initial_backoff > supervisor.max_backoff {
+                problem(
+                    "supervisor.initial_backoff",
+                    "must not be longer than supervisor.max_backoff",
+                );
+            }
+            if supervisor.degraded_after == 0 {
+                problem("supervisor.degraded_after", "must be at least 1");
+            }
+        }
+
+        for (key, duration) in [
+            ("listen.shutdown_timeout", Some(self.shutdown_timeout)),
+            ("limits.handshake_timeout", Some(limits.handshake_timeout)),
+            ("limits.idle_timeout", limits.idle_timeout),
+            (
+                "scheduler.event_poll_interval",
+                Some(self.event_poll_interval),
+            ),
+            ("supervisor.check_interval", Some(supervisor.check_interval)),
+            (
+                "supervisor.initial_backoff",
+                Some(supervisor.initial_backoff),
+            ),
+            ("policies.idempotency_window", Some(self.idempotency_window)),
+        ] {
+            if duration.is_some_and(|duration| duration.is_zero()) {
+                problem(key, "must be longer than zero");
+            }
+        }
+        problems
+    }
+
+    /// Lists the optional features these options enable, as reported in the `Hello`
+    /// handshake.
+    pub(crate) fn features(&self) -> Vec<String> {
+        let mut features = vec![
+            "events".to_owned(),
+            "idempotency".to_owned(),
+            "jobs".to_owned(),
+        ];
+        features.extend(
+            Protocol::ALL
+                .iter()
+                .filter(|&&protocol| protocol != Protocol::Native)
+                .map(|protocol| protocol.subprotocol().to_owned()),
+        );
+        let enabled = [
+            ("tls", self.tls.is_some()),
+            ("auth", self.key_store.is_some()),
+            ("rest", self.http_bind.is_some()),
+            ("rate_limits", self.rate_limits != RateLimits::default()),
+            ("audit", self.audit_log.is_some()),
+        ];
+        features.extend(
+            enabled
+                .into_iter()
+                .filter(|(_, enabled)| *enabled)
+                .map(|(feature, _)| feature.to_owned()),
+        );
+        features
+    }
+}
+
+/// Resource limits of the WebSocket server.
+///
+/// Clients that hit a limit are told which one where the protocol allows it: a close frame
+/// once the WebSocket is open, a 408 for a stalled upgrade request. A TLS handshake that
+/// times out is just closed, and `max_in_flight` makes the server stop reading from the
+/// connection until earlier requests finish. The REST gateway and the metrics listener apply
+/// `max_connections` and `handshake_timeout` too, each listener counting its own
+/// connections.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConnectionLimits {
+    /// Maximum number of concurrent connections. Further WebSocket clients are closed with
+    /// "Try Again Later" (1013) after the handshake, HTTP clients get a 503.
+    pub max_connections: Option<usize>,
+    /// Disconnect clients that don't complete the TLS and WebSocket handshakes, including
+    /// authentication, within this time. REST clients get it for every request's headers.
+    #[serde(with = "crate::config::duration")]
+    pub handshake_timeout: Duration,
+    /// Maximum size of a message in bytes; larger ones close the connection with
+    /// "Message Too Big" (1009).
+    pub max_message_size: usize,
+    /// Maximum size of a single frame in bytes.
+    pub max_frame_size: usize,
+    /// Maximum number of requests a connection may have executing at once.
+    pub max_in_flight: usize,
+    /// Maximum number of replies and events queued for a slow client.
+    pub send_queue: usize,
+    /// Close connections that sent nothing for this long while no request was running.
+    #[serde(with = "crate::config::duration::option")]
+    pub idle_timeout: Option<Duration>,
+}
+
+impl Default for ConnectionLimits {
+    fn default() -> Self {
+        Self {
+            max_connections: None,
+            handshake_timeout: Duration::from_secs(10),
+            max_message_size: 1 << 20,
+            max_frame_size: 1 << 20,
+            max_in_flight: 64,
+            send_queue: 256,
+            idle_timeout: None,
+        }
+    }
+}
+
+impl Default for EngineOptions {
+    fn default() -> Self {
+        Self {
+            shutdown_timeout: Duration::from_secs(30),
+            tls: None,
+            key_store: None,
+            event_poll_interval: Duration::from_secs(5),
+            http_bind: None,
+            metrics_bind: None,
+            unix_socket_mode: 0o660,
+            rate_limits: RateLimits::default(),
+            connection_limits: ConnectionLimits::default(),
+            network: None,
+            idempotency_window: Duration::from_secs(24 * 60 * 60),
+            audit_log: None,
+            monitor_supervisor: SupervisorOptions::default(),
+            jobs: JobOptions::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Engine, testing};
+
+    #[tokio::test]
+    async fn engines_refuse_zero_intervals() {
+        let runtime = testing::runtime(&[]).await;
+        let zero_poll = EngineOptions {
+            event_poll_interval: Duration::ZERO,
+            ..EngineOptions::default()
+        };
+        let zero_check = EngineOptions {
+            monitor_supervisor: SupervisorOptions {
+                check_interval: Duration::ZERO,
+                ..SupervisorOptions::default()
+            },
+            ..EngineOptions::default()
+        };
+        for (options, key) in [
+            (zero_poll, "scheduler.event_poll_interval"),
+            (zero_check, "supervisor.check_interval"),
+        ] {
+            match Engine::start_with("127.0.0.1:0", runtime.clone(), options).await {
+                Err(Error::InvalidConfig(e)) => assert!(e.starts_with(key), "{}", e),
+                Err(e) => panic!("unexpected error: {}", e),
+                Ok(_) => panic!("started with a zero {}", key),
+            }
+        }
+        EngineOptions::default().validate().unwrap();
+    }
+}
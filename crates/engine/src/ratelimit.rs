@@ -0,0 +1,373 @@
+use crate::{Error, auth::Identity};
+use aum_core::prelude::Request;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// A token bucket: up to `burst` requests at once, refilled at `per_second` requests a second.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimit {
+    pub per_second: f64,
+    pub burst: u32,
+}
+
+impl RateLimit {
+    pub fn new(per_second: f64, burst: u32) -> Self {
+        Self { per_second, burst }
+    }
+
+    /// Checks that the bucket refills and can hold at least one request.
+    pub(crate) fn check(&self) -> Result<(), String> {
+        if !(self.per_second.is_finite() && self.per_second > 0.0) {
+            Err("per_second must be a positive number".to_owned())
+        } else if self.burst == 0 {
+            Err("burst must be at least 1".to_owned())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Limits for the two request budgets; `None` leaves a budget unlimited.
+///
+/// Sending transactions draws from the spend budget, every other request from the read
+/// budget. A batch draws one token per request it contains, plus one read token for
+/// itself; a batch that needs more tokens than a bucket's burst is refused outright.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BudgetLimits {
+    pub read: Option<RateLimit>,
+    pub spend: Option<RateLimit>,
+}
+
+/// Rate limits applied to the requests of every client.
+///
+/// A request must fit both the budget of its connection and the budget of its API key,
+/// which is shared by all connections using that key. Anonymous callers are only limited
+/// per connection.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimits {
+    pub per_connection: BudgetLimits,
+    pub per_key: BudgetLimits,
+}
+
+impl RateLimits {
+    /// Fails with [`Error::InvalidConfig`] if a limit would never let a request through.
+    pub fn validate(&self) -> Result<(), Error> {
+        for (key, limit) in self.limits() {
+            if let Some(Err(e)) = limit.map(|limit| limit.check()) {
+                return Err(Error::InvalidConfig(format!("{}: {}", key, e)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns every limit with its key in the config file.
+    pub(crate) fn limits(&self) -> [(&'static str, Option<&RateLimit>); 4] {
+        [
+            (
+                "rate_limits.per_connection.read",
+                self.per_connection.read.as_ref(),
+            ),
+            (
+                "rate_limits.per_connection.spend",
+                self.per_connection.spend.as_ref(),
+            ),
+            ("rate_limits.per_key.read", self.per_key.read.as_ref()),
+            ("rate_limits.per_key.spend", self.per_key.spend.as_ref()),
+        ]
+    }
+}
+
+/// Tokens a request takes from each budget.
+#[derive(Clone, Copy, Default)]
+struct Cost {
+    read: u32,
+    spend: u32,
+}
+
+impl Cost {
+    fn of(req: &Request) -> Self {
+        match req {
+            Request::SendTransaction { .. } | Request::SendTransactionFrom { .. } => {
+                Cost { read: 0, spend: 1 }
+            }
+            Request::Batch { requests, .. } => {
+                requests
+                    .iter()
+                    .map(Cost::of)
+                    .fold(Cost { read: 1, spend: 0 }, |a, b| Cost {
+                        read: a.read.saturating_add(b.read),
+                        spend: a.spend.saturating_add(b.spend),
+                    })
+            }
+            _ => Cost { read: 1, spend: 0 },
+        }
+    }
+}
+
+struct Bucket {
+    limit: RateLimit,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            tokens: f64::from(limit.burst),
+            updated: Instant::now(),
+        }
+    }
+
+    /// Refills the bucket and returns how long until `tokens` are available.
+    fn wait(&mut self, now: Instant, tokens: u32) -> Duration {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens =
+            (self.tokens + elapsed * self.limit.per_second).min(f64::from(self.limit.burst));
+        self.updated = now;
+        let cost = f64::from(tokens);
+        if self.tokens >= cost {
+            Duration::ZERO
+        } else {
+            Duration::try_from_secs_f64((cost - self.tokens) / self.limit.per_second)
+                .unwrap_or(Duration::MAX)
+        }
+    }
+}
+
+struct Buckets {
+    read: Option<Bucket>,
+    spend: Option<Bucket>,
+}
+
+impl Buckets {
+    fn new(limits: &BudgetLimits) -> Self {
+        Self {
+            read: limits.read.map(Bucket::new),
+            spend: limits.spend.map(Bucket::new),
+        }
+    }
+
+    /// Pairs each bucket with the tokens `cost` takes from it.
+    fn charge(&mut self, cost: Cost) -> [(Option<&mut Bucket>, u32); 2] {
+        [
+            (self.read.as_mut(), cost.read),
+            (self.spend.as_mut(), cost.spend),
+        ]
+    }
+}
+
+/// Takes the given tokens from every bucket, or from none of them if any lacks them.
+fn acquire(buckets: &mut [(Option<&mut Bucket>, u32)]) -> Result<(), Error> {
+    // Waiting would never help a request that needs more tokens than a bucket holds.
+    for (bucket, tokens) in buckets.iter() {
+        match bucket {
+            Some(bucket) if *tokens > bucket.limit.burst => {
+                return Err(Error::OverBudget {
+                    tokens: *tokens,
+                    burst: bucket.limit.burst,
+                });
+            }
+            _ => {}
+        }
+    }
+    let now = Instant::now();
+    let wait = buckets
+        .iter_mut()
+        .filter_map(|(bucket, tokens)| Some(bucket.as_mut()?.wait(now, *tokens)))
+        .max()
+        .unwrap_or_default();
+    if !wait.is_zero() {
+        return Err(Error::RateLimited(wait));
+    }
+    for (bucket, tokens) in buckets.iter_mut() {
+        if let Some(bucket) = bucket {
+            bucket.tokens -= f64::from(*tokens);
+        }
+    }
+    Ok(())
+}
+
+/// Tracks the per-key budgets shared by every connection of a server.
+pub struct RateLimiter {
+    limits: RateLimits,
+    keys: Mutex<HashMap<String, Buckets>>,
+}
+
+impl RateLimiter {
+    pub fn new(limits: RateLimits) -> Arc<Self> {
+        Arc::new(Self {
+            limits,
+            keys: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Creates the budgets of a new connection.
+    pub fn connection(self: &Arc<Self>) -> ConnectionLimiter {
+        ConnectionLimiter {
+            buckets: Buckets::new(&self.limits.per_connection),
+            limiter: Arc::clone(self),
+        }
+    }
+
+    /// Admits a request that is only subject to the budget of its API key.
+    pub fn check_key(&self, identity: &Identity, req: &Request) -> Result<(), Error> {
+        self.check(None, identity, req)
+    }
+
+    fn check(
+        &self,
+        connection: Option<&mut Buckets>,
+        identity: &Identity,
+        req: &Request,
+    ) -> Result<(), Error> {
+        let cost = Cost::of(req);
+        let mut keys = self.keys.lock().unwrap();
+        let key = identity.key_id.as_ref().map(|key_id| {
+            keys.entry(key_id.clone())
+                .or_insert_with(|| Buckets::new(&self.limits.per_key))
+        });
+        let mut charged = Vec::with_capacity(4);
+        if let Some(buckets) = connection {
+            charged.extend(buckets.charge(cost));
+        }
+        if let Some(buckets) = key {
+            charged.extend(buckets.charge(cost));
+        }
+        acquire(&mut charged)
+    }
+}
+
+/// The budgets of a single connection.
+pub struct ConnectionLimiter {
+    buckets: Buckets,
+    limiter: Arc<RateLimiter>,
+}
+
+impl ConnectionLimiter {
+    /// Admits a request against the connection's and the key's budgets.
+    pub fn check(&mut self, identity: &Identity, req: &Request) -> Result<(), Error> {
+        self.limiter.check(Some(&mut self.buckets), identity, req)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn send() -> Request {
+        Request::SendTransaction {
+            to: "addr".to_owned(),
+            amount: 1,
+            idempotency_key: None,
+        }
+    }
+
+    fn batch(requests: usize) -> Request {
+        Request::Batch {
+            requests: vec![send(); requests],
+            atomic: false,
+        }
+    }
+
+    fn limiter(spend: RateLimit) -> Arc<RateLimiter> {
+        RateLimiter::new(RateLimits {
+            per_key: BudgetLimits {
+                read: None,
+                spend: Some(spend),
+            },
+            ..RateLimits::default()
+        })
+    }
+
+    #[test]
+    fn burst_is_available_at_once() {
+        let limiter = limiter(RateLimit::new(0.001, 3));
+        let identity = Identity::new("ops", Vec::new());
+        for _ in 0..3 {
+            limiter.check_key(&identity, &send()).unwrap();
+        }
+        let limited = limiter.check_key(&identity, &send());
+        assert!(
+            matches!(limited, Err(Error::RateLimited(wait)) if wait > Duration::from_secs(900))
+        );
+    }
+
+    #[test]
+    fn batch_is_charged_per_request() {
+        let limiter = limiter(RateLimit::new(0.001, 3));
+        let identity = Identity::new("ops", Vec::new());
+        limiter.check_key(&identity, &batch(2)).unwrap();
+        assert!(matches!(
+            limiter.check_key(&identity, &batch(2)),
+            Err(Error::RateLimited(_))
+        ));
+        limiter.check_key(&identity, &send()).unwrap();
+    }
+
+    #[test]
+    fn batch_larger_than_the_burst_is_refused() {
+        let limiter = limiter(RateLimit::new(1000.0, 3));
+        let identity = Identity::new("ops", Vec::new());
+        let refused = limiter.check_key(&identity, &batch(4));
+        assert!(matches!(
+            refused,
+            Err(Error::OverBudget {
+                tokens: 4,
+                burst: 3
+            })
+        ));
+        // Nothing was taken from the bucket.
+        limiter.check_key(&identity, &batch(3)).unwrap();
+    }
+
+    #[test]
+    fn keys_and_connections_have_separate_budgets() {
+        let limiter = RateLimiter::new(RateLimits {
+            per_connection: BudgetLimits {
+                read: None,
+                spend: Some(RateLimit::new(0.001, 1)),
+            },
+            ..RateLimits::default()
+        });
+        let identity = Identity::new("ops", Vec::new());
+        let mut first = limiter.connection();
+        let mut second = limiter.connection();
+        first.check(&identity, &send()).unwrap();
+        assert!(first.check(&identity, &send()).is_err());
+        second.check(&identity, &send()).unwrap();
+    }
+
+    #[test]
+    fn limits_that_never_refill_are_invalid() {
+        let mut limits = RateLimits::default();
+        limits.validate().unwrap();
+        limits.per_key.spend = Some(RateLimit::new(0.0, 5));
+        assert!(
+            matches!(limits.validate(), Err(Error::InvalidConfig(e)) if e.starts_with("rate_limits.per_key.spend"))
+        );
+        limits.per_key.spend = Some(RateLimit::new(1.0, 0));
+        assert!(limits.validate().is_err());
+    }
+
+    #[test]
+    fn long_waits_are_reported_without_overflow() {
+        let limiter = limiter(RateLimit::new(1e-300, 1));
+        let identity = Identity::new("ops", Vec::new());
+        limiter.check_key(&identity, &send()).unwrap();
+        let limited = limiter.check_key(&identity, &send()).unwrap_err();
+        assert!(matches!(limited, Error::RateLimited(Duration::MAX)));
+        let details = match limited.into_response() {
+            aum_core::prelude::Response::Error { details, .. } => details.unwrap(),
+            _ => unreachable!(),
+        };
+        assert_eq!(details["retry_after_ms"], u64::MAX);
+    }
+}
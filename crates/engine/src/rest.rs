@@ -0,0 +1,595 @@
+use crate::{
+    Error,
+    auth::{self, KeyStore, PeerCredentials},
+    executor::Executor,
+    options::ConnectionLimits,
+    ratelimit::RateLimiter,
+    transport::{self, ListenAddr, Listener, Socket},
+};
+use aum_core::prelude::{ErrorCode, HealthStatus, PROTOCOL_VERSION, Request, Response};
+use axum::{
+    Extension, Json, Router,
+    extract::{FromRequestParts, Path, State, rejection::JsonRejection},
+    http::{HeaderMap, HeaderValue, StatusCode, Uri, header, request::Parts},
+    response::{IntoResponse, Response as HttpResponse},
+    routing::{get, post},
+};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo, TokioTimer},
+    server::conn::auto,
+    service::TowerToHyperService,
+};
+use serde::Deserialize;
+use std::{convert::Infallible, sync::Arc, time::Duration};
+use tokio_rustls::TlsAcceptor;
+use tokio_util::sync::CancellationToken;
+use tracing::error;
+
+/// An HTTP/JSON gateway exposing the executor as REST routes.
+///
+/// Bodies use the same JSON as the WebSocket protocol, failures are the same
+/// [`Response::Error`], and callers authenticate with the same `Authorization` header.
+/// Only the per-key rate limits apply, since an HTTP client may use a new connection
+/// for every request. An `X-Request-Id` header is traced as the client id of the request.
+///
+/// | Route                       | Request                                        |
+/// |-----------------------------|------------------------------------------------|
+/// | `GET /hello`                | `Hello` with the engine's own protocol version |
+/// | `GET /health`               | `Health`, answered with 503 while degraded     |
+/// | `GET /address`              | `RetrieveAddress`                              |
+/// | `GET /wallets`              | `ListWallets`                                  |
+/// | `GET /balances`             | `RetrieveBalances`                             |
+/// | `GET /balances/{address}`   | `RetrieveBalance`                              |
+/// | `POST /transactions`        | `SendTransaction` or, with `from`, `SendTransactionFrom` |
+/// | `POST /batch`               | `Batch` of `{"requests": [...], "atomic": bool}` |
+/// | `POST /sync`                | `Sync`                                         |
+/// | `GET /audit?since=&until=`  | `QueryAudit`, times in Unix milliseconds       |
+/// | `GET /audit/verify`         | `VerifyAudit`                                  |
+/// | `GET /jobs`                 | `ListJobs`                                     |
+/// | `POST /jobs/{name}/run`     | `RunJob`                                       |
+/// | `POST /requests`            | any [`Request`] as JSON                        |
+pub struct HttpServer {
+    listener: Listener,
+    tls: Option<TlsAcceptor>,
+    key_store: Option<Arc<dyn KeyStore>>,
+}
+
+struct RestState<S, Wm, M>
+where
+    S: aum_core::prelude::Storage + Send + 'static,
+    Wm: aum_core::prelude::AsyncWalletManager + Send + 'static,
+    M: aum_core::prelude::Monitor<WalletManager = Wm> + Send + 'static,
+{
+    executor: Arc<Executor<S, Wm, M>>,
+    key_store: Option<Arc<dyn KeyStore>>,
+    limiter: Arc<RateLimiter>,
+}
+
+/// Header carrying the client's id for a request.
+const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// The caller of a route: the request headers and, for connections over a Unix domain
+/// socket, the peer process.
+struct Caller {
+    headers: HeaderMap,
+    peer: Option<PeerCredentials>,
+}
+
+impl<T: Send + Sync> FromRequestParts<T> for Caller {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _: &T) -> Result<Self, Self::Rejection> {
+        Ok(Self {
+            headers: parts.headers.clone(),
+            peer: parts.extensions.get::<PeerCredentials>().copied(),
+        })
+    }
+}
+
+/// Body of `POST /transactions`.
+///
+/// The idempotency key may also be given in an `Idempotency-Key` header.
+#[derive(Deserialize)]
+struct TransactionBody {
+    from: Option<String>,
+    to: String,
+    amount: u64,
+    idempotency_key: Option<String>,
+}
+
+/// Body of `POST /batch`.
+#[derive(Deserialize)]
+struct BatchBody {
+    requests: Vec<Request>,
+    #[serde(default)]
+    atomic: bool,
+}
+
+impl HttpServer {
+    /// Binds `bind`, a TCP address or a `unix:` socket path created with `unix_mode`.
+    pub async fn bind(
+        bind: &str,
+        unix_mode: u32,
+        tls: Option<TlsAcceptor>,
+        key_store: Option<Arc<dyn KeyStore>>,
+    ) -> Result<Self, Error> {
+        let listener = Listener::bind(bind, unix_mode).await?;
+        Ok(Self {
+            listener,
+            tls,
+            key_store,
+        })
+    }
+
+    pub fn local_addr(&self) -> std::io::Result<ListenAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Serves HTTP connections until `shutdown` is cancelled, then drains them.
+    pub async fn serve<S, Wm, M>(
+        self,
+        executor: Arc<Executor<S, Wm, M>>,
+        limiter: Arc<RateLimiter>,
+        limits: ConnectionLimits,
+        shutdown: CancellationToken,
+        drain_timeout: Duration,
+    ) where
+        S: aum_core::prelude::Storage + Send + 'static + Sync,
+        Wm: aum_core::prelude::AsyncWalletManager + Send + 'static + Sync,
+        M: aum_core::prelude::Monitor<WalletManager = Wm> + Send + 'static + Sync,
+    {
+        let state = Arc::new(RestState {
+            executor,
+            key_store: self.key_store,
+            limiter,
+        });
+        let router = Router::new()
+            .route("/hello", get(hello))
+            .route("/health", get(health))
+            .route("/address", get(retrieve_address))
+            .route("/wallets", get(list_wallets))
+            .route("/balances", get(retrieve_balances))
+            .route("/balances/{address}", get(retrieve_balance))
+            .route("/transactions", post(send_transaction))
+            .route("/batch", post(batch))
+            .route("/sync", post(sync))
+            .route("/audit", get(query_audit))
+            .route("/audit/verify", get(verify_audit))
+            .route("/jobs", get(list_jobs))
+            .route("/jobs/{name}/run", post(run_job))
+            .route("/requests", post(execute))
+            .with_state(state);
+        serve_router(
+            self.listener,
+            self.tls,
+            router,
+            limits,
+            shutdown,
+            drain_timeout,
+        )
+        .await;
+    }
+}
+
+/// Serves `router` over HTTP/1 and HTTP/2 until `shutdown` is cancelled, then drains the
+/// connections.
+///
+/// Of `limits`, only `max_connections` and `handshake_timeout` apply: a client gets
+/// `handshake_timeout` for the TLS handshake and for sending the headers of each request.
+/// Clients over `max_connections` get a 503 for their first request and are disconnected.
+///
+/// Requests over a Unix domain socket carry the peer's [`PeerCredentials`] as an extension.
+pub(crate) async fn serve_router(
+    listener: Listener,
+    tls: Option<TlsAcceptor>,
+    router: Router,
+    limits: ConnectionLimits,
+    shutdown: CancellationToken,
+    drain_timeout: Duration,
+) {
+    let timeout = limits.handshake_timeout;
+    let rejected = Router::new().fallback(too_many_connections);
+    transport::accept_loop(
+        listener,
+        &shutdown,
+        drain_timeout,
+        limits.max_connections,
+        |stream| {
+            let router = match stream.peer_credentials() {
+                Some(peer) => router.clone().layer(Extension(peer)),
+                None => router.clone(),
+            };
+            serve_connection(stream, tls.clone(), router, timeout, shutdown.clone())
+        },
+        |stream| {
+            let serve = serve_connection(
+                stream,
+                tls.clone(),
+                rejected.clone(),
+                timeout,
+                shutdown.clone(),
+            );
+            async move {
+                let _ = tokio::time::timeout(timeout, serve).await;
+            }
+        },
+    )
+    .await;
+}
+
+/// Serves `router` on one connection until the client closes it or `shutdown` is
+/// cancelled.
+async fn serve_connection(
+    stream: Socket,
+    tls: Option<TlsAcceptor>,
+    router: Router,
+    timeout: Duration,
+    shutdown: CancellationToken,
+) {
+    let stream = match transport::accept_tls(stream, tls.as_ref(), timeout).await {
+        Ok(stream) => stream,
+        Err(e) => {
+            error!("Error during TLS handshake: {}", e);
+            return;
+        }
+    };
+    let mut builder = auto::Builder::new(TokioExecutor::new());
+    builder
+        .http1()
+        .timer(TokioTimer::new())
+        .header_read_timeout(timeout)
+        .http2()
+        .timer(TokioTimer::new());
+    let service = TowerToHyperService::new(router);
+    let connection = builder.serve_connection(TokioIo::new(stream), service);
+    tokio::pin!(connection);
+    let result = tokio::select! {
+        result = connection.as_mut() => result,
+        _ = shutdown.cancelled() => {
+            connection.as_mut().graceful_shutdown();
+            connection.await
+        }
+    };
+    if let Err(e) = result {
+        error!("HTTP connection error: {}", e);
+    }
+}
+
+/// Answers every request of a client over the connection limit.
+async fn too_many_connections() -> HttpResponse {
+    let response = Response::error(
+        ErrorCode::RateLimited,
+        "Too many connections, try again later",
+    );
+    let mut res = (StatusCode::SERVICE_UNAVAILABLE, Json(response)).into_response();
+    let headers = res.headers_mut();
+    headers.insert(header::RETRY_AFTER, HeaderValue::from(1));
+    headers.insert(header::CONNECTION, HeaderValue::from_static("close"));
+    res
+}
+
+type AppState<S, Wm, M> = State<Arc<RestState<S, Wm, M>>>;
+
+async fn hello<S, Wm, M>(State(state): AppState<S, Wm, M>, caller: Caller) -> HttpResponse
+where
+    S: aum_core::prelude::Storage + Send + 'static + Sync,
+    Wm: aum_core::prelude::AsyncWalletManager + Send + 'static + Sync,
+    M: aum_core::prelude::Monitor<WalletManager = Wm> + Send + 'static + Sync,
+{
+    let req = Request::Hello {
+        protocol_version: PROTOCOL_VERSION,
+        client_name: None,
+    };
+    run(&state, &caller, req).await
+}
+
+async fn health<S, Wm, M>(State(state): AppState<S, Wm, M>, caller: Caller) -> HttpResponse
+where
+    S: aum_core::prelude::Storage + Send + 'static + Sync,
+    Wm: aum_core::prelude::AsyncWalletManager + Send + 'static + Sync,
+    M: aum_core::prelude::Monitor<WalletManager = Wm> + Send + 'static + Sync,
+{
+    run(&state, &caller, Request::Health).await
+}
+
+async fn retrieve_address<S, Wm, M>(
+    State(state): AppState<S, Wm, M>,
+    caller: Caller,
+) -> HttpResponse
+where
+    S: aum_core::prelude::Storage + Send + 'static + Sync,
+    Wm: aum_core::prelude::AsyncWalletManager + Send + 'static + Sync,
+    M: aum_core::prelude::Monitor<WalletManager = Wm> + Send + 'static + Sync,
+{
+    run(&state, &caller, Request::RetrieveAddress).await
+}
+
+async fn list_wallets<S, Wm, M>(State(state): AppState<S, Wm, M>, caller: Caller) -> HttpResponse
+where
+    S: aum_core::prelude::Storage + Send + 'static + Sync,
+    Wm: aum_core::prelude::AsyncWalletManager + Send + 'static + Sync,
+    M: aum_core::prelude::Monitor<WalletManager = Wm> + Send + 'static + Sync,
+{
+    run(&state, &caller, Request::ListWallets).await
+}
+
+async fn retrieve_balances<S, Wm, M>(
+    State(state): AppState<S, Wm, M>,
+    caller: Caller,
+) -> HttpResponse
+where
+    S: aum_core::prelude::Storage + Send + 'static + Sync,
+    Wm: aum_core::prelude::AsyncWalletManager + Send + 'static + Sync,
+    M: aum_core::prelude::Monitor<WalletManager = Wm> + Send + 'static + Sync,
+{
+    run(&state, &caller, Request::RetrieveBalances).await
+}
+
+async fn retrieve_balance<S, Wm, M>(
+    State(state): AppState<S, Wm, M>,
+    Path(address): Path<String>,
+    caller: Caller,
+) -> HttpResponse
+where
+    S: aum_core::prelude::Storage + Send + 'static + Sync,
+    Wm: aum_core::prelude::AsyncWalletManager + Send + 'static + Sync,
+    M: aum_core::prelude::Monitor<WalletManager = Wm> + Send + 'static + Sync,
+{
+    run(&state, &caller, Request::RetrieveBalance { address }).await
+}
+
+async fn send_transaction<S, Wm, M>(
+    State(state): AppState<S, Wm, M>,
+    caller: Caller,
+    body: Result<Json<TransactionBody>, JsonRejection>,
+) -> HttpResponse
+where
+    S: aum_core::prelude::Storage + Send + 'static + Sync,
+    Wm: aum_core::prelude::AsyncWalletManager + Send + 'static + Sync,
+    M: aum_core::prelude::Monitor<WalletManager = Wm> + Send + 'static + Sync,
+{
+    let Json(TransactionBody {
+        from,
+        to,
+        amount,
+        idempotency_key,
+    }) = match body {
+        Ok(body) => body,
+        Err(rejection) => return rejection_into_http(rejection),
+    };
+    let idempotency_key = idempotency_key.or_else(|| {
+        caller
+            .headers
+            .get("idempotency-key")
+            .and_then(|key| key.to_str().ok())
+            .map(str::to_owned)
+    });
+    let req = match from {
+        Some(from) => Request::SendTransactionFrom {
+            from,
+            to,
+            amount,
+            idempotency_key,
+        },
+        None => Request::SendTransaction {
+            to,
+            amount,
+            idempotency_key,
+        },
+    };
+    run(&state, &caller, req).await
+}
+
+async fn batch<S, Wm, M>(
+    State(state): AppState<S, Wm, M>,
+    caller: Caller,
+    body: Result<Json<BatchBody>, JsonRejection>,
+) -> HttpResponse
+where
+    S: aum_core::prelude::Storage + Send + 'static + Sync,
+    Wm: aum_core::prelude::AsyncWalletManager + Send + 'static + Sync,
+    M: aum_core::prelude::Monitor<WalletManager = Wm> + Send + 'static + Sync,
+{
+    let Json(BatchBody { requests, atomic }) = match body {
+        Ok(body) => body,
+        Err(rejection) => return rejection_into_http(rejection),
+    };
+    run(&state, &caller, Request::Batch { requests, atomic }).await
+}
+
+async fn sync<S, Wm, M>(State(state): AppState<S, Wm, M>, caller: Caller) -> HttpResponse
+where
+    S: aum_core::prelude::Storage + Send + 'static + Sync,
+    Wm: aum_core::prelude::AsyncWalletManager + Send + 'static + Sync,
+    M: aum_core::prelude::Monitor<WalletManager = Wm> + Send + 'static + Sync,
+{
+    run(&state, &caller, Request::Sync).await
+}
+
+async fn query_audit<S, Wm, M>(
+    State(state): AppState<S, Wm, M>,
+    caller: Caller,
+    uri: Uri,
+) -> HttpResponse
+where
+    S: aum_core::prelude::Storage + Send + 'static + Sync,
+    Wm: aum_core::prelude::AsyncWalletManager + Send + 'static + Sync,
+    M: aum_core::prelude::Monitor<WalletManager = Wm> + Send + 'static + Sync,
+{
+    let range =
+        query_param(&uri, "since").and_then(|since| Ok((since, query_param(&uri, "until")?)));
+    match range {
+        Ok((since, until)) => run(&state, &caller, Request::QueryAudit { since, until }).await,
+        Err(reason) => response_into_http(Response::Error {
+            code: ErrorCode::InvalidRequest,
+            message: Error::WrongRequest.to_string(),
+            details: Some(serde_json::json!({ "reason": reason })),
+        }),
+    }
+}
+
+async fn verify_audit<S, Wm, M>(State(state): AppState<S, Wm, M>, caller: Caller) -> HttpResponse
+where
+    S: aum_core::prelude::Storage + Send + 'static + Sync,
+    Wm: aum_core::prelude::AsyncWalletManager + Send + 'static + Sync,
+    M: aum_core::prelude::Monitor<WalletManager = Wm> + Send + 'static + Sync,
+{
+    run(&state, &caller, Request::VerifyAudit).await
+}
+
+async fn list_jobs<S, Wm, M>(State(state): AppState<S, Wm, M>, caller: Caller) -> HttpResponse
+where
+    S: aum_core::prelude::Storage + Send + 'static + Sync,
+    Wm: aum_core::prelude::AsyncWalletManager + Send + 'static + Sync,
+    M: aum_core::prelude::Monitor<WalletManager = Wm> + Send + 'static + Sync,
+{
+    run(&state, &caller, Request::ListJobs).await
+}
+
+async fn run_job<S, Wm, M>(
+    State(state): AppState<S, Wm, M>,
+    caller: Caller,
+    Path(name): Path<String>,
+) -> HttpResponse
+where
+    S: aum_core::prelude::Storage + Send + 'static + Sync,
+    Wm: aum_core::prelude::AsyncWalletManager + Send + 'static + Sync,
+    M: aum_core::prelude::Monitor<WalletManager = Wm> + Send + 'static + Sync,
+{
+    run(&state, &caller, Request::RunJob { name }).await
+}
+
+/// Reads the numeric query parameter `name`, if present.
+fn query_param(uri: &Uri, name: &str) -> Result<Option<u64>, String> {
+    let Some(query) = uri.query() else {
+        return Ok(None);
+    };
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| {
+            value
+                .parse()
+                .map_err(|_| format!("{} must be a Unix time in milliseconds", name))
+        })
+        .transpose()
+}
+
+async fn execute<S, Wm, M>(
+    State(state): AppState<S, Wm, M>,
+    caller: Caller,
+    body: Result<Json<Request>, JsonRejection>,
+) -> HttpResponse
+where
+    S: aum_core::prelude::Storage + Send + 'static + Sync,
+    Wm: aum_core::prelude::AsyncWalletManager + Send + 'static + Sync,
+    M: aum_core::prelude::Monitor<WalletManager = Wm> + Send + 'static + Sync,
+{
+    match body {
+        Ok(Json(req)) => run(&state, &caller, req).await,
+        Err(rejection) => rejection_into_http(rejection),
+    }
+}
+
+/// Authenticates the caller and runs `req` through the executor.
+async fn run<S, Wm, M>(state: &RestState<S, Wm, M>, caller: &Caller, req: Request) -> HttpResponse
+where
+    S: aum_core::prelude::Storage + Send + 'static + Sync,
+    Wm: aum_core::prelude::AsyncWalletManager + Send + 'static + Sync,
+    M: aum_core::prelude::Monitor<WalletManager = Wm> + Send + 'static + Sync,
+{
+    let admitted = auth::authenticate(state.key_store.as_deref(), &caller.headers, caller.peer);
+    let admitted = admitted.and_then(|identity| {
+        state.limiter.check_key(&identity, &req)?;
+        Ok(identity)
+    });
+    let result = match admitted {
+        Ok(identity) => {
+            let client_id = caller
+                .headers
+                .get(REQUEST_ID_HEADER)
+                .and_then(|id| id.to_str().ok())
+                .map(str::to_owned);
+            state.executor.execute(&identity, client_id, req).await
+        }
+        Err(e) => {
+            state
+                .executor
+                .metrics()
+                .record_rejected(req.kind(), e.code());
+            Err(e)
+        }
+    };
+    match result {
+        Ok(response) => {
+            let status = match &response {
+                Response::Health {
+                    status: HealthStatus::Degraded,
+                    ..
+                } => StatusCode::SERVICE_UNAVAILABLE,
+                _ => StatusCode::OK,
+            };
+            (status, Json(response)).into_response()
+        }
+        Err(e) => response_into_http(e.into_response()),
+    }
+}
+
+fn rejection_into_http(rejection: JsonRejection) -> HttpResponse {
+    response_into_http(Response::Error {
+        code: ErrorCode::InvalidRequest,
+        message: Error::WrongRequest.to_string(),
+        details: Some(serde_json::json!({ "reason": rejection.body_text() })),
+    })
+}
+
+fn response_into_http(response: Response) -> HttpResponse {
+    let status = match &response {
+        Response::Error { code, .. } => status_of(*code),
+        _ => StatusCode::OK,
+    };
+    // Throttled callers also get the standard `Retry-After` header, in whole seconds.
+    let retry_after = match &response {
+        Response::Error {
+            details: Some(details),
+            ..
+        } => details.get("retry_after_ms").and_then(|ms| ms.as_u64()),
+        _ => None,
+    };
+    let mut res = (status, Json(response)).into_response();
+    if let Some(ms) = retry_after {
+        res.headers_mut()
+            .insert(header::RETRY_AFTER, HeaderValue::from(ms.div_ceil(1000)));
+    }
+    res
+}
+
+/// Maps an error code to the HTTP status reported by the gateway.
+fn status_of(code: ErrorCode) -> StatusCode {
+    match code {
+        ErrorCode::InvalidRequest
+        | ErrorCode::UnsupportedProtocolVersion
+        | ErrorCode::InvalidAddress
+        | ErrorCode::UnsupportedAddressFormat
+        | ErrorCode::InvalidKey
+        | ErrorCode::InvalidTransaction
+        | ErrorCode::InvalidHash => StatusCode::BAD_REQUEST,
+        ErrorCode::Unauthorized => StatusCode::UNAUTHORIZED,
+        ErrorCode::Forbidden => StatusCode::FORBIDDEN,
+        ErrorCode::RateLimited => StatusCode::TOO_MANY_REQUESTS,
+        ErrorCode::InsufficientBalance => StatusCode::UNPROCESSABLE_ENTITY,
+        ErrorCode::AuditDisabled | ErrorCode::UnknownJob => StatusCode::NOT_FOUND,
+        ErrorCode::Aborted | ErrorCode::IdempotencyConflict | ErrorCode::OutcomeUnknown => {
+            StatusCode::CONFLICT
+        }
+        ErrorCode::MonitorNotRunning => StatusCode::SERVICE_UNAVAILABLE,
+        ErrorCode::KeyGenerationFailed
+        | ErrorCode::TransactionFailed
+        | ErrorCode::MonitorFailed
+        | ErrorCode::AuditLogCorrupted
+        | ErrorCode::JobFailed
+        | ErrorCode::Internal
+        | ErrorCode::Unknown => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
@@ -1,37 +1,214 @@
-use aum_core::prelude::{Monitor, Storage, WalletManager};
-pub struct Runtime<S: Storage, Wm: WalletManager, M: Monitor> {
-    storage: S,
-    scale: Wm,
-    monitor: M,
+use crate::Error;
+use crate::audit::AuditLog;
+use crate::auth::Identity;
+use crate::scheduler::Scheduler;
+use crate::wallets::{AuditSlot, Wallets};
+use aum_core::errors::MonitorError;
+use aum_core::prelude::{AsyncWalletManager, JobInfo, Monitor, Storage};
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+};
+use tokio::{
+    sync::{Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard},
+    task::JoinHandle,
+};
+use tracing::warn;
+
+/// The storage, wallet manager and monitor of an engine, shared by every task using them.
+///
+/// Cloning a runtime is cheap and gives another handle to the same state, so the
+/// application, the monitor task and the engine's servers all see and change the same
+/// wallets. Storage is behind a fair read-write lock: changes through `&mut` wait for the
+/// readers before them and are applied in the order they were asked for, and readers asking
+/// after a change see it. The wallet manager synchronizes itself; a synchronous one is
+/// wrapped in a [`BlockingWalletManager`](crate::BlockingWalletManager).
+pub struct Runtime<S: Storage, Wm: AsyncWalletManager, M: Monitor> {
+    storage: Arc<RwLock<S>>,
+    scale: Arc<Wm>,
+    monitor: Arc<M>,
+    scheduler: Arc<Scheduler<S, Wm, M>>,
+    audit: AuditSlot,
+    /// Whether an engine serving this runtime supervises the monitor.
+    supervised: Arc<AtomicBool>,
+    deleting: Arc<Mutex<()>>,
+    /// Key id that wallet changes through this handle are recorded for.
+    caller: Option<String>,
+}
+
+impl<S: Storage, Wm: AsyncWalletManager, M: Monitor> Clone for Runtime<S, Wm, M> {
+    fn clone(&self) -> Self {
+        Self {
+            storage: Arc::clone(&self.storage),
+            scale: Arc::clone(&self.scale),
+            monitor: Arc::clone(&self.monitor),
+            scheduler: Arc::clone(&self.scheduler),
+            audit: Arc::clone(&self.audit),
+            supervised: Arc::clone(&self.supervised),
+            deleting: Arc::clone(&self.deleting),
+            caller: self.caller.clone(),
+        }
+    }
 }
 
 impl<
     S: Storage + Send + 'static,
-    Wm: WalletManager + Send + 'static,
+    Wm: AsyncWalletManager + 'static,
     M: Monitor<WalletManager = Wm> + Send + 'static,
 > Runtime<S, Wm, M>
 {
     pub async fn new(storage: S, scale: Wm, monitor: M) -> Self {
         Self {
-            storage,
-            scale,
-            monitor,
+            storage: Arc::new(RwLock::new(storage)),
+            scale: Arc::new(scale),
+            monitor: Arc::new(monitor),
+            scheduler: Arc::new(Scheduler::new()),
+            audit: AuditSlot::default(),
+            supervised: Arc::default(),
+            deleting: Arc::default(),
+            caller: None,
         }
     }
-    pub fn run(mut self) {
-        tokio::spawn(async move {
-            if let Err(e) = self.monitor.start(&mut self.scale).await {
-                panic!("Error starting monitor: {:?}", e);
-            }
-        });
+    pub async fn storage(&self) -> RwLockReadGuard<'_, S> {
+        self.storage.read().await
+    }
+    pub async fn storage_mut(&self) -> RwLockWriteGuard<'_, S> {
+        self.storage.write().await
+    }
+    /// Returns the wallet manager, recording the wallets created and deleted through it in
+    /// the audit log of the engine serving this runtime.
+    pub fn wallets(&self) -> Wallets<Wm> {
+        Wallets::new(
+            Arc::clone(&self.scale),
+            Arc::clone(&self.audit),
+            Arc::clone(&self.deleting),
+            self.caller.clone(),
+        )
     }
-    pub fn storage(&self) -> &S {
-        &self.storage
+    /// Returns a handle whose wallet changes are recorded as made by `identity`.
+    pub(crate) fn on_behalf_of(&self, identity: &Identity) -> Self {
+        Self {
+            caller: identity.key_id.clone(),
+            ..self.clone()
+        }
     }
-    pub fn wallet_manager(&self) -> &Wm {
-        &self.scale
+    pub(crate) fn audit_log(&self) -> Option<Arc<AuditLog>> {
+        self.audit.read().unwrap().clone()
+    }
+    /// Makes wallet changes through this runtime go to `log`, or nowhere.
+    ///
+    /// Fails if another engine serving the runtime already records to a log.
+    pub(crate) fn set_audit_log(&self, log: Option<Arc<AuditLog>>) -> Result<(), Error> {
+        let mut audit = self.audit.write().unwrap();
+        if audit.is_some() && log.is_some() {
+            return Err(Error::InvalidConfig(
+                "another engine serving this runtime already keeps an audit log".to_owned(),
+            ));
+        }
+        *audit = log;
+        Ok(())
+    }
+    /// Marks the monitor as supervised by an engine, or no longer.
+    ///
+    /// Fails if another engine serving the runtime already supervises it.
+    pub(crate) fn set_supervised(&self, supervised: bool) -> Result<(), Error> {
+        if self.supervised.swap(supervised, Ordering::SeqCst) && supervised {
+            return Err(Error::InvalidConfig(
+                "another engine serving this runtime already supervises its monitor".to_owned(),
+            ));
+        }
+        Ok(())
     }
     pub fn monitor(&self) -> &M {
         &self.monitor
     }
+    /// Returns the recurring jobs run by the engines serving this runtime.
+    pub fn scheduler(&self) -> &Scheduler<S, Wm, M> {
+        &self.scheduler
+    }
+}
+
+impl<
+    S: Storage + Send + Sync + 'static,
+    Wm: AsyncWalletManager + 'static,
+    M: Monitor<WalletManager = Wm> + Send + Sync + 'static,
+> Runtime<S, Wm, M>
+{
+    /// Starts the monitor on its own task.
+    ///
+    /// Engines start the monitor themselves unless their supervisor is disabled, see
+    /// [`SupervisorOptions`](crate::SupervisorOptions). While one of them serves this
+    /// runtime, the monitor isn't started again and the task fails right away.
+    pub fn run(&self) -> JoinHandle<Result<(), MonitorError>> {
+        if self.supervised.load(Ordering::SeqCst) {
+            warn!("Not starting the monitor, the engine serving this runtime supervises it");
+            return tokio::spawn(async {
+                Err(MonitorError::Custom(
+                    "the monitor is supervised by the engine".into(),
+                ))
+            });
+        }
+        self.spawn_monitor()
+    }
+    /// Starts the monitor on its own task, whoever supervises it.
+    pub(crate) fn spawn_monitor(&self) -> JoinHandle<Result<(), MonitorError>> {
+        let runtime = self.clone();
+        tokio::spawn(async move { runtime.monitor.start(Arc::new(runtime.wallets())).await })
+    }
+    /// Runs the scheduled job `name` now and waits for it to finish.
+    pub async fn run_job(&self, name: &str) -> Result<JobInfo, Error> {
+        self.scheduler.run_now(name, self.clone()).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Engine, Error, testing};
+    use aum_core::prelude::Monitor;
+    use std::{sync::atomic::Ordering, time::Duration};
+
+    #[tokio::test]
+    async fn engines_start_the_monitor_and_run_leaves_it_to_them() {
+        let runtime = testing::runtime(&[]).await;
+        let engine = Engine::start("127.0.0.1:0", runtime.clone()).await.unwrap();
+        let monitor = runtime.monitor();
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !monitor.is_running() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("the engine didn't start the monitor");
+
+        assert!(runtime.run().await.unwrap().is_err());
+        assert!(Engine::start("127.0.0.1:0", runtime.clone()).await.is_err());
+        assert_eq!(monitor.starts.load(Ordering::SeqCst), 1);
+
+        engine.shutdown();
+        engine.join().await.unwrap();
+        runtime.run().await.unwrap().unwrap();
+        assert_eq!(monitor.starts.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn engines_release_the_runtime_after_a_task_panicked() {
+        let runtime = testing::runtime(&[]).await;
+        let monitor = runtime.monitor();
+        monitor.running.store(true, Ordering::SeqCst);
+        // The supervisor's first check panics.
+        monitor.panic_once.store(true, Ordering::SeqCst);
+        let engine = Engine::start("127.0.0.1:0", runtime.clone()).await.unwrap();
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while monitor.panic_once.load(Ordering::SeqCst) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("the supervisor didn't check the monitor");
+
+        engine.shutdown();
+        assert!(matches!(engine.join().await, Err(Error::TaskError(_))));
+        assert!(!monitor.is_running());
+        runtime.run().await.unwrap().unwrap();
+    }
 }
@@ -0,0 +1,544 @@
+use crate::{Error, audit::now_ms, cron::CronSchedule, executor::Executor, runtime::Runtime};
+use aum_core::prelude::{AsyncWalletManager, JobInfo, Monitor, Storage};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{
+    collections::BTreeMap,
+    fmt,
+    future::Future,
+    pin::Pin,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::{sync::Notify, task::JoinSet};
+use tokio_util::sync::CancellationToken;
+use tracing::{Instrument, debug, info, info_span, warn};
+
+/// How long the scheduler sleeps when no job is due, before looking again.
+const IDLE_WAKEUP: Duration = Duration::from_secs(60);
+
+/// Why a job run failed.
+pub type JobError = Box<dyn std::error::Error + Send + Sync>;
+
+type JobFuture = Pin<Box<dyn Future<Output = Result<(), JobError>> + Send>>;
+type JobFn<S, Wm, M> = Box<dyn Fn(Runtime<S, Wm, M>) -> JobFuture + Send + Sync>;
+type Jobs<S, Wm, M> = BTreeMap<String, Arc<Job<S, Wm, M>>>;
+
+/// When a job runs: at a fixed interval or on a cron schedule.
+///
+/// Schedules parse from `@every <duration>`, e.g. `@every 90s` or `@every 1h30m`, from a
+/// five-field cron expression evaluated in UTC, e.g. `*/15 * * * *`, and from the
+/// shorthands `@hourly`, `@daily`, `@weekly`, `@monthly` and `@yearly`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Schedule(Kind);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Kind {
+    Every(Duration),
+    Cron {
+        expression: String,
+        schedule: CronSchedule,
+    },
+}
+
+impl Schedule {
+    /// Runs a job every `interval`, the first time one interval after it is registered.
+    ///
+    /// Fails with [`Error::InvalidSchedule`] if `interval` is zero.
+    pub fn every(interval: Duration) -> Result<Self, Error> {
+        if interval.is_zero() {
+            return Err(Error::InvalidSchedule(
+                "the interval must be longer than zero".to_owned(),
+            ));
+        }
+        Ok(Self(Kind::Every(interval)))
+    }
+
+    /// Runs a job at the minutes matching a cron `expression`, in UTC.
+    ///
+    /// Fails with [`Error::InvalidSchedule`] if the expression is malformed or matches no
+    /// date at all, such as `0 0 31 2 *`.
+    pub fn cron(expression: &str) -> Result<Self, Error> {
+        let expanded = match expression.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            expression => expression,
+        };
+        let schedule = CronSchedule::parse(expanded).map_err(Error::InvalidSchedule)?;
+        Ok(Self(Kind::Cron {
+            expression: expression.trim().to_owned(),
+            schedule,
+        }))
+    }
+
+    /// Returns when the job runs next after `now_ms`, in Unix milliseconds.
+    fn next_after(&self, now_ms: u64) -> Option<u64> {
+        match &self.0 {
+            Kind::Every(interval) => Some(now_ms.saturating_add(interval.as_millis() as u64)),
+            Kind::Cron { schedule, .. } => schedule.next_after(now_ms),
+        }
+    }
+}
+
+impl FromStr for Schedule {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().strip_prefix("@every") {
+            Some(interval) => match parse_duration(interval.trim()) {
+                Some(interval) if !interval.is_zero() => Self::every(interval),
+                _ => Err(Error::InvalidSchedule(format!(
+                    "{:?} is not an interval such as 30s or 1h30m",
+                    interval.trim()
+                ))),
+            },
+            None => Self::cron(s),
+        }
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.0 {
+            Kind::Every(interval) => write!(f, "@every {}", format_duration(*interval)),
+            Kind::Cron { expression, .. } => f.write_str(expression),
+        }
+    }
+}
+
+impl Serialize for Schedule {
+    fn serialize<Se: Serializer>(&self, serializer: Se) -> Result<Se::Ok, Se::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Schedule {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Parses durations such as `500ms`, `30s` or `1h30m`; units are `ms`, `s`, `m`, `h` and `d`.
+pub(crate) fn parse_duration(s: &str) -> Option<Duration> {
+    if s.is_empty() {
+        return None;
+    }
+    let mut total = Duration::ZERO;
+    let mut rest = s;
+    while !rest.is_empty() {
+        let digits = rest.find(|c: char| !c.is_ascii_digit())?;
+        let (value, tail) = rest.split_at(digits);
+        let value: u64 = value.parse().ok()?;
+        let unit = tail
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(tail.len());
+        let (unit, tail) = tail.split_at(unit);
+        let millis = match unit {
+            "ms" => 1,
+            "s" => 1000,
+            "m" => 60 * 1000,
+            "h" => 60 * 60 * 1000,
+            "d" => 24 * 60 * 60 * 1000,
+            _ => return None,
+        };
+        total = total.checked_add(Duration::from_millis(value.checked_mul(millis)?))?;
+        rest = tail;
+    }
+    Some(total)
+}
+
+/// Formats `duration` in its largest whole unit, the inverse of [`parse_duration`].
+pub(crate) fn format_duration(duration: Duration) -> String {
+    let millis = duration.as_millis();
+    if millis == 0 {
+        return "0s".to_owned();
+    }
+    let units = [
+        ("d", 24 * 60 * 60 * 1000),
+        ("h", 60 * 60 * 1000),
+        ("m", 60 * 1000),
+        ("s", 1000),
+    ];
+    for (unit, size) in units {
+        if millis.is_multiple_of(size) {
+            return format!("{}{}", millis / size, unit);
+        }
+    }
+    format!("{}ms", millis)
+}
+
+/// Recurring jobs run against a [`Runtime`] while an engine serves it.
+///
+/// Jobs are identified by name. A job that is still running when it is due again skips
+/// that run. Every job records its last run, how long it took and why it failed, as listed
+/// by the `ListJobs` request.
+pub struct Scheduler<S: Storage, Wm: AsyncWalletManager, M: Monitor> {
+    jobs: Mutex<Jobs<S, Wm, M>>,
+    changed: Notify,
+}
+
+struct Job<S: Storage, Wm: AsyncWalletManager, M: Monitor> {
+    name: String,
+    schedule: Schedule,
+    run: JobFn<S, Wm, M>,
+    /// Held while the job runs, so that runs never overlap.
+    lock: tokio::sync::Mutex<()>,
+    state: Mutex<JobState>,
+}
+
+#[derive(Default)]
+struct JobState {
+    runs: u64,
+    failures: u64,
+    last_run_ms: Option<u64>,
+    last_duration_ms: Option<u64>,
+    last_error: Option<String>,
+    next_run_ms: Option<u64>,
+}
+
+impl<S: Storage, Wm: AsyncWalletManager, M: Monitor> Scheduler<S, Wm, M> {
+    pub(crate) fn new() -> Self {
+        Self {
+            jobs: Mutex::new(BTreeMap::new()),
+            changed: Notify::new(),
+        }
+    }
+
+    /// Schedules `job` under `name`, replacing any job with the same name.
+    ///
+    /// The job is given a handle to the runtime on every run; a failed run is recorded and
+    /// retried at the next scheduled time.
+    pub fn register<F, Fut>(&self, name: impl Into<String>, schedule: Schedule, job: F)
+    where
+        F: Fn(Runtime<S, Wm, M>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), JobError>> + Send + 'static,
+    {
+        let name = name.into();
+        let state = JobState {
+            next_run_ms: schedule.next_after(now_ms()),
+            ..JobState::default()
+        };
+        let job = Job {
+            name: name.clone(),
+            schedule,
+            run: Box::new(move |runtime| Box::pin(job(runtime))),
+            lock: tokio::sync::Mutex::new(()),
+            state: Mutex::new(state),
+        };
+        self.jobs.lock().unwrap().insert(name, Arc::new(job));
+        self.changed.notify_one();
+    }
+
+    /// Removes the job `name`, returning whether there was one. A run in progress finishes.
+    pub fn unregister(&self, name: &str) -> bool {
+        self.jobs.lock().unwrap().remove(name).is_some()
+    }
+
+    /// Lists the scheduled jobs by name.
+    pub fn jobs(&self) -> Vec<JobInfo> {
+        self.snapshot().iter().map(|job| job.info()).collect()
+    }
+
+    fn snapshot(&self) -> Vec<Arc<Job<S, Wm, M>>> {
+        self.jobs.lock().unwrap().values().cloned().collect()
+    }
+
+    fn get(&self, name: &str) -> Result<Arc<Job<S, Wm, M>>, Error> {
+        self.jobs
+            .lock()
+            .unwrap()
+            .get(name)
+            .cloned()
+            .ok_or_else(|| Error::UnknownJob(name.to_owned()))
+    }
+}
+
+impl<S: Storage, Wm: AsyncWalletManager, M: Monitor> Job<S, Wm, M> {
+    fn info(&self) -> JobInfo {
+        let state = self.state.lock().unwrap();
+        JobInfo {
+            name: self.name.clone(),
+            schedule: self.schedule.to_string(),
+            running: self.lock.try_lock().is_err(),
+            runs: state.runs,
+            failures: state.failures,
+            last_run_ms: state.last_run_ms,
+            last_duration_ms: state.last_duration_ms,
+            last_error: state.last_error.clone(),
+            next_run_ms: state.next_run_ms,
+        }
+    }
+
+    /// Returns whether the job is due at `now_ms`, moving its next run on if it is.
+    fn take_due(&self, now_ms: u64) -> bool {
+        let mut state = self.state.lock().unwrap();
+        match state.next_run_ms {
+            Some(at) if at <= now_ms => {
+                state.next_run_ms = self.schedule.next_after(now_ms);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn next_run_ms(&self) -> Option<u64> {
+        self.state.lock().unwrap().next_run_ms
+    }
+}
+
+impl<
+    S: Storage + Send + Sync + 'static,
+    Wm: AsyncWalletManager + Send + Sync + 'static,
+    M: Monitor<WalletManager = Wm> + Send + Sync + 'static,
+> Job<S, Wm, M>
+{
+    /// Runs the job once, waiting for a run in progress first, inside a `job` span.
+    async fn execute(
+        &self,
+        runtime: Runtime<S, Wm, M>,
+        trigger: &'static str,
+    ) -> Result<(), String> {
+        let _running = self.lock.lock().await;
+        let span = info_span!("job", job = %self.name, trigger);
+        self.state.lock().unwrap().last_run_ms = Some(now_ms());
+        let started = Instant::now();
+        // On its own task, so that a panicking job is recorded as a failed run.
+        let result = match tokio::spawn((self.run)(runtime).instrument(span.clone())).await {
+            Ok(result) => result.map_err(|e| e.to_string()),
+            Err(e) => Err(format!("Job task failed: {}", e)),
+        };
+        let elapsed = started.elapsed();
+        {
+            let mut state = self.state.lock().unwrap();
+            state.runs += 1;
+            state.last_duration_ms = Some(elapsed.as_millis() as u64);
+            state.last_error = result.as_ref().err().cloned();
+            if result.is_err() {
+                state.failures += 1;
+            }
+        }
+        span.in_scope(|| match &result {
+            Ok(()) => debug!("Job finished in {:?}", elapsed),
+            Err(e) => warn!("Job failed: {}", e),
+        });
+        result
+    }
+}
+
+impl<
+    S: Storage + Send + Sync + 'static,
+    Wm: AsyncWalletManager + Send + Sync + 'static,
+    M: Monitor<WalletManager = Wm> + Send + Sync + 'static,
+> Scheduler<S, Wm, M>
+{
+    /// Runs the job `name` now and waits for it, after a run in progress if there is one.
+    pub(crate) async fn run_now(
+        &self,
+        name: &str,
+        runtime: Runtime<S, Wm, M>,
+    ) -> Result<JobInfo, Error> {
+        let job = self.get(name)?;
+        job.execute(runtime, "request")
+            .await
+            .map_err(|e| Error::JobFailed(name.to_owned(), e))?;
+        Ok(job.info())
+    }
+}
+
+/// Runs the jobs of `runtime` when they are due, until `shutdown` is cancelled.
+///
+/// Runs still in progress at shutdown get `drain_timeout` to finish before they are
+/// abandoned.
+pub(crate) async fn run<S, Wm, M>(
+    runtime: Runtime<S, Wm, M>,
+    shutdown: CancellationToken,
+    drain_timeout: Duration,
+) where
+    S: aum_core::prelude::Storage + Send + 'static + Sync,
+    Wm: aum_core::prelude::AsyncWalletManager + Send + 'static + Sync,
+    M: aum_core::prelude::Monitor<WalletManager = Wm> + Send + 'static + Sync,
+{
+    let scheduler = runtime.scheduler();
+    let mut in_flight = JoinSet::new();
+    loop {
+        let now = now_ms();
+        let mut wake_at: Option<u64> = None;
+        for job in scheduler.snapshot() {
+            if job.take_due(now) {
+                if job.lock.try_lock().is_ok() {
+                    let runtime = runtime.clone();
+                    let job = Arc::clone(&job);
+                    in_flight.spawn(async move { job.execute(runtime, "schedule").await });
+                } else {
+                    debug!(
+                        "Skipping a run of job {}, the last one is still running",
+                        job.name
+                    );
+                }
+            }
+            if let Some(next) = job.next_run_ms() {
+                wake_at = Some(wake_at.map_or(next, |at| at.min(next)));
+            }
+        }
+        let delay = wake_at.map_or(IDLE_WAKEUP, |at| {
+            Duration::from_millis(at.saturating_sub(now_ms())).min(IDLE_WAKEUP)
+        });
+        tokio::select! {
+            _ = shutdown.cancelled() => break,
+            _ = scheduler.changed.notified() => {}
+            _ = tokio::time::sleep(delay) => {}
+            Some(_) = in_flight.join_next() => {}
+        }
+    }
+    let drained = tokio::time::timeout(drain_timeout, async {
+        while in_flight.join_next().await.is_some() {}
+    })
+    .await;
+    if drained.is_err() {
+        warn!("Abandoning {} job runs still in progress", in_flight.len());
+        in_flight.abort_all();
+    }
+}
+
+/// Jobs the engine schedules on its own; none by default.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct JobOptions {
+    /// Sync the monitor with the backend, as a `Sync` request does. Runs as job `sync`.
+    pub sync: Option<Schedule>,
+    /// Log how many wallets there are and their total balance. Runs as job
+    /// `balance_snapshot`.
+    pub balance_snapshot: Option<Schedule>,
+    /// Create wallets while there are fewer than `min_wallets`. Runs as job
+    /// `scaling_check`.
+    pub scaling_check: Option<Schedule>,
+    /// Number of wallets `scaling_check` keeps; at least 1 when it is scheduled.
+    pub min_wallets: u64,
+}
+
+/// Registers the jobs enabled in `options` and returns their names.
+pub(crate) fn register_engine_jobs<S, Wm, M>(
+    executor: &Arc<Executor<S, Wm, M>>,
+    options: &JobOptions,
+) -> Vec<&'static str>
+where
+    S: aum_core::prelude::Storage + Send + 'static + Sync,
+    Wm: aum_core::prelude::AsyncWalletManager + Send + 'static + Sync,
+    M: aum_core::prelude::Monitor<WalletManager = Wm> + Send + 'static + Sync,
+{
+    let scheduler = executor.runtime().scheduler();
+    let mut names = Vec::new();
+    // The jobs live in the runtime, which the executor owns, so they only hold on to the
+    // executor weakly.
+    if let Some(schedule) = &options.sync {
+        let executor = Arc::downgrade(executor);
+        scheduler.register("sync", schedule.clone(), move |_| {
+            let executor = executor.clone();
+            async move {
+                if let Some(executor) = executor.upgrade() {
+                    executor.sync_between_batches().await?;
+                }
+                Ok(())
+            }
+        });
+        names.push("sync");
+    }
+    if let Some(schedule) = &options.balance_snapshot {
+        scheduler.register("balance_snapshot", schedule.clone(), |runtime| async move {
+            let balances = runtime.wallets().retrieve_balances().await?;
+            let total: u128 = balances
+                .iter()
+                .map(|(_, balance)| u128::from(*balance))
+                .sum();
+            info!(
+                "Balance snapshot: {} wallets holding {} in total",
+                balances.len(),
+                total
+            );
+            Ok(())
+        });
+        names.push("balance_snapshot");
+    }
+    if let Some(schedule) = &options.scaling_check {
+        let executor = Arc::downgrade(executor);
+        let min_wallets = options.min_wallets;
+        scheduler.register("scaling_check", schedule.clone(), move |runtime| {
+            let executor = executor.clone();
+            async move {
+                let wallets = runtime.wallets();
+                let count = wallets.list_wallets().await?.len() as u64;
+                if count < min_wallets {
+                    wallets.scale_to(min_wallets).await?;
+                    info!("Scaled from {} to {} wallets", count, min_wallets);
+                    if let Some(executor) = executor.upgrade() {
+                        executor.events().notify_changed();
+                    }
+                }
+                Ok(())
+            }
+        });
+        names.push("scaling_check");
+    }
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations_parse_and_format() {
+        assert_eq!(parse_duration("500ms"), Some(Duration::from_millis(500)));
+        assert_eq!(parse_duration("1h30m"), Some(Duration::from_secs(90 * 60)));
+        assert_eq!(parse_duration("2d"), Some(Duration::from_secs(2 * 86_400)));
+        assert_eq!(parse_duration("0s"), Some(Duration::ZERO));
+        for invalid in ["", "5", "m", "5x", "1.5s", "-1s"] {
+            assert_eq!(parse_duration(invalid), None, "{:?}", invalid);
+        }
+        assert_eq!(format_duration(Duration::from_secs(90 * 60)), "90m");
+        assert_eq!(format_duration(Duration::from_millis(1500)), "1500ms");
+        assert_eq!(format_duration(Duration::from_secs(86_400)), "1d");
+        assert_eq!(format_duration(Duration::ZERO), "0s");
+    }
+
+    #[test]
+    fn schedules_round_trip_through_strings() {
+        for schedule in ["@every 90s", "@every 1d", "*/15 * * * *", "@hourly"] {
+            assert_eq!(schedule.parse::<Schedule>().unwrap().to_string(), schedule);
+        }
+        assert_eq!(
+            "@every 1h30m".parse::<Schedule>().unwrap(),
+            Schedule::every(Duration::from_secs(90 * 60)).unwrap()
+        );
+    }
+
+    #[test]
+    fn invalid_schedules_are_rejected() {
+        assert!(Schedule::every(Duration::ZERO).is_err());
+        for schedule in [
+            "@every 0s",
+            "@every",
+            "@every soon",
+            "@fortnightly",
+            "0 0 31 2 *",
+        ] {
+            assert!(
+                matches!(schedule.parse::<Schedule>(), Err(Error::InvalidSchedule(_))),
+                "{:?}",
+                schedule
+            );
+        }
+    }
+
+    #[test]
+    fn intervals_run_one_interval_later() {
+        let schedule = Schedule::every(Duration::from_secs(30)).unwrap();
+        assert_eq!(schedule.next_after(1_000), Some(31_000));
+        let daily = Schedule::cron("@daily").unwrap();
+        assert_eq!(daily.next_after(1_000), Some(86_400_000));
+    }
+}
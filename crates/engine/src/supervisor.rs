@@ -0,0 +1,268 @@
+use crate::executor::Executor;
+use aum_core::errors::MonitorError;
+use aum_core::prelude::{HealthStatus, MonitorHealth, MonitorState};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    task::{JoinError, JoinHandle},
+    time::Instant,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+
+/// How the engine keeps its monitor running.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SupervisorOptions {
+    /// Start the monitor with the engine and restart it when it fails. Enabled by default;
+    /// when disabled, the application runs the monitor itself with
+    /// [`Runtime::run`](crate::Runtime::run) and health only reports whether it is running.
+    pub enabled: bool,
+    /// How often the monitor is checked once it is running.
+    #[serde(with = "crate::config::duration")]
+    pub check_interval: Duration,
+    /// Delay before the first restart; it doubles with every failure in a row.
+    #[serde(with = "crate::config::duration")]
+    pub initial_backoff: Duration,
+    /// Longest delay between restarts.
+    #[serde(with = "crate::config::duration")]
+    pub max_backoff: Duration,
+    /// Failures in a row after which the engine reports itself degraded.
+    pub degraded_after: u32,
+}
+
+impl Default for SupervisorOptions {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            check_interval: Duration::from_secs(5),
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+            degraded_after: 5,
+        }
+    }
+}
+
+/// What the supervisor knows about the monitor, shared with the health request.
+#[derive(Default)]
+pub struct MonitorStatus {
+    inner: Mutex<Status>,
+}
+
+struct Status {
+    state: MonitorState,
+    consecutive_failures: u32,
+    restarts: u64,
+    last_error: Option<String>,
+    retry_at: Option<Instant>,
+    degraded: bool,
+}
+
+impl Default for Status {
+    fn default() -> Self {
+        Self {
+            state: MonitorState::Stopped,
+            consecutive_failures: 0,
+            restarts: 0,
+            last_error: None,
+            retry_at: None,
+            degraded: false,
+        }
+    }
+}
+
+impl MonitorStatus {
+    /// Returns the overall health and the monitor's state.
+    ///
+    /// Without a supervisor the state is only derived from whether the monitor `running`.
+    pub fn health(&self, supervised: bool, running: bool) -> (HealthStatus, MonitorHealth) {
+        let status = self.inner.lock().unwrap();
+        let state = match (supervised, running) {
+            (true, _) => status.state,
+            (false, true) => MonitorState::Running,
+            (false, false) => MonitorState::Stopped,
+        };
+        let health = if status.degraded {
+            HealthStatus::Degraded
+        } else {
+            HealthStatus::Ok
+        };
+        let monitor = MonitorHealth {
+            state,
+            consecutive_failures: status.consecutive_failures,
+            restarts: status.restarts,
+            last_error: status.last_error.clone(),
+            retry_in_ms: status
+                .retry_at
+                .map(|at| at.saturating_duration_since(Instant::now()).as_millis() as u64),
+        };
+        (health, monitor)
+    }
+
+    pub fn is_degraded(&self) -> bool {
+        self.inner.lock().unwrap().degraded
+    }
+
+    pub fn restarts(&self) -> u64 {
+        self.inner.lock().unwrap().restarts
+    }
+
+    fn state(&self) -> MonitorState {
+        self.inner.lock().unwrap().state
+    }
+
+    fn transition(&self, state: MonitorState) {
+        let mut status = self.inner.lock().unwrap();
+        if status.state != state {
+            info!(
+                "Monitor state changed from {:?} to {:?}",
+                status.state, state
+            );
+            status.state = state;
+        }
+    }
+
+    fn started(&self) {
+        self.transition(MonitorState::Running);
+        let mut status = self.inner.lock().unwrap();
+        if status.degraded {
+            info!("Monitor recovered, the engine is no longer degraded");
+        }
+        if status.consecutive_failures > 0 {
+            status.restarts += 1;
+        }
+        status.consecutive_failures = 0;
+        status.retry_at = None;
+        status.degraded = false;
+    }
+
+    /// Records a failure and returns how many happened in a row.
+    fn failed(&self, error: String, degraded_after: u32) -> u32 {
+        let mut status = self.inner.lock().unwrap();
+        status.consecutive_failures += 1;
+        status.last_error = Some(error);
+        if status.consecutive_failures >= degraded_after && !status.degraded {
+            error!(
+                "Monitor failed {} times in a row, the engine is degraded",
+                status.consecutive_failures
+            );
+            status.degraded = true;
+        }
+        status.consecutive_failures
+    }
+
+    fn backing_off(&self, delay: Duration) {
+        self.transition(MonitorState::BackingOff);
+        self.inner.lock().unwrap().retry_at = Some(Instant::now() + delay);
+    }
+}
+
+/// Keeps the monitor running until `shutdown` is cancelled.
+///
+/// The monitor is started on its own task unless it already runs, then checked every check
+/// interval. `Monitor::start` may return once the monitor runs or keep running it; either
+/// way the supervisor doesn't wait for it. A failed start, a monitor that stopped on its own
+/// or a failed health check counts as a failure and is followed by a restart after an
+/// exponentially growing, jittered delay.
+pub async fn supervise<S, Wm, M>(
+    executor: Arc<Executor<S, Wm, M>>,
+    options: SupervisorOptions,
+    shutdown: CancellationToken,
+) where
+    S: aum_core::prelude::Storage + Send + 'static + Sync,
+    Wm: aum_core::prelude::AsyncWalletManager + Send + 'static + Sync,
+    M: aum_core::prelude::Monitor<WalletManager = Wm> + Send + 'static + Sync,
+{
+    let runtime = executor.runtime();
+    let status = executor.monitor_status();
+    let mut backoff = options.initial_backoff;
+    // The task running `Monitor::start`, and what it returned once it finished.
+    let mut task = None;
+    let mut returned = None;
+    loop {
+        let check = match returned.take() {
+            Some(Ok(Err(e))) if status.state() == MonitorState::Running => {
+                Check::Failed(format!("Monitor stopped: {}", e))
+            }
+            Some(Ok(Err(e))) => Check::Failed(format!("Failed to start: {}", e)),
+            Some(Err(e)) => Check::Failed(format!("Monitor task failed: {}", e)),
+            Some(Ok(Ok(()))) | None if runtime.monitor().is_running() => match M::health_check() {
+                Ok(()) => Check::Running,
+                Err(e) => Check::Failed(format!("Health check failed: {}", e)),
+            },
+            // Not running yet, `start` is still at work.
+            None if task.is_some() => Check::Starting,
+            Some(Ok(Ok(()))) | None if status.state() == MonitorState::Running => {
+                Check::Failed("Monitor stopped unexpectedly".to_owned())
+            }
+            Some(Ok(Ok(()))) => Check::Failed("Monitor did not run after starting".to_owned()),
+            None => {
+                status.transition(MonitorState::Starting);
+                task = Some(runtime.spawn_monitor());
+                Check::Starting
+            }
+        };
+
+        let delay = match check {
+            Check::Running => {
+                status.started();
+                backoff = options.initial_backoff;
+                options.check_interval
+            }
+            Check::Starting => options.check_interval,
+            Check::Failed(error) => {
+                warn!("Monitor failed: {}", error);
+                let monitor = runtime.monitor();
+                if monitor.is_running() {
+                    let _ = monitor.stop();
+                }
+                if let Some(task) = task.take() {
+                    task.abort();
+                }
+                status.failed(error, options.degraded_after);
+                let delay = jitter(backoff);
+                backoff = (backoff * 2).min(options.max_backoff);
+                status.backing_off(delay);
+                delay
+            }
+        };
+        tokio::select! {
+            _ = shutdown.cancelled() => break,
+            _ = tokio::time::sleep(delay) => {}
+            result = finished(&mut task) => returned = Some(result),
+        }
+    }
+}
+
+enum Check {
+    Running,
+    Starting,
+    Failed(String),
+}
+
+/// Waits for the start task to finish, forever if there is none.
+async fn finished(
+    task: &mut Option<JoinHandle<Result<(), MonitorError>>>,
+) -> Result<Result<(), MonitorError>, JoinError> {
+    let Some(handle) = task else {
+        return std::future::pending().await;
+    };
+    let result = handle.await;
+    *task = None;
+    result
+}
+
+/// Picks a delay between half of `backoff` and all of it, so that engines failing at the
+/// same time don't all retry at the same time.
+fn jitter(backoff: Duration) -> Duration {
+    let mut bytes = [0; 4];
+    let fraction = match SystemRandom::new().fill(&mut bytes) {
+        Ok(()) => f64::from(u32::from_le_bytes(bytes)) / f64::from(u32::MAX),
+        Err(_) => 1.0,
+    };
+    backoff.mul_f64(0.5 + fraction / 2.0)
+}
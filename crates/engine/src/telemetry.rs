@@ -0,0 +1,127 @@
+use crate::{Error, LogFormat, LoggingConfig};
+#[cfg(feature = "otlp")]
+use opentelemetry::trace::TracerProvider;
+#[cfg(feature = "otlp")]
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+#[cfg(feature = "otlp")]
+use opentelemetry_sdk::{
+    Resource,
+    trace::{SdkTracerProvider, Tracer},
+};
+#[cfg(feature = "otlp")]
+use tracing::{Subscriber, warn};
+#[cfg(feature = "otlp")]
+use tracing_opentelemetry::OpenTelemetryLayer;
+#[cfg(feature = "otlp")]
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::{Layer, fmt, layer::SubscriberExt, util::SubscriberInitExt};
+
+/// Keeps the logging installed by [`LoggingConfig::install`] going; dropping it flushes the
+/// spans not exported yet.
+pub struct LoggingGuard {
+    #[cfg(feature = "otlp")]
+    _otlp: Option<OtlpExporter>,
+}
+
+impl LoggingConfig {
+    /// Installs a global `tracing` subscriber that logs to stderr at `level` in `format`
+    /// and, with `otlp_endpoint`, exports spans to that collector.
+    ///
+    /// Returns `None` without changing anything if a global subscriber is already set,
+    /// e.g. by the application.
+    pub fn install(&self) -> Result<Option<LoggingGuard>, Error> {
+        if tracing::dispatcher::has_been_set() {
+            return Ok(None);
+        }
+        let output = match self.format {
+            LogFormat::Text => fmt::layer().with_writer(std::io::stderr).boxed(),
+            LogFormat::Json => fmt::layer().json().with_writer(std::io::stderr).boxed(),
+        };
+        let subscriber = tracing_subscriber::registry()
+            .with(self.level_filter())
+            .with(output);
+
+        #[cfg(feature = "otlp")]
+        let (installed, guard) = {
+            let otlp = self
+                .otlp_endpoint
+                .as_deref()
+                .map(|endpoint| OtlpExporter::new(endpoint, "aum-engine"))
+                .transpose()?;
+            let layer = otlp.as_ref().map(OtlpExporter::layer);
+            let installed = subscriber.with(layer).try_init().is_ok();
+            (installed, LoggingGuard { _otlp: otlp })
+        };
+        #[cfg(not(feature = "otlp"))]
+        let (installed, guard) = {
+            if self.otlp_endpoint.is_some() {
+                return Err(Error::InvalidConfig(
+                    "logging.otlp_endpoint needs aum-engine built with the `otlp` feature"
+                        .to_owned(),
+                ));
+            }
+            (subscriber.try_init().is_ok(), LoggingGuard {})
+        };
+        Ok(installed.then_some(guard))
+    }
+}
+
+/// Exports the engine's `tracing` spans to an OpenTelemetry collector over OTLP/gRPC.
+///
+/// [`LoggingConfig::install`] sets one up for `otlp_endpoint`. Applications installing
+/// their own subscriber add [`OtlpExporter::layer`] to it instead. Spans are sent in
+/// batches from a background task, so the exporter must be created inside a Tokio runtime;
+/// dropping it flushes the spans not sent yet.
+///
+/// ```no_run
+/// # async fn example() -> Result<(), aum_engine::Error> {
+/// use tracing_subscriber::prelude::*;
+///
+/// let otlp = aum_engine::OtlpExporter::new("http://127.0.0.1:4317", "aum-engine")?;
+/// tracing_subscriber::registry().with(otlp.layer()).init();
+/// # Ok(())
+/// # }
+/// ```
+#[cfg(feature = "otlp")]
+pub struct OtlpExporter {
+    provider: SdkTracerProvider,
+}
+
+#[cfg(feature = "otlp")]
+impl OtlpExporter {
+    /// Sends spans to the collector at `endpoint`, e.g. `http://127.0.0.1:4317`, as coming
+    /// from the service `service_name`.
+    pub fn new(endpoint: &str, service_name: &str) -> Result<Self, Error> {
+        let exporter = SpanExporter::builder()
+            .with_tonic()
+            .with_endpoint(endpoint)
+            .build()
+            .map_err(|e| Error::InvalidConfig(format!("OTLP endpoint {:?}: {}", endpoint, e)))?;
+        let provider = SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(
+                Resource::builder()
+                    .with_service_name(service_name.to_owned())
+                    .build(),
+            )
+            .build();
+        Ok(Self { provider })
+    }
+
+    /// Returns a layer that hands the spans of a subscriber to this exporter.
+    pub fn layer<S>(&self) -> OpenTelemetryLayer<S, Tracer>
+    where
+        S: Subscriber + for<'span> LookupSpan<'span>,
+    {
+        tracing_opentelemetry::layer().with_tracer(self.provider.tracer("aum-engine"))
+    }
+}
+
+#[cfg(feature = "otlp")]
+impl Drop for OtlpExporter {
+    fn drop(&mut self) {
+        if let Err(e) = self.provider.shutdown() {
+            warn!("Failed to flush spans to the OTLP collector: {}", e);
+        }
+    }
+}
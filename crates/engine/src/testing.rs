@@ -0,0 +1,408 @@
+//! An in-memory backend for the engine's tests.
+use crate::runtime::Runtime;
+use aum_core::errors::{
+    AddressError, HashError, KeyPairError, MonitorError, TransactionError, WalletError,
+    WalletManagerError,
+};
+use aum_core::prelude::{
+    Address, AsyncWallet, AsyncWalletManager, Format, Hash, Monitor, PublicKey, SecretKey,
+    SharedWalletManager, SignedTransaction, Storage, Transaction, TransactionId,
+    TransactionSignature,
+};
+use std::{
+    fmt,
+    str::FromStr,
+    sync::{
+        Mutex,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+};
+
+pub(crate) type TestRuntime = Runtime<TestStorage, TestWallets, TestMonitor>;
+
+/// A runtime whose wallets hold `balances`, by address.
+pub(crate) async fn runtime(balances: &[(&str, u64)]) -> TestRuntime {
+    let wallets = TestWallets {
+        balances: Mutex::new(
+            balances
+                .iter()
+                .map(|(address, balance)| (TestAddress(address.to_string()), *balance))
+                .collect(),
+        ),
+        sent: AtomicU64::new(0),
+    };
+    Runtime::new(TestStorage, wallets, TestMonitor::default()).await
+}
+
+/// An address: any string starting with `addr`.
+#[derive(Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct TestAddress(pub String);
+
+impl fmt::Display for TestAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl FromStr for TestAddress {
+    type Err = AddressError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.starts_with("addr") {
+            true => Ok(Self(s.to_owned())),
+            false => Err(AddressError::ParseError),
+        }
+    }
+}
+
+impl Address for TestAddress {
+    type SecretKey = TestKey;
+    type PublicKey = TestKey;
+    type Format = TestFormat;
+
+    fn from_secret_key(_: &TestKey, _: &TestFormat) -> Result<Self, AddressError> {
+        Err(AddressError::UnsupportedFormat)
+    }
+
+    fn from_public_key(_: &TestKey, _: &TestFormat) -> Result<Self, AddressError> {
+        Err(AddressError::UnsupportedFormat)
+    }
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct TestFormat;
+
+impl fmt::Display for TestFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("test")
+    }
+}
+
+impl Format for TestFormat {}
+
+#[derive(Clone)]
+pub(crate) struct TestKey;
+
+impl SecretKey for TestKey {
+    type PublicKey = TestKey;
+
+    fn new() -> Self {
+        TestKey
+    }
+    fn to_bytes(&self) -> Vec<u8> {
+        Vec::new()
+    }
+    fn from_bytes(_: impl AsRef<[u8]>) -> Result<Self, KeyPairError> {
+        Ok(TestKey)
+    }
+    fn to_hex(&self) -> String {
+        String::new()
+    }
+    fn from_hex(_: &str) -> Result<Self, KeyPairError> {
+        Ok(TestKey)
+    }
+    fn pubkey(&self) -> TestKey {
+        TestKey
+    }
+}
+
+impl PublicKey for TestKey {
+    type SecretKey = TestKey;
+
+    fn from_secret_key(_: &TestKey) -> Self {
+        TestKey
+    }
+    fn to_bytes(&self) -> Vec<u8> {
+        Vec::new()
+    }
+    fn from_bytes(_: impl AsRef<[u8]>) -> Result<Self, KeyPairError> {
+        Ok(TestKey)
+    }
+    fn to_hex(&self) -> String {
+        String::new()
+    }
+    fn from_hex(_: &str) -> Result<Self, KeyPairError> {
+        Ok(TestKey)
+    }
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub(crate) struct TestTxid(pub String);
+
+impl fmt::Display for TestTxid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl TransactionId for TestTxid {}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct TestTransaction;
+
+impl Hash for TestTransaction {
+    fn from_bytes(_: &[u8]) -> Result<Self, HashError> {
+        Ok(TestTransaction)
+    }
+    fn to_bytes(&self) -> Vec<u8> {
+        Vec::new()
+    }
+    fn from_hex(_: &str) -> Result<Self, HashError> {
+        Ok(TestTransaction)
+    }
+    fn to_hex(&self) -> String {
+        String::new()
+    }
+}
+
+impl Transaction for TestTransaction {
+    type Hash = TestTransaction;
+    type Address = TestAddress;
+    type TransactionId = TestTxid;
+    type TransactionParameters = ();
+
+    fn new(_: TestAddress, _: TestAddress, _: ()) -> Self {
+        TestTransaction
+    }
+    fn transaction_id(&self) -> Result<TestTxid, TransactionError> {
+        Err(TransactionError::InvalidTransactionId)
+    }
+    fn hash(&self) -> TestTransaction {
+        TestTransaction
+    }
+    fn from_bytes(_: &[u8]) -> Result<Self, TransactionError> {
+        Ok(TestTransaction)
+    }
+    fn to_bytes(&self) -> Vec<u8> {
+        Vec::new()
+    }
+}
+
+impl TransactionSignature for TestTransaction {
+    type Transaction = TestTransaction;
+
+    fn from_transaction(_: &TestTransaction) -> Self {
+        TestTransaction
+    }
+    fn to_transaction(&self) -> TestTransaction {
+        TestTransaction
+    }
+}
+
+impl SignedTransaction for TestTransaction {
+    fn signature(&self) -> Vec<u8> {
+        Vec::new()
+    }
+    fn from_bytes(_: &[u8]) -> Result<Self, TransactionError> {
+        Ok(TestTransaction)
+    }
+    fn to_bytes(&self) -> Vec<u8> {
+        Vec::new()
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct TestWallet(TestAddress);
+
+#[async_trait::async_trait]
+impl AsyncWallet for TestWallet {
+    type Transaction = TestTransaction;
+    type SignedTransaction = TestTransaction;
+    type Address = TestAddress;
+    type PublicKey = TestKey;
+
+    fn address(&self) -> &TestAddress {
+        &self.0
+    }
+    fn pubkey(&self) -> &TestKey {
+        &TestKey
+    }
+    async fn balance(&self) -> Result<u64, WalletError> {
+        Err(WalletError::InvalidAddress)
+    }
+    async fn sign_transaction(&self, _: &TestTransaction) -> Result<TestTransaction, WalletError> {
+        Ok(TestTransaction)
+    }
+    async fn verify_transaction_signature(&self, _: &TestTransaction) -> Result<bool, WalletError> {
+        Ok(true)
+    }
+    async fn transfer_funds(
+        &self,
+        _: &TestAddress,
+        _: u64,
+    ) -> Result<TestTransaction, WalletError> {
+        Ok(TestTransaction)
+    }
+    async fn transaction_history(&self) -> Result<Vec<TestTransaction>, WalletError> {
+        Ok(Vec::new())
+    }
+}
+
+/// Wallets holding balances in memory. Sends without a source take from the richest
+/// wallet.
+pub(crate) struct TestWallets {
+    balances: Mutex<Vec<(TestAddress, u64)>>,
+    sent: AtomicU64,
+}
+
+impl TestWallets {
+    fn debit(
+        &self,
+        from: Option<&TestAddress>,
+        amount: u64,
+    ) -> Result<TestTxid, WalletManagerError> {
+        let mut balances = self.balances.lock().unwrap();
+        let wallet = match from {
+            Some(from) => balances.iter_mut().find(|(address, _)| address == from),
+            None => balances.iter_mut().max_by_key(|(_, balance)| *balance),
+        };
+        let (_, balance) = wallet.ok_or(WalletError::InvalidAddress)?;
+        *balance = balance
+            .checked_sub(amount)
+            .ok_or(WalletError::InsufficientBalance)?;
+        let sent = self.sent.fetch_add(1, Ordering::Relaxed) + 1;
+        Ok(TestTxid(format!("tx{}", sent)))
+    }
+}
+
+#[async_trait::async_trait]
+impl AsyncWalletManager for TestWallets {
+    type Wallet = TestWallet;
+    type Address = TestAddress;
+    type TransactionId = TestTxid;
+
+    async fn create_wallet(&self) -> Result<TestWallet, WalletManagerError> {
+        let mut balances = self.balances.lock().unwrap();
+        let address = TestAddress(format!("addr{}", balances.len()));
+        balances.push((address.clone(), 0));
+        Ok(TestWallet(address))
+    }
+    async fn delete_and_transfer(
+        &self,
+        target: &TestWallet,
+    ) -> Result<TestWallet, WalletManagerError> {
+        Ok(target.clone())
+    }
+    async fn delete_and_distribute(
+        &self,
+        targets: &[TestWallet],
+    ) -> Result<Vec<TestWallet>, WalletManagerError> {
+        Ok(targets.to_vec())
+    }
+    async fn scale_to(&self, count: u64) -> Result<Vec<TestWallet>, WalletManagerError> {
+        let mut created = Vec::new();
+        while (self.balances.lock().unwrap().len() as u64) < count {
+            created.push(self.create_wallet().await?);
+        }
+        Ok(created)
+    }
+    async fn retrieve_address(&self) -> Result<TestAddress, WalletManagerError> {
+        let balances = self.balances.lock().unwrap();
+        let (address, _) = balances.first().ok_or(WalletError::InvalidAddress)?;
+        Ok(address.clone())
+    }
+    async fn send_transaction(
+        &self,
+        _: &TestAddress,
+        amount: u64,
+    ) -> Result<TestTxid, WalletManagerError> {
+        self.debit(None, amount)
+    }
+    async fn send_transaction_from(
+        &self,
+        from: &TestAddress,
+        _: &TestAddress,
+        amount: u64,
+    ) -> Result<TestTxid, WalletManagerError> {
+        self.debit(Some(from), amount)
+    }
+    async fn list_wallets(&self) -> Result<Vec<TestWallet>, WalletManagerError> {
+        let balances = self.balances.lock().unwrap();
+        Ok(balances
+            .iter()
+            .map(|(address, _)| TestWallet(address.clone()))
+            .collect())
+    }
+    async fn retrieve_balance(&self, address: &TestAddress) -> Result<u64, WalletManagerError> {
+        let balances = self.balances.lock().unwrap();
+        let (_, balance) = balances
+            .iter()
+            .find(|(wallet, _)| wallet == address)
+            .ok_or(WalletError::InvalidAddress)?;
+        Ok(*balance)
+    }
+    async fn retrieve_balances(&self) -> Result<Vec<(TestAddress, u64)>, WalletManagerError> {
+        Ok(self.balances.lock().unwrap().clone())
+    }
+}
+
+/// A monitor that returns from `start` once started and counts its starts and syncs.
+#[derive(Default)]
+pub(crate) struct TestMonitor {
+    pub running: AtomicBool,
+    pub starts: AtomicU64,
+    pub syncs: AtomicU64,
+    /// Makes the next `is_running` call panic.
+    pub panic_once: AtomicBool,
+}
+
+#[async_trait::async_trait]
+impl Monitor for TestMonitor {
+    type WalletManager = TestWallets;
+
+    async fn start(&self, _: SharedWalletManager<TestWallets>) -> Result<(), MonitorError> {
+        self.starts.fetch_add(1, Ordering::SeqCst);
+        self.running.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+    fn stop(&self) -> Result<(), MonitorError> {
+        self.running.store(false, Ordering::SeqCst);
+        Ok(())
+    }
+    fn is_running(&self) -> bool {
+        if self.panic_once.swap(false, Ordering::SeqCst) {
+            panic!("the monitor was told to panic");
+        }
+        self.running.load(Ordering::SeqCst)
+    }
+    async fn sync(&self) -> Result<(), MonitorError> {
+        self.syncs.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+    fn health_check() -> Result<(), MonitorError> {
+        Ok(())
+    }
+}
+
+/// Storage that keeps nothing; the engine doesn't read it.
+pub(crate) struct TestStorage;
+
+impl Storage for TestStorage {
+    type Key = TestAddress;
+    type Value = TestKey;
+
+    fn get(&self, _: TestAddress) -> Option<TestKey> {
+        None
+    }
+    fn set(&mut self, _: TestAddress, _: TestKey) {}
+    fn remove(&mut self, _: TestAddress) {}
+    fn clear(&mut self) {}
+    fn iter(&self) -> Box<dyn Iterator<Item = (TestAddress, TestKey)>> {
+        Box::new(std::iter::empty())
+    }
+    fn contains_key(&self, _: &TestAddress) -> bool {
+        false
+    }
+    fn len(&self) -> usize {
+        0
+    }
+    fn is_empty(&self) -> bool {
+        true
+    }
+    fn keys(&self) -> Box<dyn Iterator<Item = TestAddress>> {
+        Box::new(std::iter::empty())
+    }
+    fn values(&self) -> Box<dyn Iterator<Item = TestKey>> {
+        Box::new(std::iter::empty())
+    }
+}
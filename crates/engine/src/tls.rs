@@ -0,0 +1,44 @@
+use crate::Error;
+use serde::{Deserialize, Serialize};
+use std::{fs::File, io::BufReader, path::PathBuf, sync::Arc};
+use tokio_rustls::{TlsAcceptor, rustls};
+
+/// Certificate and private key used to serve `wss://`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    /// Path to a PEM file with the certificate chain, leaf first.
+    pub cert_path: PathBuf,
+    /// Path to a PEM file with the private key (PKCS#8, PKCS#1 or SEC1).
+    pub key_path: PathBuf,
+}
+
+impl TlsConfig {
+    pub fn new(cert_path: impl Into<PathBuf>, key_path: impl Into<PathBuf>) -> Self {
+        Self {
+            cert_path: cert_path.into(),
+            key_path: key_path.into(),
+        }
+    }
+
+    /// Loads the certificate and key and builds an acceptor for incoming connections.
+    pub(crate) fn acceptor(&self) -> Result<TlsAcceptor, Error> {
+        let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(&self.cert_path)?))
+            .collect::<Result<Vec<_>, _>>()?;
+        let key = rustls_pemfile::private_key(&mut BufReader::new(File::open(&self.key_path)?))?
+            .ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("no private key found in {}", self.key_path.display()),
+                )
+            })?;
+
+        let config = rustls::ServerConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?;
+        Ok(TlsAcceptor::from(Arc::new(config)))
+    }
+}
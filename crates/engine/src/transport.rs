@@ -0,0 +1,331 @@
+use crate::auth::PeerCredentials;
+use std::{
+    fmt,
+    future::Future,
+    io,
+    net::SocketAddr,
+    path::PathBuf,
+    pin::Pin,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::{TcpListener, TcpStream},
+    sync::Semaphore,
+    task::JoinSet,
+};
+use tokio_rustls::{TlsAcceptor, server::TlsStream};
+use tokio_util::{either::Either, sync::CancellationToken};
+use tracing::{Instrument, debug, error, field, info_span, warn};
+
+/// Prefix of bind addresses that name a Unix domain socket, e.g. `unix:/run/aum.sock`.
+pub const UNIX_PREFIX: &str = "unix:";
+
+/// Id of the next accepted connection, shared by all listeners so ids are unique in traces.
+static NEXT_CONNECTION_ID: AtomicU64 = AtomicU64::new(1);
+
+/// An accepted socket, wrapped in TLS when the listener has it enabled.
+pub type Stream = Either<TlsStream<Socket>, Socket>;
+
+/// The address a listener is bound to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ListenAddr {
+    Tcp(SocketAddr),
+    /// The path of a Unix domain socket.
+    Unix(PathBuf),
+}
+
+impl ListenAddr {
+    /// Returns the TCP address, or `None` for a Unix domain socket.
+    pub fn tcp(&self) -> Option<SocketAddr> {
+        match self {
+            ListenAddr::Tcp(addr) => Some(*addr),
+            ListenAddr::Unix(_) => None,
+        }
+    }
+}
+
+/// Formats the address the way it is given to `bind`, so it can be passed to a client.
+impl fmt::Display for ListenAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ListenAddr::Tcp(addr) => write!(f, "{}", addr),
+            ListenAddr::Unix(path) => write!(f, "{}{}", UNIX_PREFIX, path.display()),
+        }
+    }
+}
+
+/// A listening TCP socket, or a Unix domain socket for binds starting with `unix:`.
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener, PathBuf),
+}
+
+impl Listener {
+    /// Binds `bind`, giving a Unix domain socket the file permissions `mode`.
+    ///
+    /// A stale socket file left behind by an engine that is no longer running is replaced.
+    pub async fn bind(bind: &str, mode: u32) -> io::Result<Self> {
+        let Some(path) = bind.strip_prefix(UNIX_PREFIX) else {
+            return Ok(Listener::Tcp(TcpListener::bind(bind).await?));
+        };
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let path = PathBuf::from(path);
+            if path.exists() {
+                if std::os::unix::net::UnixStream::connect(&path).is_ok() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::AddrInUse,
+                        format!("{} is in use", path.display()),
+                    ));
+                }
+                std::fs::remove_file(&path)?;
+            }
+            let listener = UnixListener::bind(&path)?;
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode))?;
+            Ok(Listener::Unix(listener, path))
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = (path, mode);
+            Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Unix domain sockets are not supported on this platform",
+            ))
+        }
+    }
+
+    pub fn local_addr(&self) -> io::Result<ListenAddr> {
+        match self {
+            Listener::Tcp(listener) => listener.local_addr().map(ListenAddr::Tcp),
+            #[cfg(unix)]
+            Listener::Unix(_, path) => Ok(ListenAddr::Unix(path.clone())),
+        }
+    }
+
+    async fn accept(&self) -> io::Result<Socket> {
+        match self {
+            Listener::Tcp(listener) => Ok(Socket::Tcp(listener.accept().await?.0)),
+            #[cfg(unix)]
+            Listener::Unix(listener, _) => Ok(Socket::Unix(listener.accept().await?.0)),
+        }
+    }
+}
+
+/// Removes the socket file, so that clients fail fast instead of finding a dead socket.
+impl Drop for Listener {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Listener::Unix(_, path) = self {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// A connected TCP or Unix domain socket.
+pub enum Socket {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Socket {
+    /// Returns the user and group of the process on the other end of a Unix domain socket.
+    pub fn peer_credentials(&self) -> Option<PeerCredentials> {
+        match self {
+            Socket::Tcp(_) => None,
+            #[cfg(unix)]
+            Socket::Unix(stream) => {
+                let cred = stream.peer_cred().ok()?;
+                Some(PeerCredentials {
+                    uid: cred.uid(),
+                    gid: cred.gid(),
+                    pid: cred.pid(),
+                })
+            }
+        }
+    }
+}
+
+impl AsyncRead for Socket {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Socket::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+            #[cfg(unix)]
+            Socket::Unix(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Socket {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Socket::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+            #[cfg(unix)]
+            Socket::Unix(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Socket::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+            #[cfg(unix)]
+            Socket::Unix(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Socket::Tcp(stream) => Pin::new(stream).poll_shutdown(cx),
+            #[cfg(unix)]
+            Socket::Unix(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+/// Completes the TLS handshake if `tls` is set, giving up after `timeout`.
+pub async fn accept_tls(
+    stream: Socket,
+    tls: Option<&TlsAcceptor>,
+    timeout: Duration,
+) -> io::Result<Stream> {
+    match tls {
+        Some(tls) => Ok(Either::Left(
+            tokio::time::timeout(timeout, tls.accept(stream))
+                .await
+                .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "handshake timed out"))??,
+        )),
+        None => Ok(Either::Right(stream)),
+    }
+}
+
+/// How long the listener waits after failing to accept a connection, e.g. because the
+/// process ran out of file descriptors, before trying again.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Longest wait between attempts while accepting keeps failing.
+const MAX_ACCEPT_BACKOFF: Duration = Duration::from_secs(5);
+
+/// Accepts connections until `shutdown` is cancelled, serving each on its own task.
+///
+/// Each connection is served inside a `connection` span; its `identity` and `protocol`
+/// fields are recorded by servers that authenticate the connection as a whole.
+///
+/// With `max_connections`, a connection accepted while that many are open is handed to
+/// `reject` instead, which tells the client why it is turned away. At most as many
+/// rejections as connections run at once; clients beyond that are disconnected without a
+/// word, so that a flood of them costs next to nothing.
+///
+/// On shutdown the listener is closed first, then open connections get up to
+/// `drain_timeout` to finish their in-flight requests before they are aborted.
+pub async fn accept_loop<F, Fut, R, RFut>(
+    listener: Listener,
+    shutdown: &CancellationToken,
+    drain_timeout: Duration,
+    max_connections: Option<usize>,
+    mut serve: F,
+    mut reject: R,
+) where
+    F: FnMut(Socket) -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+    R: FnMut(Socket) -> RFut,
+    RFut: Future<Output = ()> + Send + 'static,
+{
+    let listen_addr = listener
+        .local_addr()
+        .map_or_else(|_| "<unknown>".to_owned(), |addr| addr.to_string());
+    let permits =
+        max_connections.map(|max| (Arc::new(Semaphore::new(max)), Arc::new(Semaphore::new(max))));
+    let mut connections = JoinSet::new();
+    let mut rejections = JoinSet::new();
+    let mut backoff = ACCEPT_BACKOFF;
+    loop {
+        let stream = tokio::select! {
+            _ = shutdown.cancelled() => break,
+            Some(_) = connections.join_next(), if !connections.is_empty() => continue,
+            Some(_) = rejections.join_next(), if !rejections.is_empty() => continue,
+            accepted = listener.accept() => match accepted {
+                Ok(stream) => stream,
+                Err(e) => {
+                    error!(
+                        "Error accepting a connection to {}, retrying in {:?}: {}",
+                        listen_addr, backoff, e
+                    );
+                    tokio::select! {
+                        _ = shutdown.cancelled() => break,
+                        _ = tokio::time::sleep(backoff) => {}
+                    }
+                    backoff = (backoff * 2).min(MAX_ACCEPT_BACKOFF);
+                    continue;
+                }
+            },
+        };
+        backoff = ACCEPT_BACKOFF;
+        let span = info_span!(
+            "connection",
+            connection_id = NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed),
+            listener = %listen_addr,
+            identity = field::Empty,
+            protocol = field::Empty,
+        );
+        let Some((open, rejecting)) = &permits else {
+            connections.spawn(serve(stream).instrument(span));
+            continue;
+        };
+        // The permit is held until the connection ends.
+        if let Ok(permit) = Arc::clone(open).try_acquire_owned() {
+            let serve = serve(stream);
+            connections.spawn(
+                async move {
+                    serve.await;
+                    drop(permit);
+                }
+                .instrument(span),
+            );
+            continue;
+        }
+        let _span = span.enter();
+        warn!("Rejecting a connection, the connection limit is reached");
+        match Arc::clone(rejecting).try_acquire_owned() {
+            Ok(permit) => {
+                let reject = reject(stream);
+                rejections.spawn(
+                    async move {
+                        reject.await;
+                        drop(permit);
+                    }
+                    .in_current_span(),
+                );
+            }
+            Err(_) => debug!("Dropping the connection, too many rejections are in progress"),
+        }
+    }
+    drop(listener);
+    rejections.shutdown().await;
+
+    let drain = async { while connections.join_next().await.is_some() {} };
+    if tokio::time::timeout(drain_timeout, drain).await.is_err() {
+        warn!(
+            "Aborting {} connection(s) still busy after the drain timeout",
+            connections.len()
+        );
+        connections.shutdown().await;
+    }
+}
@@ -0,0 +1,180 @@
+use crate::audit::{AuditLink, AuditLog};
+use aum_core::errors::WalletManagerError;
+use aum_core::prelude::{AsyncWallet, AsyncWalletManager, AuditAction, AuditOutcome};
+use std::{
+    collections::HashSet,
+    sync::{Arc, RwLock},
+};
+use tokio::sync::Mutex;
+use tracing::error;
+
+/// The audit log of the engine serving a runtime, shared by every handle to it.
+pub(crate) type AuditSlot = Arc<RwLock<Option<Arc<AuditLog>>>>;
+
+/// The wallet manager of a [`Runtime`](crate::Runtime), as used by one caller.
+///
+/// Calls go straight to the wallet manager. Wallets created and deleted through it are
+/// recorded in the audit log of the engine serving the runtime, if it keeps one, as changed
+/// by the caller.
+pub struct Wallets<Wm> {
+    inner: Arc<Wm>,
+    audit: AuditSlot,
+    /// Held while deleting, so that the wallets gone after a deletion are the ones it deleted.
+    deleting: Arc<Mutex<()>>,
+    caller: Option<String>,
+}
+
+impl<Wm> Clone for Wallets<Wm> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: Arc::clone(&self.inner),
+            audit: Arc::clone(&self.audit),
+            deleting: Arc::clone(&self.deleting),
+            caller: self.caller.clone(),
+        }
+    }
+}
+
+impl<Wm: AsyncWalletManager> Wallets<Wm> {
+    pub(crate) fn new(
+        inner: Arc<Wm>,
+        audit: AuditSlot,
+        deleting: Arc<Mutex<()>>,
+        caller: Option<String>,
+    ) -> Self {
+        Self {
+            inner,
+            audit,
+            deleting,
+            caller,
+        }
+    }
+
+    /// Records `action` in the audit log, if there is one.
+    ///
+    /// A failed append is logged rather than returned, since the change already happened.
+    fn record(&self, action: AuditAction) {
+        let Some(log) = self.audit.read().unwrap().clone() else {
+            return;
+        };
+        let outcome = AuditOutcome::Success { txid: None };
+        if let Err(e) = log.append(
+            self.caller.as_deref(),
+            action.clone(),
+            outcome,
+            AuditLink::None,
+        ) {
+            error!("Failed to record {:?} in the audit log: {}", action, e);
+        }
+    }
+
+    fn record_created(&self, wallets: &[Wm::Wallet]) {
+        for wallet in wallets {
+            self.record(AuditAction::WalletCreated {
+                address: wallet.address().to_string(),
+            });
+        }
+    }
+
+    async fn addresses(&self) -> Result<HashSet<String>, WalletManagerError> {
+        Ok(self
+            .inner
+            .list_wallets()
+            .await?
+            .iter()
+            .map(|wallet| wallet.address().to_string())
+            .collect())
+    }
+
+    /// Runs a deletion, recording the wallets it removed.
+    async fn delete<T>(
+        &self,
+        delete: impl Future<Output = Result<T, WalletManagerError>>,
+    ) -> Result<T, WalletManagerError> {
+        let _deleting = self.deleting.lock().await;
+        let before = self.addresses().await?;
+        let result = delete.await?;
+        match self.addresses().await {
+            Ok(after) => {
+                for address in before.difference(&after) {
+                    self.record(AuditAction::WalletDeleted {
+                        address: address.clone(),
+                    });
+                }
+            }
+            Err(e) => error!("Failed to list the wallets left after a deletion: {}", e),
+        }
+        Ok(result)
+    }
+}
+
+#[async_trait::async_trait]
+impl<Wm> AsyncWalletManager for Wallets<Wm>
+where
+    Wm: AsyncWalletManager + 'static,
+{
+    type Wallet = Wm::Wallet;
+    type Address = Wm::Address;
+    type TransactionId = Wm::TransactionId;
+
+    async fn create_wallet(&self) -> Result<Self::Wallet, WalletManagerError> {
+        let wallet = self.inner.create_wallet().await?;
+        self.record_created(std::slice::from_ref(&wallet));
+        Ok(wallet)
+    }
+
+    async fn delete_and_transfer(
+        &self,
+        target_wallet: &Self::Wallet,
+    ) -> Result<Self::Wallet, WalletManagerError> {
+        self.delete(self.inner.delete_and_transfer(target_wallet))
+            .await
+    }
+
+    async fn delete_and_distribute(
+        &self,
+        target_wallets: &[Self::Wallet],
+    ) -> Result<Vec<Self::Wallet>, WalletManagerError> {
+        self.delete(self.inner.delete_and_distribute(target_wallets))
+            .await
+    }
+
+    async fn scale_to(&self, count: u64) -> Result<Vec<Self::Wallet>, WalletManagerError> {
+        let wallets = self.inner.scale_to(count).await?;
+        self.record_created(&wallets);
+        Ok(wallets)
+    }
+
+    async fn retrieve_address(&self) -> Result<Self::Address, WalletManagerError> {
+        self.inner.retrieve_address().await
+    }
+
+    async fn send_transaction(
+        &self,
+        to: &Self::Address,
+        amount: u64,
+    ) -> Result<Self::TransactionId, WalletManagerError> {
+        self.inner.send_transaction(to, amount).await
+    }
+
+    async fn send_transaction_from(
+        &self,
+        from: &Self::Address,
+        to: &Self::Address,
+        amount: u64,
+    ) -> Result<Self::TransactionId, WalletManagerError> {
+        self.inner.send_transaction_from(from, to, amount).await
+    }
+
+    async fn list_wallets(&self) -> Result<Vec<Self::Wallet>, WalletManagerError> {
+        self.inner.list_wallets().await
+    }
+
+    async fn retrieve_balance(&self, address: &Self::Address) -> Result<u64, WalletManagerError> {
+        self.inner.retrieve_balance(address).await
+    }
+
+    async fn retrieve_balances(&self) -> Result<Vec<(Self::Address, u64)>, WalletManagerError> {
+        self.inner.retrieve_balances().await
+    }
+}